@@ -11,8 +11,12 @@ use crate::clients::Client as TraitClient;
 /// Note: when you send the packet with routing the hop_index is increased in the receiving by a drone
 
 use crate::clients::client_chen::prelude::*;
-use crate::clients::client_chen::{CommandHandler, FragmentsHandler, PacketsReceiver, Router, Sending};
+use crate::clients::client_chen::{CommandHandler, PacketsReceiver, Router, Sending};
 use crate::general_use::{ClientType, MediaRef};
+use crate::ids::{unique_id, SeenFloods};
+use crate::routing::{Rediscovery, RouteCache};
+use crate::transport::{Transport, RETRANSMISSION_TICK};
+use crossbeam_channel::tick;
 
 #[derive(Clone)]
 pub(crate) struct ClientChen {
@@ -55,14 +59,16 @@ impl TraitClient for ClientChen {
 
             // Status
             status: NodeStatus {
-                flood_id: 0, // Initial value to be 0 for every new client
-                session_id:  (id as u64) * 10u64.pow(18), //(id as u64) << 56, not human-readable but more efficient and reserves more space for the sessions for each id.
+                flood_id: unique_id(id, 0), // Node id in the high bits: incrementing it never collides with other nodes
+                session_id: unique_id(id, 0),   // Last session used for a flood, taken from the transport
             },
 
             // Communication-related data
             communication: CommunicationInfo {
                 connected_nodes_ids: connected_nodes,
-                routing_table: HashMap::new(),
+                routes: RouteCache::new(id),
+                rediscovery: Rediscovery::new(),
+                seen_floods: SeenFloods::default(),
             },
 
             // Communication tools
@@ -76,9 +82,7 @@ impl TraitClient for ClientChen {
             // Storage
             storage: NodeStorage {
                 //irresolute_path_traces: HashMap::new(),
-                fragment_assembling_buffer: HashMap::new(),
-                output_buffer: HashMap::new(),
-                packets_status: HashMap::new(),
+                transport: Transport::new(id),
                 message_chat: HashMap::new(),
                 current_list_file: Vec::new(),
                 current_requested_text_file: String::new(),
@@ -97,24 +101,30 @@ impl TraitClient for ClientChen {
     }
 
     fn run(&mut self) {
+        let ticker = tick(RETRANSMISSION_TICK);
         loop {
             select_biased! {
                 recv(self.communication_tools.controller_recv) -> command_res => {
-                    if let Ok(command) = command_res {
-                        self.handle_controller_command(command);
-
-                        // Things to do after handling the command
-                        self.handle_fragments_in_buffer_with_checking_status();
-                        self.send_packets_in_buffer_with_checking_status();
+                    // Stop when asked to, or when the controller is gone
+                    let Ok(command) = command_res else {
+                        return;
+                    };
+                    if let ClientCommand::Shutdown = command {
+                        return;
                     }
+                    self.handle_controller_command(command);
                 },
                 recv(self.communication_tools.packet_recv) -> packet_res => {
-                    if let Ok(packet) = packet_res {
-                        self.handle_received_packet(packet);
-                        // Things to do after handling the command
-                        self.handle_fragments_in_buffer_with_checking_status();
-                        self.send_packets_in_buffer_with_checking_status();
-                    }
+                    // Every sender is gone: nothing can be received anymore
+                    let Ok(packet) = packet_res else {
+                        return;
+                    };
+                    self.handle_received_packet(packet);
+                },
+                recv(ticker) -> _ => {
+                    // Resend the fragments whose ack is late
+                    self.resend_expired_fragments();
+                    self.rediscover_if_due();
                 },
             }
         }
@@ -146,7 +156,9 @@ pub(crate) struct NodeStatus {
 #[derive(Clone)]
 pub(crate) struct CommunicationInfo {
     pub(crate) connected_nodes_ids: HashSet<NodeId>,
-    pub(crate) routing_table: HashMap<NodeId, Vec<NodeId>>, // Routing information per protocol
+    pub(crate) routes: RouteCache,                           // Best and alternative routes per destination
+    pub(crate) rediscovery: Rediscovery,                     // When to flood the network again
+    pub(crate) seen_floods: SeenFloods,                      // Flood requests already answered
 }
 
 // Tools for communication
@@ -164,10 +176,8 @@ pub struct NodeStorage {
     //for the chat client maybe better just do a map for every client destination, a communicable or not communicable state
     //pub(crate) irresolute_path_traces: HashMap<NodeId, Vec<(NodeId, NodeType)>>,   //Temporary storage for the path_traces that are received, but we didn't know how to process them
 
-    pub(crate) fragment_assembling_buffer: HashMap<SessionId, HashMap<FragmentIndex, Packet>>, // Temporary storage for recombining fragments
-    pub(crate) output_buffer: HashMap<SessionId, HashMap<FragmentIndex, Packet>>,              // Buffer for outgoing messages
-    pub(crate) packets_status: HashMap<SessionId, HashMap<FragmentIndex, PacketStatus>>,       // Map every packet with the status of sending
-    pub(crate) message_chat: HashMap<ClientId, Vec<(Speaker, Message)>>,               // Chat messages with other clients
+    pub(crate) transport: Transport,                                                           // Sessions of the messages sent and received
+    pub(crate) message_chat: HashMap<ClientId, Vec<(Speaker, Message)>>,               // Chat messages with other clients, never filled by the web browser so not archived
     pub(crate) current_list_file: Vec<String>,                                  // Files received from media servers
     pub(crate) current_requested_text_file: String,
     pub(crate) current_text_media_list: Vec<MediaRef>,
@@ -1,9 +1,6 @@
-use serde::de::DeserializeOwned;
 use crate::clients::client_chen::prelude::*;
 
 pub trait Sending{
-    fn send_packets_in_buffer_with_checking_status(&mut self);//when you run the client
-
     ///principal sending methods
     fn send(&mut self, packet: Packet);
     fn send_events(&mut self, client_event: ClientEvent);
@@ -14,10 +11,8 @@ pub trait Sending{
 
 
     ///auxiliary methods
-    fn packets_status_sending_actions(&mut self, packet: Packet, packet_status: PacketStatus);
-    fn handle_sent_packet(&mut self, packet: Packet);
-    fn handle_not_sent_packet(&mut self, packet: Packet, not_sent_type: NotSentType, destination: NodeId);
-    fn update_packet_status(&mut self, session_id: SessionId, fragment_index: FragmentIndex, status: PacketStatus);
+    fn send_transport_packets(&mut self);
+    fn resend_expired_fragments(&mut self);
 
 }
 
@@ -25,6 +20,7 @@ pub trait Sending{
 pub trait Router{
     ///main method of for discovering the routing
     fn do_flooding(&mut self);
+    fn rediscover_if_due(&mut self);
     fn update_routing_for_server(&mut self, destination_id: NodeId, path_trace: Vec<(NodeId,NodeType)>);
     fn update_routing_for_client(&mut self, destination_id: NodeId, path_trace: Vec<(NodeId,NodeType)>);
 
@@ -39,10 +35,6 @@ pub trait CommunicationTools{
 }
 
 pub trait PacketCreator{
-    ///creating fragment packet
-    fn divide_string_into_slices(&mut self, string: String, max_slice_length: usize) -> Vec<String>;
-    fn msg_to_fragments<T: Serialize>(&mut self, msg: T, destination_id: NodeId) -> Option<Vec<Packet>>;
-    fn msg_to_fragments_by_routing_header<T: Serialize>(&mut self, msg: T, source_routing_header: SourceRoutingHeader) -> Option<Vec<Packet>>;
     ///creating ack packet
     fn create_ack_packet_from_receiving_packet(&mut self, packet: Packet) -> Packet;
     fn create_nack_packet_from_receiving_packet(&mut self, packet: Packet, nack_type: NackType) -> Packet;
@@ -66,6 +58,9 @@ pub trait PacketResponseHandler:PacketsReceiver{   //Ack Nack
     fn handle_destination_is_drone(&mut self, nack_packet: Packet, nack: &Nack);
     fn handle_packdrop(&mut self, nack_packet: Packet, nack: &Nack);
     fn handle_unexpected_recipient(&mut self, node_id: NodeId, nack_packet: Packet, nack: &Nack);
+
+    ///auxiliary methods
+    fn reroute_and_resend(&mut self, session_id: SessionId, fragment_index: FragmentIndex);
 }
 
 
@@ -81,13 +76,7 @@ pub trait FragmentsHandler:PacketsReceiver{ //message fragments
     fn handle_fragment(&mut self, msg_packet: Packet, fragment: &Fragment);
 
     ///auxiliary functions
-    fn get_total_n_fragments(&self, session_id: SessionId) -> Option<u64>;
-    fn get_fragments_quantity_for_session(&self, session_id: SessionId) -> Option<u64>;
-    fn handle_fragments_in_buffer_with_checking_status(&mut self);  //when you run
     fn process_message(&mut self, initiator_id: NodeId, message: Response);
-    ///principal methods
-    fn reassemble_fragments<T: Serialize + DeserializeOwned>(&mut self, fragments: Vec<Packet>) -> Result<T, String>;
-    fn reassemble_fragments_in_buffer<T: Serialize + DeserializeOwned>(&mut self, session_id: SessionId) -> Result<T, String>;
 }
 
 pub trait CommandHandler{
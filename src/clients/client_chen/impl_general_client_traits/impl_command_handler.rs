@@ -10,9 +10,16 @@ impl CommandHandler for ClientChen{
         match command {
             ClientCommand::AddSender(target_node_id, sender) => {
                 self.communication_tools.packet_send.insert(target_node_id, sender);
+                // The new neighbour may open shorter routes
+                self.communication.rediscovery.request();
             }
             ClientCommand::RemoveSender(target_node_id) => {
                 self.communication_tools.packet_send.remove(&target_node_id);
+                self.communication.routes.remove_node(target_node_id);
+                self.communication.rediscovery.request();
+            }
+            ClientCommand::SetRediscoveryInterval(interval) => {
+                self.communication.rediscovery.set_interval(interval);
             }
 
             ClientCommand::StartFlooding => {
@@ -52,12 +59,9 @@ impl CommandHandler for ClientChen{
             ClientCommand::RequestMedia(server_id, media_ref) => {
                 self.send_query(server_id, Query::AskMedia(media_ref));
             }
-            //testing command
             ClientCommand::RequestRoutes(destination_id) => {
-                if let Some(routes) = self.communication.routing_table.get(&destination_id) {
-                    eprintln!("The routes from {} to {} are: \n\
-                     {:?}", self.metadata.node_id, destination_id, routes);
-                }
+                let routes = self.communication.routes.route_costs(destination_id);
+                self.send_events(ClientEvent::RouteCosts(self.metadata.node_id, destination_id, routes));
             }
             _=>{}
         }
@@ -7,23 +7,19 @@ impl FloodingPacketsHandler for ClientChen {
         self.update_connected_nodes();      // todo: only for testing repo
 
         info!("{:?} Client {} has received flood request that contains the path: {:?}", self.metadata.client_type ,self.metadata.node_id , request.path_trace);
+
+        // The client never forwards floods, so every copy is answered: the initiator learns every link into the client
+        if !self.communication.seen_floods.first_time(request.initiator_id, request.flood_id) {
+            debug!("Flood {} of node {} received again, through another path", request.flood_id, request.initiator_id);
+        }
+
         // Prepare the flood response.
-        self.status.session_id += 1;
+        self.status.session_id = self.storage.transport.new_session_id();
         request.path_trace.push((self.metadata.node_id, self.metadata.node_type));
         let mut response = request.generate_response(self.status.session_id);
 
         //you send directly because the source routing header is there
-        self.send(response.clone());
-        // For packets_status
-        self.storage.packets_status
-            .entry(response.session_id)
-            .or_insert_with(HashMap::new)
-            .insert(0, PacketStatus::InProgress);
-        // Store in the buffer and wait for the Ack to arrive.
-        self.storage.output_buffer
-            .entry(response.session_id)
-            .or_insert_with(HashMap::new)
-            .insert(0, response.clone());
+        self.send(response);
     }
 
     /// When you receive a flood response, you need first to update the topology with the elements of the path_traces
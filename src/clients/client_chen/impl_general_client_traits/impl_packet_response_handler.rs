@@ -1,26 +1,20 @@
-use crate::clients::client_chen::{ClientChen, PacketResponseHandler, Router, Sending};
+use crate::clients::client_chen::{ClientChen, PacketResponseHandler, Sending};
 use crate::clients::client_chen::prelude::*;
-use crate::clients::client_chen::general_client_traits::*;
 
 impl PacketResponseHandler for ClientChen {
-    fn handle_ack(&mut self, _ack_packet: Packet, ack: &Ack) {
-        let session_id = self.status.session_id;
-        let fragment_index = ack.fragment_index;
-
-        // Update packets_status using nested HashMap access
-        if let Some(fragments) = self.storage.packets_status.get_mut(&session_id) {
-            fragments.insert(fragment_index, PacketStatus::Sent);
+    fn handle_ack(&mut self, ack_packet: Packet, ack: &Ack) {
+        // Every drone of the route forwarded the fragment
+        if let Some(route) = self.storage.transport.route(ack_packet.session_id) {
+            self.communication.routes.record_delivered(route);
         }
 
-        // Remove from output_buffer using proper nested structure
-        if let Some(fragments) = self.storage.output_buffer.get_mut(&session_id) {
-            fragments.remove(&fragment_index);
-
-            // Clean up empty session entries
-            if fragments.is_empty() {
-                self.storage.output_buffer.remove(&session_id);
-            }
+        // The transport forgets the message once every fragment is acked
+        if let Some(session) = self.storage.transport.handle_ack(ack_packet.session_id, ack.fragment_index) {
+            info!("Every fragment of session {} acked by {}", session.session_id, session.destination);
         }
+
+        // Send the fragments that entered the window
+        self.send_transport_packets();
     }
 
 
@@ -35,7 +29,7 @@ impl PacketResponseHandler for ClientChen {
     }
 
 
-    fn handle_error_in_routing(&mut self, node_id: NodeId, _nack_packet: Packet, nack: &Nack) {
+    fn handle_error_in_routing(&mut self, node_id: NodeId, nack_packet: Packet, nack: &Nack) {
         // Clean up packet_send connection
         if self.communication_tools.packet_send.remove(&node_id).is_some() {
             warn!("Removed broken connection to node {} from packet_send", node_id);
@@ -43,124 +37,52 @@ impl PacketResponseHandler for ClientChen {
 
         warn!("Routing error encountered for node {}: Drone crashed or sender not found", node_id);
 
-        let session_id = self.status.session_id;
-        let fragment_index = nack.fragment_index;
-
-        self.update_packet_status(
-            session_id,
-            fragment_index,
-            PacketStatus::NotSent(NotSentType::RoutingError),
-        );
-        let opt_packet = self.storage.output_buffer
-            .get_mut(&session_id)
-            .and_then(|fragments| fragments.get_mut(&fragment_index))
-            .cloned();
-
-        let packet_to_send = {
-            if let Some(mut packet) = opt_packet {
-                let opt_destination = packet.routing_header.destination();
-                if let Some(destination) = opt_destination {
-                    let pack = match self.communication.routing_table.get(&destination) {
-                        Some(routes) => {
-                            if routes.clone() == packet.routing_header.hops {
-                                //still the wrong path memorized
-                                self.do_flooding();
-                                None
-                            } else if !routes.is_empty() {
-                                let source_routing_header = self.get_source_routing_header(destination);
-                                if let Some(srh) = source_routing_header {
-                                    packet.routing_header = srh; // Perform the update
-                                    Some(packet.clone()) // Clone the packet
-                                } else {
-                                    None
-                                }
-                            } else {  //when the routing table doesn't contain the wrong route and is empty
-                                None
-                            }
-                        }
-                        None => None,
-                    };
-                    pack  // packet_to_send
-                } else {
-                    None // packet_to_send
-                }
-            } else {
-                warn!("Packet not found in output buffer (Session: {}, Fragment: {})", session_id, fragment_index);
-                None
-            }
-        };
-
-        // Send the packet when conditions are satisfied
-        if let Some(p) = packet_to_send {
-            self.send(p);
-        }
+        // The next route to every destination takes over
+        self.communication.routes.remove_node(node_id);
+        self.reroute_and_resend(nack_packet.session_id, nack.fragment_index);
     }
-    fn handle_destination_is_drone(&mut self, _nack_packet: Packet, nack: &Nack) {
-        self.update_packet_status(self.status.session_id, nack.fragment_index, PacketStatus::NotSent(NotSentType::DroneDestination));
-        self.storage.output_buffer.remove(&self.status.session_id);
-        //the post-part of the handling is in the send_packets_in_buffer_checking_status
+    fn handle_destination_is_drone(&mut self, nack_packet: Packet, _nack: &Nack) {
+        // The message can never arrive: giving up the whole session
+        warn!("Session {} is addressed to a drone, dropping it", nack_packet.session_id);
+        self.storage.transport.cancel(nack_packet.session_id);
     }
-    fn handle_packdrop(&mut self, _nack_packet: Packet, nack: &Nack) {
-        self.update_packet_status(self.status.session_id, nack.fragment_index, PacketStatus::NotSent(NotSentType::Dropped));
-        if let Some(entry) = &mut self.storage.output_buffer.get(&self.status.session_id){
-            if let Some(packet) = entry.get(&nack.fragment_index) {
-                self.send(packet.clone());
-            }
+    fn handle_packdrop(&mut self, nack_packet: Packet, nack: &Nack) {
+        // The drone that dropped the fragment is the source of the nack
+        if let (Some(route), Some(&drone)) = (self.storage.transport.route(nack_packet.session_id), nack_packet.routing_header.hops.first()) {
+            self.communication.routes.record_dropped(route, drone);
+        }
+
+        if self.storage.transport.resend(nack_packet.session_id, nack.fragment_index) {
+            self.send_transport_packets();
         }
     }
 
-    fn handle_unexpected_recipient(&mut self, node_id: NodeId, _nack_packet: Packet, nack: &Nack) {
+    fn handle_unexpected_recipient(&mut self, node_id: NodeId, nack_packet: Packet, nack: &Nack) {
         info!("unexpected recipient found {}", node_id);
-        let session_id = self.status.session_id;
-        let fragment_index = nack.fragment_index;
-
-        self.update_packet_status(
-            self.status.session_id,
-            nack.fragment_index,
-            PacketStatus::NotSent(NotSentType::BeenInWrongRecipient));
-
-        let opt_packet = self.storage.output_buffer
-            .get_mut(&session_id)
-            .and_then(|fragments| fragments.get_mut(&fragment_index))
-            .cloned();
+        self.reroute_and_resend(nack_packet.session_id, nack.fragment_index);
+    }
 
-        let packet_to_send = {
-            if let Some(mut packet) = opt_packet {
-                let opt_destination = packet.routing_header.destination();
-                if let Some(destination) = opt_destination {
-                    let pack = match self.communication.routing_table.get(&destination) {
-                        Some(routes) => {
-                            if routes.clone() == packet.routing_header.hops {
-                                //still the wrong path memorized
-                                self.do_flooding();
-                                None
-                            } else if !routes.is_empty() {
-                                let source_routing_header = self.get_source_routing_header(destination);
-                                if let Some(srh) = source_routing_header {
-                                    packet.routing_header = srh; // Perform the update
-                                    Some(packet.clone()) // Clone the packet
-                                } else {
-                                    None
-                                }
-                            } else {  //when the routing table doesn't contain the wrong route and is empty
-                                None
-                            }
-                        }
-                        None => None,
-                    };
-                    pack  // packet_to_send
-                } else {
-                    None // packet_to_send
-                }
-            } else {
-                warn!("Packet not found in output buffer (Session: {}, Fragment: {})", session_id, fragment_index);
-                None
-            }
+    fn reroute_and_resend(&mut self, session_id: SessionId, fragment_index: FragmentIndex) {
+        let Some(destination) = self.storage.transport.destination(session_id) else {
+            warn!("Packet not found in the transport (Session: {}, Fragment: {})", session_id, fragment_index);
+            return;
         };
 
-        // Send the packet when conditions are satisfied
-        if let Some(p) = packet_to_send {
-            self.send(p);
+        match self.communication.routes.best(destination).cloned() {
+            Some(route) if self.storage.transport.route(session_id) == Some(route.as_slice()) => {
+                //still the wrong path memorized, the retransmission timer resends the fragment later
+                self.communication.rediscovery.request();
+            }
+            Some(route) => {
+                self.storage.transport.set_route(session_id, route);
+                self.storage.transport.resend(session_id, fragment_index);
+                self.send_transport_packets();
+            }
+            None => {
+                //every route to the destination failed, the retransmission timer resends the fragment later
+                warn!("No valid routes to {}", destination);
+                self.communication.rediscovery.request();
+            }
         }
     }
-}
\ No newline at end of file
+}
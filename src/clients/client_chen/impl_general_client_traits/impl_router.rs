@@ -7,10 +7,10 @@ impl Router for ClientChen {
     fn do_flooding(&mut self) {
         // New ids for the flood and new session because of the flood response packet
         self.status.flood_id += 1;
-        self.status.session_id += 1;
+        self.status.session_id = self.storage.transport.new_session_id();
 
-        self.communication.routing_table.clear();
-        self.network_info.topology.clear();
+        // The known routes are kept, the flood responses are merged into them
+        self.communication.rediscovery.flooded();
 
         // Initialize the flood request with the current flood_id, id, and node type
         let flood_request = FloodRequest::initialize(self.status.flood_id, self.metadata.node_id, NodeType::Client);
@@ -33,9 +33,17 @@ impl Router for ClientChen {
         }
     }
 
+    ///flooding again when it is time to, see Rediscovery
+    fn rediscover_if_due(&mut self) {
+        if self.communication.rediscovery.is_due() {
+            debug!("Rediscovering the network");
+            self.do_flooding();
+        }
+    }
+
     fn update_routing_for_server(&mut self, destination_id: NodeId, path_trace: Vec<(NodeId, NodeType)>) {
         let hops = self.get_hops_from_path_trace(path_trace);
-        self.communication.routing_table.insert(destination_id, hops.clone());
+        self.communication.routes.add(destination_id, hops.clone());
         //println!("Successfully updated routing table for server {}", destination_id);
         //println!("The routing table is: {:?}", self.communication.routing_table);
         let srh = SourceRoutingHeader::initialize(hops);
@@ -44,9 +52,10 @@ impl Router for ClientChen {
     }
     fn update_routing_for_client(&mut self, destination_id: NodeId, path_trace: Vec<(NodeId, NodeType)>) {
         let hops = self.get_hops_from_path_trace(path_trace.clone());
-        self.communication.routing_table.insert(destination_id, hops);
-        info!("Successfully updated routing table for client {}", destination_id);
-        info!("The routing table is: {:?}", self.communication.routing_table);
+        if self.communication.routes.add(destination_id, hops) {
+            info!("Successfully updated routing table for client {}", destination_id);
+        }
+        info!("The routes to client {} are: {:?}", destination_id, self.communication.routes.routes(destination_id));
     }
 
     ///auxiliary function
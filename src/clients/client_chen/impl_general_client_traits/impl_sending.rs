@@ -1,40 +1,7 @@
-use crate::clients::client_chen::{ClientChen, PacketCreator, Router, Sending};
+use crate::clients::client_chen::{ClientChen, PacketCreator, Sending};
 use crate::clients::client_chen::prelude::*;
-use crate::clients::client_chen::general_client_traits::*;
-use crate::general_use::NotSentType::ToBeSent;
 
 impl Sending for ClientChen {
-    fn send_packets_in_buffer_with_checking_status(&mut self) {
-        //looping in the sessions, but now agreed to have only one session in the buffer.
-        //so the first for is pretty useless, but it doesn't hurt the program
-        let sessions: Vec<SessionId> = self.storage.output_buffer.keys().cloned().collect();
-        for session_id in sessions {
-            if let Some(fragments) = self.storage.output_buffer.get(&session_id) {
-                let fragment_indices: Vec<_> = fragments.keys().cloned().collect();
-
-                //now we are looping in the fragment indexes to see which packets are not sent
-                for fragment_index in fragment_indices {
-                    if let Some(packet) = self.storage.output_buffer
-                        .get(&session_id)
-                        .and_then(|fragments| fragments.get(&fragment_index))
-                    {
-                        if let Some(status) = self.storage.packets_status
-                            .get(&session_id)
-                            .and_then(|fragments| fragments.get(&fragment_index))
-                        {
-                            //we will manage these packets based on their sending status
-                            self.packets_status_sending_actions(packet.clone(), status.clone());
-                        } else {
-                            warn!("Missing status for session {} fragment {}", session_id, fragment_index);
-                        }
-                    } else {
-                        warn!("Missing packet in output_packet_disk for session {} fragment {}", session_id, fragment_index);
-                    }
-                }
-            }
-        }
-    }
-
     fn send(&mut self, packet: Packet) {
         if let Some(next_hop) = packet.routing_header.next_hop() {
             self.send_packet_to_connected_node(next_hop, packet);
@@ -49,130 +16,52 @@ impl Sending for ClientChen {
     }
 
     fn send_query(&mut self, server_id: ServerId, query: Query) {
-        if let Some(query_packets) = self.msg_to_fragments(query, server_id) {
-            for query_packet in query_packets {
-                self.send(query_packet);
-            }
+        if let Some(source_routing_header) = self.get_source_routing_header(server_id) {
+            self.send_query_by_routing_header(source_routing_header, query);
         } else {
-            warn!("Failed to fragment query");
+            warn!("No route to server {}, query not sent", server_id);
         }
     }
 
     fn send_query_by_routing_header(&mut self, source_routing_header: SourceRoutingHeader, query: Query) {
-        if let Some(query_packets) = self.msg_to_fragments_by_routing_header(query, source_routing_header) {
-            let test_query = self.reassemble_fragments::<Query>(query_packets.clone());
-            match test_query {
-                Ok(test_query) => {
-                    println!("the query: {:?}", test_query);
-                }
-                Err(e) => {
-                    error!("Failed to assemble query: {}", e);
-                }
+        //the transport fragments the query and keeps it until every fragment is acked
+        match self.storage.transport.send(&query, source_routing_header.hops) {
+            Ok(session) => info!("Sending query {:?} to {} in session {}", query, session.destination, session.session_id),
+            Err(e) => {
+                warn!("Failed to fragment query: {}", e);
+                return;
             }
-            for query_packet in query_packets {
-                self.send(query_packet);
-            }
-        } else {
-            warn!("Failed to fragment query");
         }
+        self.send_transport_packets();
     }
 
     fn send_packet_to_connected_node(&mut self, target_node_id: NodeId, mut packet: Packet) {
-        // Store packet with proper nested structure
-        let (session_id, fragment_index) = match &packet.pack_type {
-            PacketType::MsgFragment(fragment) => (packet.session_id, fragment.fragment_index),
-            _ => (packet.session_id, 0),
-        };
-
-        self.storage.output_buffer
-            .entry(session_id)
-            .or_default()
-            .insert(fragment_index, packet.clone());
-
         packet.routing_header.increase_hop_index();
-        // Attempt to send packet
+        // Attempt to send packet, the fragments not sent are retried by the retransmission timers
         match self.communication_tools.packet_send.get_mut(&target_node_id) {
             Some(sender) if self.communication.connected_nodes_ids.contains(&target_node_id) => {
                 match sender.send(packet.clone()) {
-                    Ok(_) => {
-                        info!("Successfully sent packet to {}", target_node_id);
-                        self.update_packet_status(session_id, fragment_index, PacketStatus::InProgress);
-                    },
-                    Err(e) => {
-                        error!("Failed to send to {}: {}", target_node_id, e);
-                        self.update_packet_status(session_id, fragment_index, PacketStatus::NotSent(ToBeSent));
-                    }
+                    Ok(_) => info!("Successfully sent packet to {}", target_node_id),
+                    Err(e) => error!("Failed to send to {}: {}", target_node_id, e),
                 }
             }
-            _ => {
-                warn!("No valid connection to {}", target_node_id);
-                self.update_packet_status(session_id, fragment_index, PacketStatus::NotSent(ToBeSent));
-            }
+            _ => warn!("No valid connection to {}", target_node_id),
         }
     }
 
-    fn packets_status_sending_actions(&mut self, packet: Packet, packet_status: PacketStatus) {
-        let destination = packet.routing_header.destination();
-
-        match packet_status {
-            PacketStatus::NotSent(not_sent_type) => {
-                self.handle_not_sent_packet(packet, not_sent_type, destination.unwrap());
-            }
-            PacketStatus::Sent => {
-                self.handle_sent_packet(packet);
-            }
-            _ => {} // No action needed
-        }
-    }
-
-    fn handle_sent_packet(&mut self, packet: Packet) {
-        let (session_id, fragment_index) = match &packet.pack_type {
-            PacketType::MsgFragment(fragment) => (packet.session_id, fragment.fragment_index),
-            _ => (packet.session_id, 0),
-        };
-
-        // This is already done in the ack handling, but it's ok to retain it,
-        if let Some(fragments) = self.storage.output_buffer.get_mut(&session_id) {
-            fragments.remove(&fragment_index);
-            if fragments.is_empty() {
-                self.storage.output_buffer.remove(&session_id);
-            }
+    fn send_transport_packets(&mut self) {
+        for packet in self.storage.transport.take_packets() {
+            self.send(packet);
         }
     }
 
-    fn handle_not_sent_packet(&mut self, mut packet: Packet, not_sent_type: NotSentType, destination: NodeId) {
-        let routes = self.communication.routing_table.get(&destination);
-        match not_sent_type {
-            //through
-            NotSentType::RoutingError | NotSentType::ToBeSent | NotSentType::BeenInWrongRecipient => {
-                if routes.map_or(false, |r| !r.is_empty()) {
-                    let srh = self.get_source_routing_header(destination);
-                    packet.routing_header = srh.unwrap();
-                    self.send(packet);
-                } else {
-                    warn!("No valid routes to {}", destination);
-                }
-            }
-            NotSentType::Dropped =>{
-                //resend the packet
-                self.send(packet);
-            }
-            NotSentType::DroneDestination => {
-                let (session_id, fragment_index) = match &packet.pack_type {
-                    PacketType::MsgFragment(fragment) => (packet.session_id, fragment.fragment_index),
-                    _ => (packet.session_id, 0),
-                };
-                self.storage.output_buffer
-                    .entry(session_id)
-                    .and_modify(|fragments| { fragments.remove(&fragment_index); });
-            }
+    fn resend_expired_fragments(&mut self) {
+        //giving up the sessions whose fragments ran out of retries
+        for session in self.storage.transport.check_timers() {
+            error!("Session {} to {} failed: no ack after every retry", session.session_id, session.destination);
+            self.send_events(ClientEvent::SessionFailed(self.metadata.node_id, session.session_id, session.destination));
         }
+        //resending the fragments whose ack is late
+        self.send_transport_packets();
     }
-
-    fn update_packet_status(&mut self, session_id: SessionId, fragment_index: FragmentIndex, status: PacketStatus) {
-        self.storage.packets_status
-            .entry(session_id)
-            .or_default()
-            .insert(fragment_index, status);
-    }
-}
\ No newline at end of file
+}
@@ -11,7 +11,6 @@ pub use wg_2024::{
     network::{NodeId, SourceRoutingHeader},
     packet::{
         Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
-        FRAGMENT_DSIZE,
     },
 };
 
@@ -23,8 +22,6 @@ pub use crate::general_use::{ClientId,
                              ClientCommand,
                              ClientEvent,
                              Message,
-                             NotSentType,
-                             PacketStatus,
                              Query,
                              Response,
                              ServerType,
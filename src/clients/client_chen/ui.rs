@@ -1,6 +1,6 @@
 use crate::clients::client_chen::prelude::*;
 use crate::ui_traits::Monitoring;
-use crate::clients::client_chen::{ClientChen, CommandHandler, FragmentsHandler, PacketsReceiver, Router, Sending};
+use crate::clients::client_chen::{ClientChen, CommandHandler, PacketsReceiver, Router};
 use std::collections::{HashMap};
 use crossbeam_channel::{Sender, select_biased};
 use log::log;
@@ -49,9 +49,6 @@ impl Monitoring for ClientChen{
                     if let Ok(command) = command_res {
                         // Handle the command
                         self.handle_controller_command_with_monitoring(command, sender_to_gui.clone());
-                        // Things to do after handling the command
-                        self.handle_fragments_in_buffer_with_checking_status();
-                        self.send_packets_in_buffer_with_checking_status();
                         //self.update_routing_checking_status();
 
                         // Update the network
@@ -62,10 +59,6 @@ impl Monitoring for ClientChen{
                     if let Ok(packet) = packet_res {
                         // Handle the packet
                         self.handle_received_packet(packet);
-
-                        // Things to do after handling the packets
-                        self.handle_fragments_in_buffer_with_checking_status();
-                        self.send_packets_in_buffer_with_checking_status();
                         //self.update_routing_checking_status();
 
                         // Update the network
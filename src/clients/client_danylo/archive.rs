@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::general_use::{ChatHistory, ClientId, Conversation, MessageId, MessageStatus, RoomName, Speaker};
+use super::encryption::PeerKeys;
+
+/// ###### Directory of the data each chat client keeps between runs.
+const ARCHIVE_DIR: &str = "chat_data";
+/// ###### Minimum time between two saves, the changes in between are written together.
+/// Unlike a save on every change, a client killed without `Shutdown` can lose up to this much of its history;
+/// `Shutdown` and the disconnection of the controller always save the pending changes.
+pub const ARCHIVE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// ###### Data the chat client keeps on disk between runs.
+/// Saved on the tick after the chat histories or the keys changed, see `ARCHIVE_SAVE_INTERVAL`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChatArchive {
+    pub keys: PeerKeys,
+    #[serde(default)]
+    pub chats: HashMap<ClientId, ChatHistory>,
+    #[serde(default)]
+    pub room_chats: HashMap<RoomName, ChatHistory>,
+    #[serde(default)]
+    pub message_id_counter: MessageId,      // Message IDs are never reused, or old receipts would match new messages
+}
+
+impl ChatArchive {
+    /// ###### File of the client's archive.
+    pub fn path(client_id: ClientId) -> PathBuf {
+        Path::new(ARCHIVE_DIR).join(format!("client_{}.json", client_id))
+    }
+
+    /// ###### Loads the archive, empty if the client has none yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        let mut archive: Self = serde_json::from_str(&data)
+            .map_err(|err| format!("Unable to parse {}: {}", path.display(), err))?;
+
+        // The messages still pending when the client stopped were lost with its sessions
+        for chat_message in archive.chats.values_mut().chain(archive.room_chats.values_mut()).flatten() {
+            if chat_message.status == MessageStatus::Pending {
+                chat_message.status = MessageStatus::Failed;
+            }
+        }
+
+        Ok(archive)
+    }
+}
+
+/// ###### The data of the archive borrowed from the client, to save it without copying the chat histories.
+/// Same fields as `ChatArchive`, which loads the file back.
+#[derive(Debug, Serialize)]
+pub struct ArchiveView<'a> {
+    pub keys: &'a PeerKeys,
+    pub chats: &'a HashMap<ClientId, ChatHistory>,
+    pub room_chats: &'a HashMap<RoomName, ChatHistory>,
+    pub message_id_counter: MessageId,
+}
+
+impl ArchiveView<'_> {
+    /// ###### Saves the archive.
+    /// Writes a temporary file first, so a crash never leaves a truncated archive behind.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("Unable to create {}: {}", dir.display(), err))?;
+        }
+
+        let data = serde_json::to_string_pretty(self).map_err(|err| format!("Unable to serialize the archive: {}", err))?;
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, data).map_err(|err| format!("Unable to write {}: {}", temp_path.display(), err))?;
+        fs::rename(&temp_path, path).map_err(|err| format!("Unable to replace {}: {}", path.display(), err))
+    }
+}
+
+/// ###### Writes the chat history of a conversation to a text file, one message per line.
+/// Every line holds the message ID, the time in seconds since the UNIX epoch, the speaker and the status.
+pub fn export_conversation(conversation: &Conversation, history: &ChatHistory, path: &Path) -> Result<(), String> {
+    let mut text = format!("Chat with {}\n", conversation);
+
+    for chat_message in history {
+        let speaker = match (&chat_message.speaker, conversation) {
+            (Speaker::Me, _) => "me".to_string(),
+            (Speaker::HimOrHer, Conversation::Direct(peer)) | (Speaker::Member(peer), _) => format!("client {}", peer),
+            (Speaker::HimOrHer, Conversation::Room(_)) => "unknown".to_string(),
+        };
+        let _ = writeln!(text, "[{}] #{} {}: {} ({})",
+            chat_message.timestamp, chat_message.id, speaker, chat_message.content, chat_message.status);
+    }
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|err| format!("Unable to create {}: {}", dir.display(), err))?;
+    }
+    fs::write(path, text).map_err(|err| format!("Unable to write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::general_use::ChatMessage;
+
+    #[test]
+    fn saved_view_loads_back_with_pending_messages_failed() {
+        let path = std::env::temp_dir().join(format!("chat_archive_test_{}.json", std::process::id()));
+        let message = |id, status| ChatMessage { id, speaker: Speaker::Me, content: "hi".to_string(), status, timestamp: 0 };
+        let chats = HashMap::from([(2, vec![message(1, MessageStatus::DeliveredToPeer), message(2, MessageStatus::Pending)])]);
+        let room_chats = HashMap::new();
+
+        let view = ArchiveView { keys: &PeerKeys::default(), chats: &chats, room_chats: &room_chats, message_id_counter: 3 };
+        view.save(&path).unwrap();
+        let archive = ChatArchive::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(archive.message_id_counter, 3);
+        let statuses: Vec<MessageStatus> = archive.chats[&2].iter().map(|chat_message| chat_message.status).collect();
+        assert_eq!(statuses, vec![MessageStatus::DeliveredToPeer, MessageStatus::Failed]);
+    }
+}
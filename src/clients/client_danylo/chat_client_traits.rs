@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+use std::time::Duration;
 use crossbeam_channel::Sender;
 
 use wg_2024::{
@@ -6,19 +8,19 @@ use wg_2024::{
 };
 
 use crate::general_use::{
-    ClientCommand, ClientEvent, ClientId, FloodId, FragmentIndex, Message, Query, Response,
-    ServerId, ServerType, SessionId, Node,
+    ChatHistory, ClientCommand, ClientEvent, ClientId, Conversation, FloodId, FragmentIndex, Message, MessageId,
+    MessageStatus, Presence, Query, Response, RoomMessage, RoomName, ServerId, ServerType, SessionId, Speaker, Node,
 };
 
 pub(super) trait PacketHandler {
     fn handle_packet(&mut self, packet: Packet);
     fn handle_ack(&mut self, fragment_index: FragmentIndex, session_id: SessionId);
-    fn handle_nack(&mut self, nack: Nack, session_id: SessionId);
+    fn handle_nack(&mut self, nack: Nack, session_id: SessionId, source: NodeId);
     fn update_topology_and_routes(&mut self, error_node: NodeId);
-    fn find_route_to(&self, server_id: ServerId) -> Option<Vec<NodeId>>;
+    fn refresh_routes(&mut self);
     fn update_message_route_and_resend(&mut self, fragment_index: FragmentIndex, session_id: SessionId);
     fn update_message_route(&mut self, session_id: &SessionId) -> Result<(), String>;
-    fn handle_fragment(&mut self, fragment: Fragment, session_id: SessionId, server_id: ServerId);
+    fn handle_fragment(&mut self, fragment: Fragment, session_id: SessionId, routing_header: SourceRoutingHeader);
     fn handle_flood_request(&mut self, flood_request: FloodRequest, session_id: SessionId);
     fn handle_flood_response(&mut self, flood_response: FloodResponse);
     fn update_topology(&mut self, path: &[Node]);
@@ -30,36 +32,73 @@ pub(super) trait CommandHandler {
     fn add_sender(&mut self, id: NodeId, sender: Sender<Packet>);
     fn remove_sender(&mut self, id: NodeId);
     fn send_known_servers(&mut self);
+    fn send_route_costs(&mut self, destination: NodeId);
     fn discovery(&mut self);
+    fn rediscover_if_due(&mut self);
     fn request_server_type(&mut self, server_id: ServerId);
     fn send_message_to(&mut self, to: ClientId, message: String);
+    fn server_of_client(&self, client_id: ClientId) -> Option<ServerId>;
+    fn send_direct_message(&mut self, message: Message, server_id: ServerId);
     fn request_to_register(&mut self, server_id: ServerId);
+    fn request_to_unregister(&mut self, server_id: ServerId);
     fn request_clients_list(&mut self, server_id: ServerId);
+    fn create_room(&mut self, server_id: ServerId, room: RoomName);
+    fn join_room(&mut self, server_id: ServerId, room: RoomName);
+    fn leave_room(&mut self, room: RoomName);
+    fn request_rooms_list(&mut self, server_id: ServerId);
+    fn send_room_message(&mut self, room: RoomName, content: String);
+    fn send_room_query(&mut self, query: Query, server_id: ServerId);
     fn create_and_send_message(&mut self, query: Query, server_id: ServerId) -> Result<(), String>;
+    fn set_window_size(&mut self, window_size: usize);
+    fn set_rediscovery_interval(&mut self, interval: Option<Duration>);
+    fn set_encryption(&mut self, enabled: bool);
 }
 
 pub(super) trait ServerResponseHandler {
     fn handle_server_response(&mut self, response: Option<Response>, server_id: ServerId);
     fn handle_server_type(&mut self, server_id: ServerId, server_type: ServerType);
     fn handle_client_registered(&mut self, server_id: ServerId);
+    fn handle_client_unregistered(&mut self, server_id: ServerId);
+    fn handle_presence_changed(&mut self, server_id: ServerId, client_id: ClientId, presence: Presence);
     fn handle_clients_list(&mut self, server_id: ServerId, clients: Vec<ClientId>);
-    fn handle_message(&mut self, message: Message);
+    fn handle_message(&mut self, message: Message, server_id: ServerId);
+    fn handle_message_delivered(&mut self, message_id: MessageId, recipient: ClientId);
+    fn handle_rooms_list(&mut self, rooms: Vec<RoomName>);
+    fn handle_room_message(&mut self, message: RoomMessage);
+    fn handle_joined_room(&mut self, server_id: ServerId, room: RoomName, client_id: ClientId);
+    fn handle_left_room(&mut self, room: RoomName, client_id: ClientId);
+    fn update_message_status(&mut self, conversation: Conversation, message_id: MessageId, status: MessageStatus);
+    fn chat_history(&mut self, conversation: &Conversation) -> &mut ChatHistory;
 }
 
 pub(super) trait Senders {
     fn send_to_next_hop(&mut self, packet: Packet) -> Result<(), String>;
+    fn send_transport_packets(&mut self) -> Result<(), String>;
     fn send_ack(&mut self, fragment_index: FragmentIndex, session_id: SessionId, routing_header: SourceRoutingHeader);
     fn send_event(&self, event: ClientEvent);
     fn resend_fragment(&mut self, fragment_index: FragmentIndex, session_id: SessionId);
+    fn resend_expired_fragments(&mut self);
     fn resend_queries(&mut self);
 }
 
+pub(super) trait Encryption {
+    fn send_public_key(&mut self, peer: ClientId, server_id: ServerId, reply: bool);
+    fn handle_public_key(&mut self, peer: ClientId, key: &[u8], reply: bool, server_id: ServerId);
+    fn send_waiting_messages(&mut self, peer: ClientId, server_id: ServerId);
+    fn trust_new_key(&mut self, peer: ClientId);
+    fn check_key_exchanges(&mut self);
+}
+
+pub(super) trait History {
+    fn add_to_history(&mut self, conversation: &Conversation, id: MessageId, speaker: Speaker, content: String, status: MessageStatus);
+    fn export_conversation(&mut self, conversation: Conversation, path: PathBuf);
+    fn archive_changed(&mut self);
+    fn save_archive_if_due(&mut self);
+    fn save_archive(&mut self);
+}
+
 pub(super) trait GeneratorId {
     fn generate_session_id(&mut self) -> SessionId;
     fn generate_flood_id(&mut self) -> FloodId;
-    fn parse_id(&self, id: u64) -> u64;
-}
-
-pub(super) trait Reassembler {
-    fn reassemble(&mut self, session_id: SessionId) -> Option<Response>;
+    fn generate_message_id(&mut self) -> MessageId;
 }
@@ -1,20 +1,25 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::Instant;
 
-use crossbeam_channel::{select_biased, Receiver, Sender};
-use log::info;
+use crossbeam_channel::{select_biased, tick, Receiver, Sender};
+use log::{error, info};
 
 use wg_2024::{
     network::NodeId,
-    packet::{Fragment, Packet},
+    packet::Packet,
 };
 
 use crate::{
     general_use::{
-        ClientCommand, ClientEvent, Query, ServerType, ClientId, ServerId, SessionId, FloodId, ChatHistory
+        ClientCommand, ClientEvent, Query, ServerType, ClientId, ServerId, SessionId, FloodId, ChatHistory, MessageId, Conversation, RoomName, Presence
     },
-    clients::Client
+    clients::Client,
+    ids::SeenFloods,
+    routing::{Rediscovery, RouteCache, Topology},
+    transport::{Transport, RETRANSMISSION_TICK},
 };
-use super::{PacketHandler, CommandHandler, MessageFragments};
+use super::{PacketHandler, CommandHandler, Encryption, History, Senders, archive::ChatArchive, encryption::{PeerKeys, WaitingForKey}};
 
 pub struct ChatClientDanylo {
     // ID
@@ -32,24 +37,37 @@ pub struct ChatClientDanylo {
     pub(super) servers: HashMap<ServerId, ServerType>,                       // IDs and types of the available servers
     pub(super) is_registered: HashMap<ServerId, bool>,                       // Registration status on servers
     pub(super) clients: HashMap<ServerId, Vec<ClientId>>,                    // Available clients on different servers
+    pub(super) rooms: HashMap<RoomName, ServerId>,                           // Rooms joined and their server
+    pub(super) presence: HashMap<ClientId, Presence>,                        // Last known presence of the other clients
 
     // Used IDs
-    pub(super) session_id_counter: SessionId,                                // Counter for session IDs
     pub(super) flood_id_counter: FloodId,                                    // Counter for flood IDs
+    pub(super) message_id_counter: MessageId,                                // Counter for chat message IDs
     pub(super) session_ids: Vec<SessionId>,                                  // Used session IDs
     pub(super) flood_ids: Vec<FloodId>,                                      // Used flood IDs
+    pub(super) seen_floods: SeenFloods,                                      // Flood requests already answered
 
     // Network
-    pub(super) topology: HashMap<NodeId, HashSet<NodeId>>,                   // Nodes and their neighbours
-    pub(super) routes: HashMap<ServerId, Vec<NodeId>>,                       // Routes to the servers
+    pub(super) topology: Topology,                                           // Nodes and their neighbours
+    pub(super) routes: RouteCache,                                           // Best and alternative routes to the servers
+    pub(super) rediscovery: Rediscovery,                                     // When to flood the network again
 
     // Message queues
-    pub(super) messages_to_send: HashMap<SessionId, MessageFragments>,       // Queue of messages to be sent for different sessions
-    pub(super) fragments_to_reassemble: HashMap<SessionId, Vec<Fragment>>,   // Queue of fragments to be reassembled for different sessions
+    pub(super) transport: Transport,                                         // Sessions of the messages sent and received
     pub(super) queries_to_resend: VecDeque<(ServerId, Query)>,               // Queue of queries to resend
 
     // Chats
     pub(super) chats: HashMap<ClientId, ChatHistory>,                        // Chat histories with other clients
+    pub(super) room_chats: HashMap<RoomName, ChatHistory>,                   // Chat histories of the rooms, kept after leaving
+    pub(super) message_sessions: HashMap<SessionId, (Conversation, MessageId)>, // Chat messages being sent, by session
+
+    // End-to-end encryption
+    pub(super) encryption: bool,                                             // Encrypt the direct messages
+    pub(super) peer_keys: PeerKeys,                                          // Keys shared with the other clients
+    pub(super) waiting_for_key: HashMap<ClientId, WaitingForKey>,            // Messages waiting for the key exchange
+    pub(super) archive_path: PathBuf,                                        // File of the chat histories and keys kept between runs
+    pub(super) archive_changed: bool,                                        // Changes not saved yet
+    pub(super) archive_saved: Instant,                                       // Last save of the archive
 }
 
 impl Client for ChatClientDanylo {
@@ -62,6 +80,13 @@ impl Client for ChatClientDanylo {
         ui_response_send: Sender<crate::general_use::Response>,
     ) -> Self {
         info!("Starting ChatClientDanylo with ID: {}", id);
+
+        let archive_path = ChatArchive::path(id);
+        let archive = ChatArchive::load(&archive_path).unwrap_or_else(|err| {
+            error!("Client {}: Failed to load the archive: {}", id, err);
+            ChatArchive::default()
+        });
+
         Self {
             id,
             packet_send,
@@ -72,34 +97,64 @@ impl Client for ChatClientDanylo {
             servers: HashMap::new(),
             is_registered: HashMap::new(),
             clients: HashMap::new(),
-            session_id_counter: 0,
+            rooms: HashMap::new(),
+            presence: HashMap::new(),
             flood_id_counter: 0,
+            message_id_counter: archive.message_id_counter,
             session_ids: Vec::new(),
             flood_ids: Vec::new(),
-            topology: HashMap::new(),
-            routes: HashMap::new(),
-            messages_to_send: HashMap::new(),
-            fragments_to_reassemble: HashMap::new(),
+            seen_floods: SeenFloods::default(),
+            topology: Topology::new(),
+            routes: RouteCache::new(id),
+            rediscovery: Rediscovery::new(),
+            transport: Transport::new(id),
             queries_to_resend: VecDeque::new(),
-            chats: HashMap::new(),
+            chats: archive.chats,
+            room_chats: archive.room_chats,
+            message_sessions: HashMap::new(),
+            encryption: false,
+            peer_keys: archive.keys,
+            waiting_for_key: HashMap::new(),
+            archive_path,
+            archive_changed: false,
+            archive_saved: Instant::now(),
         }
     }
 
     fn run(&mut self) {
         info!("Running ChatClientDanylo with ID: {}", self.id);
+        let ticker = tick(RETRANSMISSION_TICK);
         loop {
             select_biased! {
                 recv(self.controller_recv) -> command_res => {
-                    if let Ok(command) = command_res {
-                        info!("Client {}: Received command: {:?}", self.id, command);
-                        self.handle_command(command);
+                    let Ok(command) = command_res else {
+                        info!("Client {}: Controller disconnected, stopping", self.id);
+                        self.save_archive();
+                        return;
+                    };
+                    if let ClientCommand::Shutdown = command {
+                        info!("Client {}: Shutting down", self.id);
+                        self.save_archive();
+                        return;
                     }
+                    info!("Client {}: Received command: {:?}", self.id, command);
+                    self.handle_command(command);
                 },
                 recv(self.packet_recv) -> packet_res => {
-                    if let Ok(packet) = packet_res {
-                        info!("Client {}: Received packet: {:?}", self.id, packet);
-                        self.handle_packet(packet);
-                    }
+                    let Ok(packet) = packet_res else {
+                        info!("Client {}: Every packet sender disconnected, stopping", self.id);
+                        self.save_archive();
+                        return;
+                    };
+                    info!("Client {}: Received packet: {:?}", self.id, packet);
+                    self.handle_packet(packet);
+                },
+                recv(ticker) -> _ => {
+                    self.resend_expired_fragments();
+                    self.refresh_routes();
+                    self.rediscover_if_due();
+                    self.check_key_exchanges();
+                    self.save_archive_if_due();
                 },
             }
         }
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::general_use::{ClientId, MessageContent, MessageId};
+
+/// ###### Context of the key derivation, so the shared secret is used only for the chat messages.
+const KEY_INFO: &[u8] = b"chat client end-to-end key";
+const NONCE_LENGTH: usize = 12;
+
+/// ###### Time between two public keys sent to a peer that did not answer yet.
+pub const KEY_RESEND_INTERVAL: Duration = Duration::from_secs(5);
+/// ###### Time a message waits for the key exchange before it is marked as failed.
+pub const KEY_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// ###### Messages to a peer waiting for the key exchange, with the time they were queued.
+#[derive(Debug, Clone)]
+pub struct WaitingForKey {
+    pub messages: Vec<(MessageId, String, Instant)>,
+    pub key_sent: Instant,      // Last public key sent to the peer
+}
+
+impl WaitingForKey {
+    pub fn new() -> Self {
+        Self { messages: Vec::new(), key_sent: Instant::now() }
+    }
+
+    /// ###### Removes and returns the messages that waited too long.
+    pub fn take_expired(&mut self, timeout: Duration) -> Vec<MessageId> {
+        let (expired, waiting) = self.messages.drain(..).partition(|(_, _, queued)| queued.elapsed() >= timeout);
+        self.messages = waiting;
+        expired.into_iter().map(|(message_id, _, _)| message_id).collect()
+    }
+}
+
+/// ###### Keys used with a peer.
+/// The secret is generated on the first exchange, the shared key is derived once the peer's public key arrives.
+/// The first public key of the peer is pinned, a different one is refused until the user trusts it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PeerKey {
+    secret: [u8; 32],
+    shared: Option<[u8; 32]>,
+    #[serde(default)]
+    pinned: Option<[u8; 32]>,
+    #[serde(skip)]
+    refused: Option<[u8; 32]>,  // Last key refused, kept for the user to trust it
+}
+
+/// ###### Outcome of a public key received from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyExchange {
+    Established,
+    Unchanged,
+    /// The peer sent a key different from the pinned one, nothing was changed.
+    Refused { pinned: String, received: String },
+}
+
+/// ###### End-to-end keys, one per peer.
+/// Every peer gets its own X25519 key pair; the shared secret goes through HKDF-SHA256
+/// and the resulting key encrypts the messages with ChaCha20-Poly1305,
+/// bound to their ID, sender and recipient so the server cannot swap them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerKeys {
+    keys: HashMap<ClientId, PeerKey>,
+}
+
+impl PeerKeys {
+    /// ###### Returns the public key to send to the peer, generating the key pair the first time.
+    pub fn public_key(&mut self, peer: ClientId) -> [u8; 32] {
+        let key = self.keys.entry(peer).or_insert_with(|| PeerKey { secret: rand::random(), shared: None, pinned: None, refused: None });
+        PublicKey::from(&StaticSecret::from(key.secret)).to_bytes()
+    }
+
+    pub fn is_established(&self, peer: ClientId) -> bool {
+        self.keys.get(&peer).is_some_and(|key| key.shared.is_some())
+    }
+
+    /// ###### Derives the key shared with the peer from its public key.
+    /// The first key of the peer is trusted and pinned, a different one is refused with the fingerprints of the two.
+    pub fn establish(&mut self, own_id: ClientId, peer: ClientId, peer_public: &[u8]) -> Result<KeyExchange, String> {
+        let peer_public: [u8; 32] = peer_public.try_into()
+            .map_err(|_| format!("Invalid public key from client {}", peer))?;
+
+        self.public_key(peer);
+        let key = self.keys.get_mut(&peer).ok_or(format!("No key pair for client {}", peer))?;
+
+        match key.pinned {
+            Some(pinned) if pinned == peer_public && key.shared.is_some() => return Ok(KeyExchange::Unchanged),
+            Some(pinned) if pinned != peer_public => {
+                key.refused = Some(peer_public);
+                return Ok(KeyExchange::Refused { pinned: fingerprint(&pinned), received: fingerprint(&peer_public) });
+            }
+            _ => {}
+        }
+
+        let shared_secret = StaticSecret::from(key.secret).diffie_hellman(&PublicKey::from(peer_public));
+        if !shared_secret.was_contributory() {
+            return Err(format!("Weak public key from client {}", peer));
+        }
+
+        // Both clients put the IDs in the same order, so they derive the same key
+        let mut info = KEY_INFO.to_vec();
+        info.extend([own_id.min(peer), own_id.max(peer)]);
+
+        let mut shared = [0u8; 32];
+        Hkdf::<Sha256>::new(None, shared_secret.as_bytes())
+            .expand(&info, &mut shared)
+            .map_err(|err| format!("Failed to derive the key shared with client {}: {}", peer, err))?;
+
+        key.shared = Some(shared);
+        key.pinned = Some(peer_public);
+        Ok(KeyExchange::Established)
+    }
+
+    /// ###### Replaces the pinned key of the peer with the last one refused.
+    /// To be used once the user checked the new fingerprint with the peer.
+    pub fn trust_refused_key(&mut self, own_id: ClientId, peer: ClientId) -> Result<(), String> {
+        let key = self.keys.get_mut(&peer).ok_or(format!("No key exchanged with client {}", peer))?;
+        let refused = key.refused.take().ok_or(format!("No changed key from client {}", peer))?;
+        key.pinned = None;
+        key.shared = None;
+
+        self.establish(own_id, peer, &refused).map(|_| ())
+    }
+
+    /// ###### Encrypts the text of a message for its recipient.
+    pub fn encrypt(&self, message_id: MessageId, from: ClientId, to: ClientId, text: &str) -> Result<MessageContent, String> {
+        let cipher = self.cipher(to)?;
+        let nonce: [u8; NONCE_LENGTH] = rand::random();
+        let aad = associated_data(message_id, from, to);
+
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: text.as_bytes(), aad: &aad })
+            .map_err(|_| format!("Failed to encrypt message {}", message_id))?;
+
+        Ok(MessageContent::Encrypted { nonce: nonce.to_vec(), ciphertext })
+    }
+
+    /// ###### Decrypts the text of a message received from its sender.
+    /// Fails if the message was altered or the keys of the two clients differ.
+    pub fn decrypt(&self, message_id: MessageId, from: ClientId, to: ClientId, nonce: &[u8], ciphertext: &[u8]) -> Result<String, String> {
+        if nonce.len() != NONCE_LENGTH {
+            return Err(format!("Invalid nonce in message {}", message_id));
+        }
+
+        let cipher = self.cipher(from)?;
+        let aad = associated_data(message_id, from, to);
+
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| format!("Failed to decrypt message {} from client {}", message_id, from))?;
+
+        String::from_utf8(plaintext).map_err(|_| format!("Message {} from client {} is not valid text", message_id, from))
+    }
+
+    fn cipher(&self, peer: ClientId) -> Result<ChaCha20Poly1305, String> {
+        let shared = self.keys.get(&peer)
+            .and_then(|key| key.shared)
+            .ok_or(format!("No key shared with client {}", peer))?;
+
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&shared)))
+    }
+}
+
+/// ###### Short readable form of a public key, for the users to compare out of band.
+pub fn fingerprint(public_key: &[u8; 32]) -> String {
+    Sha256::digest(public_key)[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn associated_data(message_id: MessageId, from: ClientId, to: ClientId) -> Vec<u8> {
+    let mut data = message_id.to_be_bytes().to_vec();
+    data.extend([from, to]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(a: &mut PeerKeys, b: &mut PeerKeys) -> (KeyExchange, KeyExchange) {
+        let (a_public, b_public) = (a.public_key(2), b.public_key(1));
+        (a.establish(1, 2, &b_public).unwrap(), b.establish(2, 1, &a_public).unwrap())
+    }
+
+    #[test]
+    fn first_key_is_pinned_and_shared() {
+        let (mut alice, mut bob) = (PeerKeys::default(), PeerKeys::default());
+        assert_eq!(exchange(&mut alice, &mut bob), (KeyExchange::Established, KeyExchange::Established));
+        assert_eq!(exchange(&mut alice, &mut bob), (KeyExchange::Unchanged, KeyExchange::Unchanged));
+
+        let MessageContent::Encrypted { nonce, ciphertext } = alice.encrypt(7, 1, 2, "hello").unwrap() else {
+            panic!("message not encrypted");
+        };
+        assert_eq!(bob.decrypt(7, 1, 2, &nonce, &ciphertext).unwrap(), "hello");
+    }
+
+    #[test]
+    fn changed_key_is_refused_until_trusted() {
+        let (mut alice, mut bob) = (PeerKeys::default(), PeerKeys::default());
+        exchange(&mut alice, &mut bob);
+
+        // Bob lost his keys
+        let mut new_bob = PeerKeys::default();
+        let new_public = new_bob.public_key(1);
+        let refused = alice.establish(1, 2, &new_public).unwrap();
+        assert!(matches!(refused, KeyExchange::Refused { .. }));
+
+        // The old key is still in use
+        let MessageContent::Encrypted { nonce, ciphertext } = alice.encrypt(8, 1, 2, "hi").unwrap() else {
+            panic!("message not encrypted");
+        };
+        assert!(bob.decrypt(8, 1, 2, &nonce, &ciphertext).is_ok());
+
+        alice.trust_refused_key(1, 2).unwrap();
+        new_bob.establish(2, 1, &alice.public_key(2)).unwrap();
+        let MessageContent::Encrypted { nonce, ciphertext } = alice.encrypt(9, 1, 2, "hi again").unwrap() else {
+            panic!("message not encrypted");
+        };
+        assert_eq!(new_bob.decrypt(9, 1, 2, &nonce, &ciphertext).unwrap(), "hi again");
+        assert!(alice.trust_refused_key(1, 2).is_err());
+    }
+
+    #[test]
+    fn waiting_messages_expire() {
+        let mut waiting = WaitingForKey::new();
+        waiting.messages.push((1, "old".to_string(), Instant::now() - KEY_EXCHANGE_TIMEOUT));
+        waiting.messages.push((2, "new".to_string(), Instant::now()));
+
+        assert_eq!(waiting.take_expired(KEY_EXCHANGE_TIMEOUT), vec![1]);
+        assert_eq!(waiting.messages.len(), 1);
+        assert!(waiting.take_expired(KEY_EXCHANGE_TIMEOUT).is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use std::time::{Duration, Instant};
 use crossbeam_channel::Sender;
 use log::{debug, error, info, warn};
 
@@ -7,9 +8,11 @@ use wg_2024::{
 };
 
 use crate::general_use::{
-    ClientCommand, ClientEvent, ClientId, Message, Query, ServerId, ServerType, Speaker::Me
+    ClientCommand, ClientEvent, ClientId, Conversation, Message, MessageContent, MessageStatus, Query, RoomMessage,
+    RoomName, ServerId, ServerType, Speaker::Me
 };
-use super::{CommandHandler, ChatClientDanylo, PacketHandler, Senders, GeneratorId, MessageFragments};
+use super::{CommandHandler, ChatClientDanylo, Encryption, History, PacketHandler, Senders, GeneratorId, ServerResponseHandler};
+use crate::clients::client_danylo::encryption::WaitingForKey;
 
 impl CommandHandler for ChatClientDanylo {
     /// ###### Handles incoming commands.
@@ -40,23 +43,63 @@ impl CommandHandler for ChatClientDanylo {
             ClientCommand::RegisterToServer(server_id) => {
                 self.request_to_register(server_id)
             }
+            ClientCommand::UnregisterFromServer(server_id) => {
+                self.request_to_unregister(server_id)
+            }
             ClientCommand::AskListClients(server_id) => {
                 self.request_clients_list(server_id)
             }
+            ClientCommand::SetWindowSize(window_size) => {
+                self.set_window_size(window_size)
+            }
+            ClientCommand::SetRediscoveryInterval(interval) => {
+                self.set_rediscovery_interval(interval)
+            }
+            ClientCommand::SetEncryption(enabled) => {
+                self.set_encryption(enabled)
+            }
+            ClientCommand::TrustNewKey(peer) => {
+                self.trust_new_key(peer)
+            }
+            ClientCommand::ExportConversation(conversation, path) => {
+                self.export_conversation(conversation, path)
+            }
+            ClientCommand::RequestRoutes(destination) => {
+                self.send_route_costs(destination)
+            }
+            ClientCommand::CreateRoom(server_id, room) => {
+                self.create_room(server_id, room)
+            }
+            ClientCommand::JoinRoom(server_id, room) => {
+                self.join_room(server_id, room)
+            }
+            ClientCommand::LeaveRoom(room) => {
+                self.leave_room(room)
+            }
+            ClientCommand::AskListRooms(server_id) => {
+                self.request_rooms_list(server_id)
+            }
+            ClientCommand::SendRoomMessage(room, content) => {
+                self.send_room_message(room, content)
+            }
             _ => {}
         }
     }
 
     /// ###### Adds a sender for specified node.
+    /// The new neighbour may open shorter routes: a new discovery is requested.
     fn add_sender(&mut self, id: NodeId, sender: Sender<Packet>) {
         self.packet_send.insert(id, sender);
+        self.rediscovery.request();
         info!("Client {}: Added sender for node {}", self.id, id);
     }
 
     /// ###### Removes a sender for specified node.
+    /// The routes through the node are dropped and a new discovery is requested.
     fn remove_sender(&mut self, id: NodeId) {
         self.packet_send.remove(&id);
         self.update_topology_and_routes(id);
+        self.rediscovery.request();
         info!("Client {}: Removed sender for node {}", self.id, id);
     }
 
@@ -75,14 +118,20 @@ impl CommandHandler for ChatClientDanylo {
         self.send_event(ClientEvent::KnownServers(servers));
     }
 
+    /// ###### Handles the 'RequestRoutes' command.
+    /// Sends the routes to the destination, best first, with their estimated cost to the simulation controller.
+    fn send_route_costs(&mut self, destination: NodeId) {
+        let routes = self.routes.route_costs(destination);
+        self.send_event(ClientEvent::RouteCosts(self.id, destination, routes));
+    }
+
     /// ###### Initiates the discovery process to find available servers and clients.
-    /// Clears current data structures and sends a flood request to all neighbors.
+    /// Sends a flood request to all neighbors.
+    /// The known topology and routes are kept: the flood responses are merged into them,
+    /// so that the messages being sent keep their routes while the discovery runs.
     fn discovery(&mut self) {
         info!("Client {}: Starting discovery process", self.id);
-
-        // Clear all current data structures related to topology.
-        self.routes.clear();
-        self.topology.clear();
+        self.rediscovery.flooded();
 
         // Generate a new flood ID.
         let flood_id = self.generate_flood_id();
@@ -119,6 +168,14 @@ impl CommandHandler for ChatClientDanylo {
         }
     }
 
+    /// ###### Starts a new discovery when it is time to, see `Rediscovery`.
+    fn rediscover_if_due(&mut self) {
+        if self.rediscovery.is_due() {
+            debug!("Client {}: Rediscovering the network", self.id);
+            self.discovery();
+        }
+    }
+
     /// ###### Requests the server type for a specified server.
     fn request_server_type(&mut self, server_id: ServerId) {
         debug!("Client {}: Requesting server type for server {}", self.id, server_id);
@@ -144,12 +201,10 @@ impl CommandHandler for ChatClientDanylo {
     /// ###### Sends a message to a specified client.
     /// Sends a message to the server that the client is connected to,
     /// which then forwards the message to the specified client.
+    /// The message enters the chat history as pending and its status follows the ACKs and the receipt.
+    /// With the encryption on, the message waits for the key exchange with the client if it is not done yet.
     fn send_message_to(&mut self, to: ClientId, content: String) {
-        let option_server_id = self.clients.iter()
-            .find(|(_, clients)| clients.contains(&to))
-            .map(|(server_id, _)| *server_id);
-
-        let server_id = match option_server_id {
+        let server_id = match self.server_of_client(to) {
             Some(id) => id,
             None => {
                 error!("Client {}: Failed to send message: Client {} is not found", self.id, to);
@@ -159,15 +214,52 @@ impl CommandHandler for ChatClientDanylo {
 
         debug!("Client {}: Sending message to client {} via server {}", self.id, to, server_id);
 
-        let message = Message::new(self.id, to, content.clone());
+        let message_id = self.generate_message_id();
+
+        self.add_to_history(&Conversation::Direct(to), message_id, Me, content.clone(), MessageStatus::Pending);
+
+        let content = if !self.encryption {
+            MessageContent::Text(content)
+        } else if !self.peer_keys.is_established(to) {
+            // The key is sent again on the tick until the peer answers, see `check_key_exchanges`
+            let is_first = !self.waiting_for_key.contains_key(&to);
+            let waiting = self.waiting_for_key.entry(to).or_insert_with(WaitingForKey::new);
+            waiting.messages.push((message_id, content, Instant::now()));
+            if is_first {
+                self.send_public_key(to, server_id, true);
+            }
+            return;
+        } else {
+            match self.peer_keys.encrypt(message_id, self.id, to, &content) {
+                Ok(encrypted) => encrypted,
+                Err(err) => {
+                    error!("Client {}: Failed to send message: {}", self.id, err);
+                    self.update_message_status(Conversation::Direct(to), message_id, MessageStatus::Failed);
+                    return;
+                }
+            }
+        };
+
+        self.send_direct_message(Message::new(message_id, self.id, to, content), server_id);
+    }
+
+    /// ###### Returns the server the client is registered to, as listed by the servers.
+    fn server_of_client(&self, client_id: ClientId) -> Option<ServerId> {
+        self.clients.iter()
+            .find(|(_, clients)| clients.contains(&client_id))
+            .map(|(server_id, _)| *server_id)
+    }
+
+    /// ###### Sends a message of the chat history to the server of its recipient.
+    /// Marks the message as failed if it cannot be sent.
+    fn send_direct_message(&mut self, message: Message, server_id: ServerId) {
+        let (message_id, to) = (message.get_id(), message.get_recipient());
 
         let result = self.create_and_send_message(Query::SendMessage(message), server_id);
 
         match result {
             Ok(_) => {
                 info!("Client {}: Message sent successfully.", self.id);
-                let chat = self.chats.entry(to).or_insert_with(Vec::new);
-                chat.push((Me, content));
             }
             Err(err) => {
                 let error_string = format!("Client {}: Failed to send message: {}", self.id, err);
@@ -176,6 +268,7 @@ impl CommandHandler for ChatClientDanylo {
                     warn!("{}", error_string);
                 } else {
                     error!("{}", error_string);
+                    self.update_message_status(Conversation::Direct(to), message_id, MessageStatus::Failed);
                 }
             },
         }
@@ -211,6 +304,34 @@ impl CommandHandler for ChatClientDanylo {
         }
     }
 
+    /// ###### Requests to unregister the client from a specified server.
+    fn request_to_unregister(&mut self, server_id: ServerId) {
+        if self.is_registered.get(&server_id) != Some(&true) {
+            warn!("Client {}: Not registered on server {}", self.id, server_id);
+            self.ui_response_send.send(crate::general_use::Response::Err("Not registered".to_string())).unwrap();
+            return;
+        }
+
+        debug!("Client {}: Requesting to unregister from server {}", self.id, server_id);
+
+        let result = self.create_and_send_message(Query::UnregisterClient(self.id), server_id);
+
+        match result {
+            Ok(_) => {
+                info!("Client {}: Request to unregister sent successfully.", self.id);
+            }
+            Err(err) => {
+                let error_string = format!("Client {}: Failed to send request to unregister: {}", self.id, err);
+
+                if err == "Topology is empty. Discovery started and the query will be resent" {
+                    warn!("{}", error_string);
+                } else {
+                    error!("{}", error_string);
+                }
+            },
+        }
+    }
+
     /// ###### Requests the list of clients from a specified server.
     fn request_clients_list(&mut self, server_id: ServerId) {
         debug!("Client {}: Requesting clients list from server {}", self.id, server_id);
@@ -233,8 +354,80 @@ impl CommandHandler for ChatClientDanylo {
         }
     }
 
+    /// ###### Creates a room on a specified server, the client joins it.
+    fn create_room(&mut self, server_id: ServerId, room: RoomName) {
+        debug!("Client {}: Creating room {} on server {}", self.id, room, server_id);
+        self.send_room_query(Query::CreateRoom(room), server_id);
+    }
+
+    /// ###### Joins a room of a specified server.
+    fn join_room(&mut self, server_id: ServerId, room: RoomName) {
+        debug!("Client {}: Joining room {} on server {}", self.id, room, server_id);
+        self.send_room_query(Query::JoinRoom(room), server_id);
+    }
+
+    /// ###### Leaves a room, through the server of the room.
+    fn leave_room(&mut self, room: RoomName) {
+        let Some(&server_id) = self.rooms.get(&room) else {
+            error!("Client {}: Failed to leave room {}: not a member", self.id, room);
+            self.ui_response_send.send(crate::general_use::Response::Err(format!("Not a member of room {}", room))).unwrap();
+            return;
+        };
+
+        debug!("Client {}: Leaving room {} on server {}", self.id, room, server_id);
+        self.send_room_query(Query::LeaveRoom(room), server_id);
+    }
+
+    /// ###### Requests the list of rooms from a specified server.
+    fn request_rooms_list(&mut self, server_id: ServerId) {
+        debug!("Client {}: Requesting rooms list from server {}", self.id, server_id);
+        self.send_room_query(Query::AskListRooms, server_id);
+    }
+
+    /// ###### Sends a message to every member of a room.
+    /// The message enters the chat history of the room as pending and its status follows the ACKs of the server.
+    fn send_room_message(&mut self, room: RoomName, content: String) {
+        let Some(&server_id) = self.rooms.get(&room) else {
+            error!("Client {}: Failed to send message to room {}: not a member", self.id, room);
+            self.ui_response_send.send(crate::general_use::Response::Err(format!("Not a member of room {}", room))).unwrap();
+            return;
+        };
+
+        debug!("Client {}: Sending message to room {} via server {}", self.id, room, server_id);
+
+        let message_id = self.generate_message_id();
+        let message = RoomMessage::new(message_id, room.clone(), self.id, content.clone());
+
+        self.add_to_history(&Conversation::Room(room.clone()), message_id, Me, content, MessageStatus::Pending);
+
+        if let Err(err) = self.create_and_send_message(Query::SendRoomMessage(message), server_id) {
+            error!("Client {}: Failed to send message to room {}: {}", self.id, room, err);
+            if err != "Topology is empty. Discovery started and the query will be resent" {
+                self.update_message_status(Conversation::Room(room), message_id, MessageStatus::Failed);
+            }
+        }
+    }
+
+    /// ###### Sends a query about the rooms to a specified server.
+    fn send_room_query(&mut self, query: Query, server_id: ServerId) {
+        match self.create_and_send_message(query.clone(), server_id) {
+            Ok(_) => {
+                info!("Client {}: Query {:?} sent successfully.", self.id, query);
+            }
+            Err(err) => {
+                let error_string = format!("Client {}: Failed to send query {:?}: {}", self.id, query, err);
+
+                if err == "Topology is empty. Discovery started and the query will be resent" {
+                    warn!("{}", error_string);
+                } else {
+                    error!("{}", error_string);
+                }
+            },
+        }
+    }
+
     /// ###### Creates and sends a message to a specified server.
-    /// Serializes the data, splits it into fragments, and sends the first fragment.
+    /// Serializes the data, splits it into fragments, and sends the fragments of the first window.
     fn create_and_send_message(&mut self, query: Query, server_id: ServerId) -> Result<(), String> {
         debug!("Client {}: Creating and sending message to server {}: {:?}", self.id, server_id, query);
 
@@ -247,27 +440,48 @@ impl CommandHandler for ChatClientDanylo {
             return Err("Topology is empty. Discovery started and the query will be resent".to_string());
         }
 
-        // Find a route to the server or use the cached route if available.
-        let hops = if let Some(route) = self.routes.get(&server_id) {
-            route.clone()
-        } else if let Some(route) = self.find_route_to(server_id) {
-            self.routes.insert(server_id, route.clone());
-            route
-        } else {
+        // Use the best cached route, or look for routes in the topology if there is none.
+        if !self.routes.contains(server_id) {
+            self.routes.refresh(server_id, &self.topology);
+        }
+        let Some(hops) = self.routes.best(server_id).cloned() else {
             return Err(format!("No routes to the server with id {server_id}"));
         };
 
-        // Generate a new session ID.
-        let session_id = self.generate_session_id();
-        self.session_ids.push(session_id);
-
-        // Create message (split the query into fragments) and send first fragment.
-        let mut message = MessageFragments::new(session_id, hops);
-        if message.create_message_of(query) {
-            self.messages_to_send.insert(session_id, message.clone());
-            self.send_to_next_hop(message.get_fragment_packet(0).unwrap())
-        } else {
-            Err("Failed to create message.".to_string())
+        // Split the query into fragments in a new session and send the fragments of the first window.
+        let session = self.transport.send(&query, hops)?;
+        self.session_ids.push(session.session_id);
+        match &query {
+            Query::SendMessage(message) => {
+                let conversation = Conversation::Direct(message.get_recipient());
+                self.message_sessions.insert(session.session_id, (conversation, message.get_id()));
+            }
+            Query::SendRoomMessage(message) => {
+                let conversation = Conversation::Room(message.get_room().to_string());
+                self.message_sessions.insert(session.session_id, (conversation, message.get_id()));
+            }
+            _ => {}
         }
+        self.send_transport_packets()
+    }
+
+    /// ###### Sets the number of fragments that can wait for their ACK at the same time.
+    /// Messages already being sent keep their window.
+    fn set_window_size(&mut self, window_size: usize) {
+        self.transport.set_window_size(window_size);
+        info!("Client {}: Window size set to {}", self.id, self.transport.window_size());
+    }
+
+    /// ###### Handles the 'SetRediscoveryInterval' command.
+    fn set_rediscovery_interval(&mut self, interval: Option<Duration>) {
+        self.rediscovery.set_interval(interval);
+        info!("Client {}: Rediscovery interval set to {:?}", self.id, interval);
+    }
+
+    /// ###### Handles the 'SetEncryption' command.
+    /// Encrypted messages are always read, the setting only affects the messages sent.
+    fn set_encryption(&mut self, enabled: bool) {
+        self.encryption = enabled;
+        info!("Client {}: End-to-end encryption {}", self.id, if enabled { "enabled" } else { "disabled" });
     }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+use std::time::Instant;
+
+use log::{debug, error, info, warn};
+
+use crate::general_use::{ClientId, Conversation, Message, MessageContent, MessageStatus, Query, Response, ServerId};
+use super::{ChatClientDanylo, CommandHandler, Encryption, GeneratorId, History, ServerResponseHandler};
+use crate::clients::client_danylo::encryption::{KeyExchange, KEY_EXCHANGE_TIMEOUT, KEY_RESEND_INTERVAL};
+
+impl Encryption for ChatClientDanylo {
+    /// ###### Sends the public key of this client to a peer.
+    /// The key travels as a direct message, so the server forwards it like any other.
+    /// With `reply` the peer answers with its own public key.
+    fn send_public_key(&mut self, peer: ClientId, server_id: ServerId, reply: bool) {
+        let key = self.peer_keys.public_key(peer).to_vec();
+
+        // The secret must be on disk before the peer can use the public key
+        self.save_archive();
+
+        debug!("Client {}: Sending the public key to client {} via server {}", self.id, peer, server_id);
+
+        let message_id = self.generate_message_id();
+        let message = Message::new(message_id, self.id, peer, MessageContent::PublicKey { key, reply });
+
+        if let Err(err) = self.create_and_send_message(Query::SendMessage(message), server_id) {
+            error!("Client {}: Failed to send the public key to client {}: {}", self.id, peer, err);
+        }
+    }
+
+    /// ###### Handles the public key received from a peer.
+    /// Derives the shared key, answers with the own public key if asked
+    /// and sends the messages that were waiting for the exchange.
+    /// A key different from the pinned one is refused and shown to the user with its fingerprint.
+    fn handle_public_key(&mut self, peer: ClientId, key: &[u8], reply: bool, server_id: ServerId) {
+        match self.peer_keys.establish(self.id, peer, key) {
+            Ok(KeyExchange::Established) => {
+                info!("Client {}: Key shared with client {}", self.id, peer);
+                self.save_archive();
+            }
+            Ok(KeyExchange::Unchanged) => {
+                debug!("Client {}: Key shared with client {} unchanged", self.id, peer);
+            }
+            Ok(KeyExchange::Refused { pinned, received }) => {
+                let warning = format!(
+                    "The key of client {} changed from {} to {}, check the new fingerprint with the client before trusting it",
+                    peer, pinned, received
+                );
+                warn!("Client {}: {}", self.id, warning);
+                self.ui_response_send.send(Response::Err(warning)).unwrap();
+                return;
+            }
+            Err(err) => {
+                error!("Client {}: Key exchange with client {} failed: {}", self.id, peer, err);
+                return;
+            }
+        }
+
+        if reply {
+            self.send_public_key(peer, server_id, false);
+        }
+
+        self.send_waiting_messages(peer, server_id);
+    }
+
+    /// ###### Encrypts and sends the messages that were waiting for the key exchange with a peer.
+    fn send_waiting_messages(&mut self, peer: ClientId, server_id: ServerId) {
+        let Some(waiting) = self.waiting_for_key.remove(&peer) else {
+            return;
+        };
+
+        for (message_id, text, _) in waiting.messages {
+            match self.peer_keys.encrypt(message_id, self.id, peer, &text) {
+                Ok(content) => {
+                    self.send_direct_message(Message::new(message_id, self.id, peer, content), server_id);
+                }
+                Err(err) => {
+                    error!("Client {}: Failed to send message: {}", self.id, err);
+                    self.update_message_status(Conversation::Direct(peer), message_id, MessageStatus::Failed);
+                }
+            }
+        }
+    }
+
+    /// ###### Handles the 'TrustNewKey' command.
+    /// Pins the key the peer sent last, answers with the own public key and sends the messages waiting for it.
+    fn trust_new_key(&mut self, peer: ClientId) {
+        if let Err(err) = self.peer_keys.trust_refused_key(self.id, peer) {
+            error!("Client {}: Failed to trust the new key of client {}: {}", self.id, peer, err);
+            self.ui_response_send.send(Response::Err(err)).unwrap();
+            return;
+        }
+        info!("Client {}: New key of client {} trusted", self.id, peer);
+        self.save_archive();
+        self.ui_response_send.send(Response::KeyTrusted(peer)).unwrap();
+
+        if let Some(server_id) = self.server_of_client(peer) {
+            self.send_public_key(peer, server_id, false);
+            self.send_waiting_messages(peer, server_id);
+        }
+    }
+
+    /// ###### Marks as failed the messages that waited too long for the key exchange
+    /// and sends the public key again to the peers that did not answer yet.
+    fn check_key_exchanges(&mut self) {
+        let peers: Vec<ClientId> = self.waiting_for_key.keys().copied().collect();
+
+        for peer in peers {
+            let Some(waiting) = self.waiting_for_key.get_mut(&peer) else {
+                continue;
+            };
+            let expired = waiting.take_expired(KEY_EXCHANGE_TIMEOUT);
+            let resend = !waiting.messages.is_empty() && waiting.key_sent.elapsed() >= KEY_RESEND_INTERVAL;
+            if waiting.messages.is_empty() {
+                self.waiting_for_key.remove(&peer);
+            }
+
+            for message_id in expired {
+                warn!("Client {}: No key from client {} in time, message {} failed", self.id, peer, message_id);
+                self.update_message_status(Conversation::Direct(peer), message_id, MessageStatus::Failed);
+            }
+
+            if resend {
+                if let Some(waiting) = self.waiting_for_key.get_mut(&peer) {
+                    waiting.key_sent = Instant::now();
+                }
+                match self.server_of_client(peer) {
+                    Some(server_id) => self.send_public_key(peer, server_id, true),
+                    None => debug!("Client {}: No server of client {} to send the key again", self.id, peer),
+                }
+            }
+        }
+    }
+}
@@ -1,25 +1,25 @@
-use crate::general_use::{FloodId, SessionId};
+use crate::general_use::{FloodId, MessageId, SessionId};
+use crate::ids::unique_id;
 use super::{GeneratorId, ChatClientDanylo};
 
 impl GeneratorId for ChatClientDanylo {
     /// ###### Generates a new session ID.
+    /// The transport owns the session counter, so flood sessions never collide with message sessions.
     fn generate_session_id(&mut self) -> SessionId {
-        self.session_id_counter += 1;
-        let next_session_id: SessionId = self.session_id_counter;
-        self.parse_id(next_session_id)
+        self.transport.new_session_id()
     }
 
     /// ###### Generates a new flood ID.
+    /// The client ID fills the high bits, so the flood IDs of two nodes never collide.
     fn generate_flood_id(&mut self) -> FloodId {
         self.flood_id_counter += 1;
-        let next_flood_id: FloodId = self.flood_id_counter;
-        self.parse_id(next_flood_id)
+        unique_id(self.id, self.flood_id_counter)
     }
 
-    /// ###### Parses the ID by concatenating the client ID and the provided ID.
-    fn parse_id(&self, id: u64) -> u64 {
-        format!("{}{}", self.id, id)
-            .parse()
-            .unwrap()
+    /// ###### Generates a new chat message ID.
+    /// The client ID fills the high bits, so the recipient can tell apart the messages of different senders.
+    fn generate_message_id(&mut self) -> MessageId {
+        self.message_id_counter += 1;
+        unique_id(self.id, self.message_id_counter)
     }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+use std::time::Instant;
+use log::{error, info};
+
+use crate::clients::client_danylo::archive::{export_conversation, ArchiveView, ARCHIVE_SAVE_INTERVAL};
+use crate::general_use::{unix_time, ChatMessage, Conversation, MessageId, MessageStatus, Response, Speaker};
+use super::{ChatClientDanylo, History, ServerResponseHandler};
+
+impl History for ChatClientDanylo {
+    /// ###### Adds a message to the chat history of the conversation, the archive is saved on the tick.
+    fn add_to_history(&mut self, conversation: &Conversation, id: MessageId, speaker: Speaker, content: String, status: MessageStatus) {
+        let chat = self.chat_history(conversation);
+        chat.push(ChatMessage { id, speaker, content, status, timestamp: unix_time() });
+
+        self.archive_changed();
+    }
+
+    /// ###### Handles the 'ExportConversation' command.
+    /// Writes the chat history of the conversation to a text file, the UI gets the result.
+    fn export_conversation(&mut self, conversation: Conversation, path: PathBuf) {
+        let history = match &conversation {
+            Conversation::Direct(peer) => self.chats.get(peer),
+            Conversation::Room(room) => self.room_chats.get(room),
+        };
+        let Some(history) = history else {
+            error!("Client {}: Failed to export the chat: no chat with {}", self.id, conversation);
+            self.ui_response_send.send(Response::Err(format!("No chat with {}", conversation))).unwrap();
+            return;
+        };
+
+        match export_conversation(&conversation, history, &path) {
+            Ok(()) => {
+                info!("Client {}: Chat with {} exported to {}", self.id, conversation, path.display());
+                self.ui_response_send.send(Response::ConversationExported(conversation, path)).unwrap();
+            }
+            Err(err) => {
+                error!("Client {}: Failed to export the chat with {}: {}", self.id, conversation, err);
+                self.ui_response_send.send(Response::Err(err)).unwrap();
+            }
+        }
+    }
+
+    /// ###### Marks the archive to be saved on the next tick.
+    /// The changes of a burst of messages are written together instead of rewriting the file for each one.
+    fn archive_changed(&mut self) {
+        self.archive_changed = true;
+    }
+
+    /// ###### Saves the archive if it changed and the last save is old enough, see `ARCHIVE_SAVE_INTERVAL`.
+    fn save_archive_if_due(&mut self) {
+        if self.archive_changed && self.archive_saved.elapsed() >= ARCHIVE_SAVE_INTERVAL {
+            self.save_archive();
+        }
+    }
+
+    /// ###### Saves the chat histories and the keys kept between runs right away.
+    /// Used when the data must be on disk before going on, e.g. a new secret key before its public key is sent.
+    fn save_archive(&mut self) {
+        let archive = ArchiveView {
+            keys: &self.peer_keys,
+            chats: &self.chats,
+            room_chats: &self.room_chats,
+            message_id_counter: self.message_id_counter,
+        };
+
+        match archive.save(&self.archive_path) {
+            Ok(()) => {
+                self.archive_changed = false;
+                self.archive_saved = Instant::now();
+            }
+            Err(err) => error!("Client {}: Failed to save the archive: {}", self.id, err),
+        }
+    }
+}
@@ -1,28 +1,25 @@
-use std::collections::{HashSet, VecDeque};
 use log::{debug, error, info};
 
 use wg_2024::{
     packet::{Fragment, Nack, NackType, Packet, PacketType, FloodRequest, NodeType, FloodResponse},
-    network::NodeId,
+    network::{NodeId, SourceRoutingHeader},
 };
 
-use crate::general_use::{FragmentIndex, ServerId, ServerType, SessionId, Node};
-use super::{PacketHandler, ChatClientDanylo, Senders, ServerResponseHandler, Reassembler, CommandHandler};
+use crate::general_use::{FragmentIndex, MessageStatus, Response, ServerType, SessionId, Node};
+use crate::transport::{deserialize, Reassembly};
+use super::{PacketHandler, ChatClientDanylo, Senders, ServerResponseHandler, CommandHandler};
 
 impl PacketHandler for ChatClientDanylo {
     /// ###### Handles incoming packets and delegates them to the appropriate handler based on the packet type.
     fn handle_packet(&mut self, packet: Packet) {
         match packet.pack_type.clone() {
             PacketType::Ack(ack) => self.handle_ack(ack.fragment_index, packet.session_id),
-            PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-            PacketType::MsgFragment(fragment) => {
-                // Send acknowledgment for the received fragment
-                self.send_ack(fragment.fragment_index, packet.session_id, packet.routing_header.clone());
-
-                // Get the server ID from the routing header and handle the fragment
-                let server_id = packet.routing_header.hops.first().unwrap();
-                self.handle_fragment(fragment, packet.session_id, *server_id)
+            PacketType::Nack(nack) => {
+                // The NACK starts at the node that sent it
+                let source = packet.routing_header.hops.first().copied().unwrap_or_default();
+                self.handle_nack(nack, packet.session_id, source)
             }
+            PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.session_id, packet.routing_header),
             PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
             PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
         }
@@ -30,33 +27,40 @@ impl PacketHandler for ChatClientDanylo {
 
     /// ###### Handles the acknowledgment (ACK) for a given session and fragment.
     /// Processes the acknowledgment for a specific fragment in a session.
-    /// If there are more fragments to send, it sends the next fragment.
-    /// If all fragments are acknowledged, it removes the message from queue.
+    /// The window slides forward and the fragments entering it are sent.
+    /// Duplicate and late ACKs are ignored by the transport.
     fn handle_ack(&mut self, fragment_index: FragmentIndex, session_id: SessionId) {
         debug!("Client {}: Handling ACK for session {} and fragment {}", self.id, session_id, fragment_index);
 
-        // Retrieve the message fragments for the given session.
-        let message = self.messages_to_send.get_mut(&session_id).unwrap();
+        // Every drone of the route forwarded the fragment.
+        if let Some(route) = self.transport.route(session_id) {
+            self.routes.record_delivered(route);
+        }
 
-        // Check if there is a next fragment to send.
-        if let Some(next_fragment) = message.get_fragment_packet((fragment_index + 1) as usize) {
-            // Prepare and send the next fragment if available.
-            match self.send_to_next_hop(next_fragment) {
-                Ok(_) => info!("Client {}: Sent next fragment for session {}", self.id, session_id),
-                Err(err) => error!("Client {}: Failed to send next fragment for session {}: {}", self.id, session_id, err),
-            }
-        } else {
-            // All fragments are acknowledged; remove the message from queue.
-            self.messages_to_send.remove(&session_id);
+        if self.transport.handle_ack(session_id, fragment_index).is_some() {
             info!("Client {}: All fragments acknowledged for session {}", self.id, session_id);
+            if let Some((conversation, message_id)) = self.message_sessions.remove(&session_id) {
+                self.update_message_status(conversation, message_id, MessageStatus::DeliveredToServer);
+            }
+            return;
+        }
+
+        // Send the fragments that entered the window.
+        if let Err(err) = self.send_transport_packets() {
+            error!("Client {}: Failed to send next fragment for session {}: {}", self.id, session_id, err);
         }
     }
 
     /// ###### Handles the negative acknowledgment (NACK) for a given session.
     /// Processes the NACK for a specific session and takes appropriate action based on the NACK type.
-    fn handle_nack(&mut self, nack: Nack, session_id: SessionId) {
+    fn handle_nack(&mut self, nack: Nack, session_id: SessionId, source: NodeId) {
         debug!("Client {}: Handling NACK for session {}: {:?}", self.id, session_id, nack);
 
+        // The message may have been completed or abandoned in the meantime.
+        if self.transport.destination(session_id).is_none() {
+            return;
+        }
+
         match nack.nack_type {
             NackType::ErrorInRouting(id) => {
                 self.update_topology_and_routes(id);
@@ -68,87 +72,42 @@ impl PacketHandler for ChatClientDanylo {
                 self.update_message_route_and_resend(nack.fragment_index, session_id);
             }
             NackType::Dropped => {
+                // The drone that dropped the fragment is the source of the NACK.
+                if let Some(route) = self.transport.route(session_id) {
+                    self.routes.record_dropped(route, source);
+                }
                 self.resend_fragment(nack.fragment_index, session_id);
             }
         }
     }
 
     /// ###### Updates the network topology and routes.
-    /// Removes the node that caused the error from the topology and routes.
-    /// Finds new routes for the servers that need them.
+    /// Removes the node that caused the error from the topology and drops the routes through it,
+    /// so that the next route to every server takes over.
+    /// Finds new routes for the servers left without one.
     fn update_topology_and_routes(&mut self, error_node: NodeId) {
         // Remove the node that caused the error from the topology.
-        for (_, neighbors) in self.topology.iter_mut() {
-            neighbors.remove(&error_node);
-        }
-        self.topology.remove(&error_node);
+        self.topology.remove_node(error_node);
         debug!("Client {}: Removed node {} from the topology", self.id, error_node);
 
-        // Replace the paths that contain the node that caused the error with an empty vector.
-        for route in self.routes.values_mut() {
-            if route.contains(&error_node) {
-                *route = Vec::new();
-            }
-        }
-        debug!("Client {}: Routes with node {} cleared", self.id, error_node);
-
-        // Collect server IDs that need new routes.
-        let servers_to_update: Vec<ServerId> = self
-            .routes
-            .iter()
-            .filter(|(_, path)| path.is_empty())
-            .map(|(server_id, _)| *server_id)
-            .collect();
+        // Drop the routes that contain the node that caused the error.
+        let servers_to_update = self.routes.remove_node(error_node);
+        debug!("Client {}: Routes with node {} dropped", self.id, error_node);
 
-        // Find new routes for the collected server IDs.
+        // Find new routes for the servers left without one.
         for server_id in servers_to_update {
-            if let Some(new_path) = self.find_route_to(server_id) {
-                if let Some(path) = self.routes.get_mut(&server_id) {
-                    *path = new_path;
-                    debug!("Client {}: Found new route to the server {}: {:?}", self.id, server_id, path);
-                }
+            if self.routes.refresh(server_id, &self.topology) {
+                debug!("Client {}: Found new routes to the server {}: {:?}", self.id, server_id, self.routes.routes(server_id));
             } else {
-                error!("Client {}: No route found to the server {}", self.id, server_id);
+                error!("Client {}: No route found to the server {}, requesting a new discovery", self.id, server_id);
+                self.rediscovery.request();
             }
         }
     }
 
-    /// ###### Finds a route from the current node to the specified server using breadth-first search.
-    ///
-    /// This method explores the network topology starting from the current node, and returns the shortest path
-    /// (in terms of hops) to the specified server if one exists. It uses a queue to explore nodes level by level,
-    /// ensuring that the first valid path found is the shortest. If no path is found, it returns `None`.
-    fn find_route_to(&self, server_id: ServerId) -> Option<Vec<NodeId>> {
-        // Initialize a queue for breadth-first search and a set to track visited nodes.
-        let mut queue: VecDeque<(NodeId, Vec<NodeId>)> = VecDeque::new();
-        let mut visited: HashSet<NodeId> = HashSet::new();
-
-        // Start from the current node with an initial path containing just the current node.
-        queue.push_back((self.id, vec![self.id]));
-
-        // Perform breadth-first search.
-        while let Some((current, path)) = queue.pop_front() {
-            // If the destination node is reached, return the path.
-            if current == server_id {
-                return Some(path);
-            }
-
-            // Mark the current node as visited.
-            visited.insert(current);
-
-            // Explore the neighbors of the current node.
-            if let Some(neighbors) = self.topology.get(&current) {
-                for &neighbor in neighbors {
-                    // Only visit unvisited neighbors.
-                    if !visited.contains(&neighbor) {
-                        let mut new_path = path.clone();
-                        new_path.push(neighbor); // Extend the path to include the neighbor.
-                        queue.push_back((neighbor, new_path)); // Add the neighbor to the queue.
-                    }
-                }
-            }
-        }
-        None    // Return None if no path to the server is found.
+    /// ###### Rebuilds the alternative routes to the servers from the topology, when it is time to.
+    fn refresh_routes(&mut self) {
+        self.routes.refresh_if_due(&self.topology);
     }
 
     /// ###### Updates the message route and resends the fragment if possible.
@@ -158,7 +117,9 @@ impl PacketHandler for ChatClientDanylo {
                 self.resend_fragment(fragment_index, session_id);
             }
             Err(err) => {
+                // The retransmission timer resends the fragment once the discovery found a route.
                 error!("Client {}: Impossible to resend fragment: {}", self.id, err);
+                self.rediscovery.request();
             }
         }
     }
@@ -167,41 +128,62 @@ impl PacketHandler for ChatClientDanylo {
     /// If a new route is found, it updates the message with the new route.
     /// If no route is found, it returns an error message.
     fn update_message_route(&mut self, session_id: &SessionId) -> Result<(), String> {
-        let message = self.messages_to_send.get_mut(session_id).unwrap();
-        let dest_id = message.get_route().last().unwrap();
-
-        if let Some(new_route) = self.routes.get(dest_id) {
-            message.update_route(new_route.clone());
-            Ok(())
-        } else {
-            Err(format!("No routes to the server {}", dest_id))
+        let Some(dest_id) = self.transport.destination(*session_id) else {
+            return Err(format!("No message queued for session {}", session_id));
+        };
+
+        match self.routes.best(dest_id) {
+            Some(new_route) => {
+                self.transport.set_route(*session_id, new_route.clone());
+                Ok(())
+            }
+            _ => Err(format!("No routes to the server {}", dest_id)),
         }
     }
 
     /// ###### Handles received message fragment.
-    /// Adds the fragment to the collection for the session and checks if it is the last fragment.
-    /// If it is the last fragment, reassembles the message and processes the server response.
-    fn handle_fragment(&mut self, fragment: Fragment, session_id: SessionId, server_id: ServerId) {
+    /// The transport stores the fragment, ignoring duplicates and fragments of already complete messages,
+    /// and every fragment it does not discard as malformed is acknowledged.
+    /// Once every fragment is present, the message is deserialized and the server response is processed.
+    fn handle_fragment(&mut self, fragment: Fragment, session_id: SessionId, routing_header: SourceRoutingHeader) {
         debug!("Client {}: Handling fragment for session {}: {:?}", self.id, session_id, fragment);
 
-        // Retrieve or create a vector to store fragments for the session.
-        let fragments = self.fragments_to_reassemble.entry(session_id).or_insert_with(Vec::new);
+        let Some(&server_id) = routing_header.hops.first() else {
+            error!("Client {}: Fragment of session {} without a route", self.id, session_id);
+            return;
+        };
+        let fragment_index = fragment.fragment_index;
+        let reassembly = self.transport.receive(server_id, session_id, fragment);
 
-        // Add the current fragment to the collection.
-        fragments.push(fragment.clone());
+        if reassembly != Reassembly::Discarded {
+            self.send_ack(fragment_index, session_id, routing_header);
+        }
 
-        // Check if the current fragment is the last one in the sequence.
-        if fragment.fragment_index == fragment.total_n_fragments - 1 {
-            // Reassemble the fragments into a complete message and process it.
-            let message = self.reassemble(session_id);
-            self.handle_server_response(message, server_id);
+        match reassembly {
+            Reassembly::Complete(data) => {
+                let response = match deserialize::<Response>(&data) {
+                    Ok(response) => Some(response),
+                    Err(err) => {
+                        error!("Client {}: Failed to reassemble session {}: {}", self.id, session_id, err);
+                        None
+                    }
+                };
+                self.handle_server_response(response, server_id);
+            }
+            Reassembly::Pending | Reassembly::Ignored | Reassembly::Discarded => {}
         }
     }
 
     /// ###### Handles a flood request by adding the client to the path trace and generating a response.
+    /// The client is a leaf: it never forwards the flood, so it answers every copy,
+    /// and the initiator learns every link into the client.
     fn handle_flood_request(&mut self, mut flood_request: FloodRequest, session_id: SessionId) {
         debug!("Client {}: Handling flood request for session {}: {:?}", self.id, session_id, flood_request);
 
+        if !self.seen_floods.first_time(flood_request.initiator_id, flood_request.flood_id) {
+            debug!("Client {}: Flood {} of node {} received again, through another path", self.id, flood_request.flood_id, flood_request.initiator_id);
+        }
+
         // Add client to the flood request's path trace.
         flood_request.increment(self.id, NodeType::Client);
 
@@ -229,36 +211,22 @@ impl PacketHandler for ChatClientDanylo {
     }
 
     /// ###### Updates the network topology based on the provided path.
-    /// Adds connections between nodes in both directions.
+    /// Adds connections between nodes in both directions, and the type of every node.
     fn update_topology(&mut self, path: &[Node]) {
-        for i in 0..path.len() - 1 {
-            let current = path[i].0;
-            let next = path[i + 1].0;
-
-            // Add the connection between the current and next node in both directions.
-            self.topology
-                .entry(current)
-                .or_insert_with(HashSet::new)
-                .insert(next);
-
-            self.topology
-                .entry(next)
-                .or_insert_with(HashSet::new)
-                .insert(current);
-        }
+        self.topology.add_path(path);
         info!("Client {}: Updated topology with path: {:?}", self.id, path);
     }
 
     /// ###### Updates the routes and servers based on the provided path.
-    /// If the path leads to a server, it updates the routing table and the servers list.
-    /// If there are queries waiting for the route to the server, it resends them.
+    /// If the path leads to a server, it is kept as a route to the server.
+    /// When it becomes the best route, it updates the servers list
+    /// and resends the queries waiting for the route to the server.
     fn update_routes_and_servers(&mut self, path: &[Node]) {
         if let Some((id, NodeType::Server)) = path.last() {
-            if self
-                .routes
-                .get(id)
-                .map_or(true, |prev_path| prev_path.len() > path.len())
-            {
+            let route = path.iter().map(|entry| entry.0).collect();
+            if self.routes.add(*id, route) {
+                info!("Client {}: Updated route to server {}: {:?}", self.id, id, path);
+
                 // Add the server to the servers list with an undefined type if it is not already present.
                 if !self.servers.contains_key(id) {
                     self.servers.insert(*id, ServerType::Undefined);
@@ -269,13 +237,6 @@ impl PacketHandler for ChatClientDanylo {
                     self.request_server_type(*id);
                 }
 
-                // Update the routing table with the new, shorter path.
-                self.routes.insert(
-                    *id,
-                    path.iter().map(|entry| entry.0.clone()).collect(),
-                );
-                info!("Client {}: Updated route to server {}: {:?}", self.id, id, path);
-
                 // Resend queries that were waiting for the route to the server.
                 if !self.queries_to_resend.is_empty() && self.queries_to_resend.front().unwrap().0 == *id {
                     self.resend_queries();
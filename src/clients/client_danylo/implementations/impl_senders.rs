@@ -5,8 +5,8 @@ use wg_2024::{
     network::SourceRoutingHeader,
 };
 
-use crate::general_use::{ClientEvent, FragmentIndex, SessionId};
-use super::{Senders, ChatClientDanylo, CommandHandler};
+use crate::general_use::{ClientEvent, FragmentIndex, MessageStatus, SessionId};
+use super::{Senders, ChatClientDanylo, CommandHandler, ServerResponseHandler};
 
 impl Senders for ChatClientDanylo {
     /// ###### Sends the packet to the next hop in the route.
@@ -44,6 +44,19 @@ impl Senders for ChatClientDanylo {
         }
     }
 
+    /// ###### Sends the packets queued by the transport.
+    /// Every packet is sent even if one fails: the retransmission timers of the transport retry the failed ones.
+    /// Returns the last error, if any.
+    fn send_transport_packets(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        for packet in self.transport.take_packets() {
+            if let Err(err) = self.send_to_next_hop(packet) {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
     /// ###### Sends an acknowledgment (ACK) for a received fragment.
     /// Creates an ACK packet and sends it to the next hop.
     fn send_ack(&mut self, fragment_index: FragmentIndex, session_id: SessionId, mut routing_header: SourceRoutingHeader) {
@@ -71,6 +84,8 @@ impl Senders for ChatClientDanylo {
             ClientEvent::PacketSent(_) => "PacketSent",
             ClientEvent::KnownServers(_) => "KnownServers",
             ClientEvent::ChatClientData(_, _, _) => "ChatClientData",
+            ClientEvent::SessionFailed(_, _, _) => "SessionFailed",
+            ClientEvent::RouteCosts(_, _, _) => "RouteCosts",
             _ => "Unknown",
         };
 
@@ -81,13 +96,15 @@ impl Senders for ChatClientDanylo {
     }
 
     /// ###### Resends the fragment for the specified session.
-    /// Retrieves the message and resends the fragment with the specified index.
+    /// The transport queues the fragment again, on the current route of the message.
     fn resend_fragment(&mut self, fragment_index: FragmentIndex, session_id: SessionId) {
         debug!("Client {}: Resending fragment {} for session {}", self.id, fragment_index, session_id);
 
-        let message = self.messages_to_send.get(&session_id).unwrap();
-        let packet = message.get_fragment_packet(fragment_index as usize).unwrap();
-        match self.send_to_next_hop(packet) {
+        if !self.transport.resend(session_id, fragment_index) {
+            debug!("Client {}: Fragment {} for session {} is no longer queued", self.id, fragment_index, session_id);
+            return;
+        }
+        match self.send_transport_packets() {
             Ok(_) =>
                 info!("Client {}: Resent fragment {} for session {}", self.id, fragment_index, session_id),
             Err(err) =>
@@ -95,6 +112,23 @@ impl Senders for ChatClientDanylo {
         }
     }
 
+    /// ###### Resends the fragments whose ACK did not arrive in time.
+    /// When a fragment runs out of retries, its message is dropped and the failure is reported to the controller.
+    fn resend_expired_fragments(&mut self) {
+        for failed in self.transport.check_timers() {
+            error!("Client {}: Session {} to node {} failed: no ACK after every retry",
+                self.id, failed.session_id, failed.destination);
+            self.send_event(ClientEvent::SessionFailed(self.id, failed.session_id, failed.destination));
+            if let Some((conversation, message_id)) = self.message_sessions.remove(&failed.session_id) {
+                self.update_message_status(conversation, message_id, MessageStatus::Failed);
+            }
+        }
+
+        if let Err(err) = self.send_transport_packets() {
+            error!("Client {}: Failed to resend expired fragments: {}", self.id, err);
+        }
+    }
+
     /// ###### Resends queries that were waiting for the route to the server.
     /// Iterates over the queries to resend and sends them to the corresponding servers.
     /// If the server does not have a route, the query is not resent.
@@ -104,7 +138,7 @@ impl Senders for ChatClientDanylo {
 
         for (server_id, query) in queries {
 
-            if !self.routes.contains_key(&server_id) {
+            if !self.routes.contains(server_id) {
                 return;
             }
 
@@ -1,6 +1,9 @@
 use log::{debug, error, info};
-use crate::clients::client_danylo::chat_client_traits::CommandHandler;
-use crate::general_use::{ClientId, Message, Response, ServerId, ServerType, Speaker::HimOrHer};
+use crate::clients::client_danylo::chat_client_traits::{CommandHandler, Encryption, History};
+use crate::general_use::{
+    ChatHistory, ClientId, Conversation, Message, MessageContent, MessageId, MessageStatus, Presence, Query,
+    Response, RoomMessage, RoomName, ServerId, ServerType, Speaker::{HimOrHer, Member}
+};
 use super::{ServerResponseHandler, ChatClientDanylo};
 
 impl ServerResponseHandler for ChatClientDanylo {
@@ -17,14 +20,37 @@ impl ServerResponseHandler for ChatClientDanylo {
                 Response::ClientRegistered => {
                     self.handle_client_registered(server_id);
                 }
+                Response::ClientUnregistered => {
+                    self.handle_client_unregistered(server_id);
+                }
+                Response::PresenceChanged(client_id, presence) => {
+                    self.handle_presence_changed(server_id, client_id, presence);
+                }
                 Response::ListClients(clients) => {
                     self.handle_clients_list(server_id, clients);
                 }
                 Response::MessageReceived(message) => {
-                    self.handle_message(message);
+                    self.handle_message(message, server_id);
+                }
+                Response::MessageDelivered(message_id, recipient) => {
+                    self.handle_message_delivered(message_id, recipient);
+                }
+                Response::ListRooms(rooms) => {
+                    self.handle_rooms_list(rooms);
+                }
+                Response::RoomMessageReceived(message) => {
+                    self.handle_room_message(message);
+                }
+                Response::JoinedRoom(room, client_id) => {
+                    self.handle_joined_room(server_id, room, client_id);
+                }
+                Response::LeftRoom(room, client_id) => {
+                    self.handle_left_room(room, client_id);
+                }
+                Response::Err(error) => {
+                    error!("Client {}: Error received from server {}: {:?}", self.id, server_id, error);
+                    self.ui_response_send.send(Response::Err(error)).unwrap();
                 }
-                Response::Err(error) =>
-                    error!("Client {}: Error received from server {}: {:?}", self.id, server_id, error),
                 _ => {}
             }
         }
@@ -60,6 +86,36 @@ impl ServerResponseHandler for ChatClientDanylo {
         self.ui_response_send.send(Response::ClientRegistered).unwrap();
     }
 
+    /// ###### Handles the client unregistration response.
+    /// Forgets the clients and the rooms of the server.
+    fn handle_client_unregistered(&mut self, server_id: ServerId) {
+        info!("Client {}: Client unregistered successfully.", self.id);
+
+        self.is_registered.insert(server_id, false);
+        self.clients.remove(&server_id);
+        self.rooms.retain(|_, room_server_id| *room_server_id != server_id);
+
+        self.ui_response_send.send(Response::ClientUnregistered).unwrap();
+    }
+
+    /// ###### Handles the presence change of another client of the server.
+    /// Keeps the list of available clients current: registered clients are added, unregistered ones removed.
+    fn handle_presence_changed(&mut self, server_id: ServerId, client_id: ClientId, presence: Presence) {
+        info!("Client {}: Client {} on server {} is {}", self.id, client_id, server_id, presence);
+
+        self.presence.insert(client_id, presence);
+
+        let clients = self.clients.entry(server_id).or_default();
+        match presence {
+            Presence::Unregistered(_) => clients.retain(|&id| id != client_id),
+            Presence::Online | Presence::LastSeen(_) => {
+                if client_id != self.id && !clients.contains(&client_id) {
+                    clients.push(client_id);
+                }
+            }
+        }
+    }
+
     /// ###### Handles the list of clients received from the server.
     /// Updates the list of available clients.
     fn handle_clients_list(&mut self, server_id: ServerId, mut clients: Vec<ClientId>) {
@@ -76,13 +132,126 @@ impl ServerResponseHandler for ChatClientDanylo {
     }
 
     /// ###### Handles the message received from another client.
-    /// Adds the message to the chat history with the sender.
-    fn handle_message(&mut self, message: Message) {
-        info!("Client {}: New message from {}: {:?}", self.id, message.get_sender(), message.get_content());
+    /// Adds the message to the chat history with the sender
+    /// and sends the delivery receipt back through the same server.
+    /// Encrypted messages are decrypted first, key exchanges never reach the chat history.
+    fn handle_message(&mut self, message: Message, server_id: ServerId) {
+        info!("Client {}: New message from {}: {}", self.id, message.get_sender(), message.get_content());
+
+        let (message_id, sender) = (message.get_id(), message.get_sender());
+        let text = match message.get_content() {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Encrypted { nonce, ciphertext } => {
+                match self.peer_keys.decrypt(message_id, sender, self.id, nonce, ciphertext) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        // The keys of the two clients differ, e.g. one of them lost its archive
+                        error!("Client {}: {}, exchanging the keys again", self.id, err);
+                        self.send_public_key(sender, server_id, true);
+                        return;
+                    }
+                }
+            }
+            MessageContent::PublicKey { key, reply } => {
+                self.handle_public_key(sender, key, *reply, server_id);
+                return;
+            }
+        };
+
+        // The UI gets the message readable
+        let readable = Message::new(message_id, sender, self.id, MessageContent::Text(text.clone()));
+        self.ui_response_send.send(Response::MessageReceived(readable)).unwrap();
+
+        self.add_to_history(&Conversation::Direct(sender), message_id, HimOrHer, text, MessageStatus::DeliveredToPeer);
+
+        let receipt = Query::MessageDelivered(message.get_id(), message.get_sender());
+        if let Err(err) = self.create_and_send_message(receipt, server_id) {
+            error!("Client {}: Failed to send the receipt of message {}: {}", self.id, message.get_id(), err);
+        }
+    }
+
+    /// ###### Handles the receipt of a message sent to another client.
+    fn handle_message_delivered(&mut self, message_id: MessageId, recipient: ClientId) {
+        info!("Client {}: Message {} delivered to client {}", self.id, message_id, recipient);
+
+        self.update_message_status(Conversation::Direct(recipient), message_id, MessageStatus::DeliveredToPeer);
+    }
+
+    /// ###### Handles the list of rooms received from the server.
+    fn handle_rooms_list(&mut self, rooms: Vec<RoomName>) {
+        info!("Client {}: List of rooms received successfully.", self.id);
+
+        self.ui_response_send.send(Response::ListRooms(rooms)).unwrap();
+    }
+
+    /// ###### Handles the message sent to a room by another member.
+    /// Adds the message to the chat history of the room.
+    fn handle_room_message(&mut self, message: RoomMessage) {
+        info!("Client {}: New message in room {} from {}: {:?}",
+            self.id, message.get_room(), message.get_sender(), message.get_content());
+
+        self.add_to_history(
+            &Conversation::Room(message.get_room().to_string()),
+            message.get_id(),
+            Member(message.get_sender()),
+            message.get_content().to_string(),
+            MessageStatus::DeliveredToPeer,
+        );
+
+        self.ui_response_send.send(Response::RoomMessageReceived(message)).unwrap();
+    }
+
+    /// ###### Handles the notice of a client joining a room.
+    /// When the client itself joined, the room is remembered with its server.
+    fn handle_joined_room(&mut self, server_id: ServerId, room: RoomName, client_id: ClientId) {
+        info!("Client {}: Client {} joined room {}", self.id, client_id, room);
 
-        self.ui_response_send.send(Response::MessageReceived(message.clone())).unwrap();
+        if client_id == self.id {
+            self.rooms.insert(room.clone(), server_id);
+            self.chat_history(&Conversation::Room(room.clone()));
+        }
+
+        self.ui_response_send.send(Response::JoinedRoom(room, client_id)).unwrap();
+    }
+
+    /// ###### Handles the notice of a client leaving a room.
+    /// When the client itself left, the room is forgotten but its chat history is kept.
+    fn handle_left_room(&mut self, room: RoomName, client_id: ClientId) {
+        info!("Client {}: Client {} left room {}", self.id, client_id, room);
 
-        let chat = self.chats.entry(message.get_sender()).or_insert_with(Vec::new);
-        chat.push((HimOrHer, message.get_content().to_string()));
+        if client_id == self.id {
+            self.rooms.remove(&room);
+        }
+
+        self.ui_response_send.send(Response::LeftRoom(room, client_id)).unwrap();
+    }
+
+    /// ###### Updates the status of a message sent to a peer or a room and shows it in the UI.
+    /// A message delivered to the peer keeps its status, even if the last ACK of the server arrives later.
+    fn update_message_status(&mut self, conversation: Conversation, message_id: MessageId, status: MessageStatus) {
+        let chat = match &conversation {
+            Conversation::Direct(peer) => self.chats.get_mut(peer),
+            Conversation::Room(room) => self.room_chats.get_mut(room),
+        };
+        let Some(chat_message) = chat
+            .and_then(|chat| chat.iter_mut().find(|chat_message| chat_message.id == message_id))
+        else {
+            return;
+        };
+        if chat_message.status == MessageStatus::DeliveredToPeer {
+            return;
+        }
+        chat_message.status = status;
+        self.archive_changed();
+
+        self.ui_response_send.send(Response::MessageStatus(conversation, message_id, status)).unwrap();
+    }
+
+    /// ###### Returns the chat history of the conversation, direct chats and rooms are kept apart.
+    fn chat_history(&mut self, conversation: &Conversation) -> &mut ChatHistory {
+        match conversation {
+            Conversation::Direct(peer) => self.chats.entry(*peer).or_default(),
+            Conversation::Room(room) => self.room_chats.entry(room.clone()).or_default(),
+        }
     }
 }
\ No newline at end of file
@@ -1,10 +1,10 @@
 mod impl_packet_handler;
 mod impl_command_handler;
-mod impl_reassembler;
 mod impl_generator_id;
 mod impl_senders;
 mod impl_server_response_handler;
+mod impl_encryption;
+mod impl_history;
 
 use super::chat_client_traits::*;
-use super::ChatClientDanylo;
-use super::MessageFragments;
\ No newline at end of file
+use super::ChatClientDanylo;
\ No newline at end of file
@@ -1,8 +1,8 @@
 pub mod client_danylo;
-mod message_fragments;
 mod chat_client_traits;
 mod implementations;
+mod encryption;
+mod archive;
 
 pub use client_danylo::*;
-use message_fragments::MessageFragments;
 use chat_client_traits::*;
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{Receiver, Sender};
+use wg_2024::{network::NodeId, packet::Packet};
+
+use crate::clients::{client_chen::ClientChen, client_danylo::ChatClientDanylo, Client};
+use crate::general_use::{ClientCommand, ClientEvent, ClientType, Response};
+
+/// Spawns the thread of a client with the given ID and channels.
+pub type ClientSpawner = fn(
+    NodeId,
+    Receiver<Packet>,
+    Sender<ClientEvent>,
+    Receiver<ClientCommand>,
+    Sender<Response>,
+) -> JoinHandle<()>;
+
+/// ###### Registry of the client implementations that can be named in the topology file.
+/// Every kind is registered under a name together with the `ClientType` it speaks,
+/// so the controller knows which commands the client understands.
+///
+/// ```ignore
+/// let mut network = NetworkInit::new();
+/// network.client_factory().register::<MyClient>("my_client", ClientType::Chat);
+/// // [[client]] entries of the topology can now use client_type = "my_client"
+/// ```
+pub struct ClientFactory {
+    kinds: HashMap<String, (ClientType, ClientSpawner)>,
+}
+
+impl Default for ClientFactory {
+    /// ###### Creates a factory with the built-in clients: `chat` and `web`.
+    fn default() -> Self {
+        let mut factory = Self::empty();
+        factory.register::<ChatClientDanylo>("chat", ClientType::Chat);
+        factory.register::<ClientChen>("web", ClientType::Web);
+        factory
+    }
+}
+
+impl ClientFactory {
+    /// ###### Creates a factory without any registered kind.
+    pub fn empty() -> Self {
+        Self {
+            kinds: HashMap::new(),
+        }
+    }
+
+    /// ###### Registers a `Client` implementation under the given kind name.
+    /// Kind names are case-insensitive; registering an existing name replaces it.
+    pub fn register<T: Client + 'static>(&mut self, kind: &str, client_type: ClientType) {
+        self.kinds.insert(kind.to_lowercase(), (client_type, spawn_client::<T>));
+    }
+
+    pub fn contains(&self, kind: &str) -> bool {
+        self.kinds.contains_key(&kind.to_lowercase())
+    }
+
+    pub fn get_client_type(&self, kind: &str) -> Option<ClientType> {
+        self.kinds.get(&kind.to_lowercase()).map(|(client_type, _)| *client_type)
+    }
+
+    /// ###### Starts a client of the given kind in its own thread.
+    pub fn spawn(
+        &self,
+        kind: &str,
+        id: NodeId,
+        packet_recv: Receiver<Packet>,
+        controller_send: Sender<ClientEvent>,
+        controller_recv: Receiver<ClientCommand>,
+        ui_response_send: Sender<Response>,
+    ) -> Result<JoinHandle<()>, String> {
+        let Some((_, spawner)) = self.kinds.get(&kind.to_lowercase()) else {
+            return Err(format!("Client kind \"{}\" is not registered", kind));
+        };
+        Ok(spawner(id, packet_recv, controller_send, controller_recv, ui_response_send))
+    }
+}
+
+fn spawn_client<T: Client + 'static>(
+    id: NodeId,
+    packet_recv: Receiver<Packet>,
+    controller_send: Sender<ClientEvent>,
+    controller_recv: Receiver<ClientCommand>,
+    ui_response_send: Sender<Response>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut client = T::new(
+            id,
+            HashMap::new(),
+            packet_recv,
+            controller_send,
+            controller_recv,
+            ui_response_send,
+        );
+        client.run();
+    })
+}
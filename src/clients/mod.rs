@@ -1,6 +1,8 @@
 pub mod client;
 pub mod client_danylo;
 pub mod client_chen;
+pub mod client_factory;
 
 pub use client::Client;
+pub use client_factory::ClientFactory;
 
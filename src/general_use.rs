@@ -1,5 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use crossbeam_channel::Sender;
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +11,8 @@ use wg_2024::{
     packet::{Packet, NodeType},
 };
 
+use crate::routing::RouteInfo;
+
 pub type MediaRef = String;
 pub type FileRef = String;
 pub type ServerId = NodeId;
@@ -18,8 +23,10 @@ pub type DestinationId = NodeId;
 pub type SessionId = u64;
 pub type FloodId = u64;
 pub type FragmentIndex = u64;
+pub type MessageId = u64;
+pub type RoomName = String;
 pub type UsingTimes = u64;  //to measure traffic of fragments in a path.
-pub type ChatHistory = Vec<(Speaker, String)>;
+pub type ChatHistory = Vec<ChatMessage>;
 pub type Node = (NodeId, NodeType);
 
 ///all the monitoring data
@@ -40,14 +47,37 @@ pub struct DisplayDataWebBrowser {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Message {
+    id: MessageId,
     from: NodeId,
     to: NodeId,
-    content: String,
+    content: MessageContent,
+}
+
+///Content of a direct message, the servers forward it untouched
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MessageContent {
+    Text(String),
+    Encrypted { nonce: Vec<u8>, ciphertext: Vec<u8> },  //end-to-end encrypted text, only the recipient can read it
+    PublicKey { key: Vec<u8>, reply: bool },             //end-to-end key exchange, reply: the peer sends its key back
+}
+
+impl Display for MessageContent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageContent::Text(text) => write!(f, "{}", text),
+            MessageContent::Encrypted { .. } => write!(f, "<encrypted>"),
+            MessageContent::PublicKey { .. } => write!(f, "<key exchange>"),
+        }
+    }
 }
 
 impl Message {
-    pub fn new(from: NodeId, to: NodeId, content: String) -> Self {
-        Self { from, to, content }
+    pub fn new(id: MessageId, from: NodeId, to: NodeId, content: MessageContent) -> Self {
+        Self { id, from, to, content }
+    }
+
+    pub fn get_id(&self) -> MessageId {
+        self.id
     }
 
     pub fn get_sender(&self) -> NodeId {
@@ -58,6 +88,37 @@ impl Message {
         self.to
     }
 
+    pub fn get_content(&self) -> &MessageContent {
+        &self.content
+    }
+}
+
+///Message sent to every member of a room
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoomMessage {
+    id: MessageId,
+    room: RoomName,
+    from: NodeId,
+    content: String,
+}
+
+impl RoomMessage {
+    pub fn new(id: MessageId, room: RoomName, from: NodeId, content: String) -> Self {
+        Self { id, room, from, content }
+    }
+
+    pub fn get_id(&self) -> MessageId {
+        self.id
+    }
+
+    pub fn get_room(&self) -> &str {
+        &self.room
+    }
+
+    pub fn get_sender(&self) -> NodeId {
+        self.from
+    }
+
     pub fn get_content(&self) -> &str {
         &self.content
     }
@@ -81,6 +142,8 @@ pub struct DisplayDataChatClient {
 
     // Chats
     pub chats: HashMap<ClientId, ChatHistory>,
+    pub room_chats: HashMap<RoomName, ChatHistory>,
+    pub presence: HashMap<ClientId, Presence>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -91,6 +154,9 @@ pub struct DisplayDataCommunicationServer{
     pub connected_node_ids: HashSet<NodeId>,
     pub routing_table: HashMap<NodeId, Vec<NodeId>>,
     pub registered_clients: Vec<NodeId>,
+    pub stored_messages: HashMap<ClientId, usize>,  //offline messages waiting for every recipient
+    pub presence: HashMap<ClientId, Presence>,
+    pub federated_clients: HashMap<ServerId, Vec<ClientId>>,  //clients of the other communication servers
 }
 
 #[derive(Debug, Clone,  Serialize)]
@@ -115,27 +181,81 @@ pub struct DisplayDataTextServer{
     pub text_files: Vec<String>,
 }
 
-///packet sending status
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum NotSentType{
-    ToBeSent,
-    Dropped,
-    RoutingError,
-    DroneDestination,
-    BeenInWrongRecipient,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Speaker {
     Me,
     HimOrHer,
+    Member(ClientId),   //another member of a room
+}
+
+///Presence of a client on a communication server, the timestamps are seconds since the UNIX epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Presence {
+    Online,
+    LastSeen(u64),      //registered but unreachable
+    Unregistered(u64),  //left the server
+}
+
+impl Display for Presence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Presence::Online => write!(f, "online"),
+            Presence::LastSeen(timestamp) => write!(f, "last seen at {}", timestamp),
+            Presence::Unregistered(timestamp) => write!(f, "unregistered, last seen at {}", timestamp),
+        }
+    }
+}
+
+///Seconds since the UNIX epoch, for the presence of the clients and the chat histories
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
+
+///Direct chat with another client, or chat room
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Conversation {
+    Direct(ClientId),
+    Room(RoomName),
+}
+
+impl Display for Conversation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conversation::Direct(client_id) => write!(f, "client {}", client_id),
+            Conversation::Room(room) => write!(f, "room {}", room),
+        }
+    }
 }
 
+///Message of a chat history, the status is followed only for the messages sent
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum PacketStatus{
-    Sent,                   //Successfully sent packet, that is with ack received
-    NotSent(NotSentType),   //Include the packet not successfully sent, that is nack received
-    InProgress,             //When we have no ack or nack confirmation
+pub struct ChatMessage {
+    pub id: MessageId,
+    pub speaker: Speaker,
+    pub content: String,
+    pub status: MessageStatus,
+    #[serde(default)]
+    pub timestamp: u64,     //seconds since the UNIX epoch, when the message was sent or received
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageStatus {
+    Pending,            //waiting for a route or for the acks of the server
+    DeliveredToServer,  //every fragment acked by the communication server
+    DeliveredToPeer,    //receipt sent back by the recipient
+    Failed,             //the communication server could not be reached
+}
+
+impl Display for MessageStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MessageStatus::Pending => "pending",
+            MessageStatus::DeliveredToServer => "delivered to server",
+            MessageStatus::DeliveredToPeer => "delivered to peer",
+            MessageStatus::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 /// From controller to Server
@@ -147,6 +267,11 @@ pub enum ServerCommand {
     RemoveSender(NodeId),
     AddSender(NodeId, Sender<Packet>),
     ShortcutPacket(Packet),
+    RequestRoutes(DestinationId),   //answered with the RouteCosts event
+    SetRediscoveryInterval(Option<Duration>),   //None: no periodic flood
+    SetFloodForwarding(bool),   //false: the server answers every flood request instead of forwarding it
+    SetMessageStore(Option<Duration>, usize),   //TTL (None: no expiry) and capacity per recipient of the offline messages
+    Shutdown,   //stops the server thread
 }
 
 ///Server-Controller
@@ -156,6 +281,9 @@ pub enum ServerEvent {
     CommunicationServerData(InitiatorId, DisplayDataCommunicationServer, DataScope),
     TextServerData(InitiatorId, DisplayDataTextServer, DataScope),
     MediaServerData(InitiatorId, DisplayDataMediaServer, DataScope),
+
+    SessionFailed(InitiatorId, SessionId, DestinationId),  //no ack after every retransmission
+    RouteCosts(InitiatorId, DestinationId, Vec<RouteInfo>),  //routes to the destination, best first
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -183,11 +311,22 @@ pub enum ClientCommand {
     ShortcutPacket(Packet),
     GetKnownServers,
     RegisterToServer(ServerId),
+    UnregisterFromServer(ServerId),
     AskListClients(ServerId),
-
-
-    //commands for testing
-    RequestRoutes(DestinationId),
+    SetWindowSize(usize),   //fragments of a message sent before waiting for their acks
+    SetRediscoveryInterval(Option<Duration>),   //None: no periodic flood
+    SetEncryption(bool),    //true: the direct messages are encrypted end to end
+    TrustNewKey(ClientId),  //accepts the changed key of a client, once its fingerprint was checked
+    CreateRoom(ServerId, RoomName),  //the creator joins the room
+    JoinRoom(ServerId, RoomName),
+    LeaveRoom(RoomName),
+    AskListRooms(ServerId),
+    SendRoomMessage(RoomName, String),
+    ExportConversation(Conversation, PathBuf),  //writes the chat history of the conversation to a text file
+    Shutdown,   //stops the client thread
+
+
+    RequestRoutes(DestinationId),   //answered with the RouteCosts event
 }
 
 
@@ -199,7 +338,8 @@ pub enum ClientEvent {
 
     PacketSent(Packet),
     KnownServers(Vec<(NodeId, ServerType, bool)>),
-
+    SessionFailed(InitiatorId, SessionId, DestinationId),  //no ack after every retransmission
+    RouteCosts(InitiatorId, DestinationId, Vec<RouteInfo>),  //routes to the destination, best first
 }
 
 //Queries (Client -> Server)
@@ -213,6 +353,17 @@ pub enum Query {
     UnregisterClient(NodeId),
     AskListClients,
     SendMessage(Message),
+    MessageDelivered(MessageId, ClientId),  //receipt of a message, to its sender
+    CreateRoom(RoomName),
+    JoinRoom(RoomName),
+    LeaveRoom(RoomName),
+    AskListRooms,
+    SendRoomMessage(RoomMessage),
+
+    //Between Communication Servers
+    ServerDirectory(Vec<ClientId>),  //clients registered on the sending server
+    RelayMessage(Message),           //message to a client registered on the receiving server
+    RelayReceipt(MessageId, ClientId, ClientId),  //receipt of a relayed message: message, sender, recipient
 
     //To Content Server
     //(Text)
@@ -223,15 +374,27 @@ pub enum Query {
 }
 
 //Server -> Client
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum Response {
     //Common-shared
     ServerType(ServerType),
 
     //From Communication Server
     ClientRegistered,
+    ClientUnregistered,
     MessageReceived(Message),
     ListClients(Vec<NodeId>),
+    MessageDelivered(MessageId, ClientId),  //receipt of a message, from its recipient
+    ListRooms(Vec<RoomName>),
+    RoomMessageReceived(RoomMessage),
+    JoinedRoom(RoomName, ClientId),  //notice to every member, the new one included
+    LeftRoom(RoomName, ClientId),    //notice to every member, the one leaving included
+    PresenceChanged(ClientId, Presence),  //notice to every other registered client
+
+    //From the chat client to its UI
+    MessageStatus(Conversation, MessageId, MessageStatus),
+    KeyTrusted(ClientId),   //the changed key of the client is now pinned
+    ConversationExported(Conversation, PathBuf),  //the chat history was written to the file
 
     //From Content Server
     //(Text)
@@ -265,6 +428,20 @@ impl Display for ServerType {
     }
 }
 
+impl FromStr for ServerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "communication" => Ok(ServerType::Communication),
+            "text" => Ok(ServerType::Text),
+            "media" => Ok(ServerType::Media),
+            "undefined" => Ok(ServerType::Undefined),
+            _ => Err(format!("Unknown server type: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Hash, Serialize, Deserialize)]
 pub enum ClientType {
     Chat,
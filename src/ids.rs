@@ -0,0 +1,114 @@
+use std::collections::{HashSet, VecDeque};
+
+use wg_2024::network::NodeId;
+
+use crate::general_use::FloodId;
+
+/// ###### Bits of an ID left to the counter, the node ID takes the high bits.
+const COUNTER_BITS: u32 = u64::BITS - NodeId::BITS;
+const COUNTER_MASK: u64 = (1 << COUNTER_BITS) - 1;
+
+/// ###### Floods remembered by a node, older ones are forgotten first.
+const MAX_SEEN_FLOODS: usize = 1024;
+
+/// ###### Builds the ID number `counter` of a node, for sessions and floods.
+/// The node ID fills the high bits and the counter the low ones, so the IDs of two nodes never collide
+/// and a node repeats an ID only after 2^56 of them.
+pub fn unique_id(node_id: NodeId, counter: u64) -> u64 {
+    (u64::from(node_id) << COUNTER_BITS) | (counter & COUNTER_MASK)
+}
+
+/// ###### Flood requests already received, by initiator and flood ID.
+#[derive(Debug, Clone, Default)]
+pub struct SeenFloods {
+    seen: HashSet<(NodeId, FloodId)>,
+    order: VecDeque<(NodeId, FloodId)>,
+}
+
+impl SeenFloods {
+    /// ###### Records the flood. Returns false if it was already received.
+    pub fn first_time(&mut self, initiator_id: NodeId, flood_id: FloodId) -> bool {
+        if !self.seen.insert((initiator_id, flood_id)) {
+            return false;
+        }
+        self.order.push_back((initiator_id, flood_id));
+        if self.order.len() > MAX_SEEN_FLOODS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // Every node with the first counters, the last ones before the mask and the last one
+    fn counters() -> impl Iterator<Item = u64> + Clone {
+        (0..64).chain(COUNTER_MASK - 64..=COUNTER_MASK)
+    }
+
+    #[test]
+    fn ids_of_different_nodes_or_counters_never_collide() {
+        let mut seen = HashSet::new();
+        for node_id in NodeId::MIN..=NodeId::MAX {
+            for counter in counters() {
+                assert!(seen.insert(unique_id(node_id, counter)), "unique_id({}, {}) collides", node_id, counter);
+            }
+        }
+        assert_eq!(seen.len(), (usize::from(NodeId::MAX) + 1) * counters().count());
+    }
+
+    proptest! {
+        #[test]
+        fn distinct_node_and_counter_pairs_never_collide(
+            node_a: NodeId, counter_a in 0..=COUNTER_MASK,
+            node_b: NodeId, counter_b in 0..=COUNTER_MASK,
+        ) {
+            prop_assume!((node_a, counter_a) != (node_b, counter_b));
+            prop_assert_ne!(unique_id(node_a, counter_a), unique_id(node_b, counter_b));
+        }
+
+        // Random pairs rarely share the node, so the counters of a single node get their own property
+        #[test]
+        fn counters_of_a_node_never_collide(node: NodeId, counter_a in 0..=COUNTER_MASK, counter_b in 0..=COUNTER_MASK) {
+            prop_assume!(counter_a != counter_b);
+            prop_assert_ne!(unique_id(node, counter_a), unique_id(node, counter_b));
+        }
+    }
+
+    #[test]
+    fn node_and_counter_are_not_concatenated() {
+        // "1" + "12" and "11" + "2" collided with the old scheme
+        assert_ne!(unique_id(1, 12), unique_id(11, 2));
+        assert_eq!(unique_id(1, 12) >> COUNTER_BITS, 1);
+        assert_eq!(unique_id(1, 12) & COUNTER_MASK, 12);
+    }
+
+    #[test]
+    fn repeated_floods_are_recognised() {
+        let mut seen = SeenFloods::default();
+        assert!(seen.first_time(1, 7));
+        assert!(!seen.first_time(1, 7));
+        assert!(seen.first_time(2, 7));
+        assert!(seen.first_time(1, 8));
+    }
+
+    #[test]
+    fn oldest_floods_are_forgotten_first() {
+        let mut seen = SeenFloods::default();
+        for flood_id in 0..=MAX_SEEN_FLOODS as FloodId {
+            assert!(seen.first_time(1, flood_id));
+        }
+
+        // Flood 0 was evicted by the last one, flood 1 is the oldest left
+        assert!(!seen.first_time(1, 1));
+        assert!(!seen.first_time(1, MAX_SEEN_FLOODS as FloodId));
+        assert!(seen.first_time(1, 0));
+        assert!(seen.first_time(1, 1));
+    }
+}
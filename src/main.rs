@@ -2,16 +2,39 @@ use crate::network_initializer::NetworkInit;
 mod general_use;
 mod clients;
 mod network_initializer;
+mod network_config;
+mod topology_validator;
 mod ui;
 mod simulation_controller;
 mod servers;
 mod new_ui_test;
+mod transport;
+mod routing;
+mod ids;
 
 
 fn main() {
     env_logger::init();
 
     let mut my_net = NetworkInit::new();
+
+    //Headless mode: `--scenario <file>` runs the steps of the file and exits with the result
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--scenario") {
+        let Some(scenario_path) = args.get(position + 1) else {
+            eprintln!("Usage: {} --scenario <file>", args[0]);
+            std::process::exit(2);
+        };
+
+        match my_net.run_scenario(scenario_path) {
+            Some(report) => {
+                println!("{}", report);
+                std::process::exit(if report.passed() { 0 } else { 1 });
+            }
+            None => std::process::exit(2),
+        }
+    }
+
     // my_net.parse("input.toml");
     my_net.parse("topologies/butterfly.toml");
 }
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+use wg_2024::{config::Config, network::NodeId};
+
+use crate::routing::DEFAULT_REDISCOVERY_INTERVAL;
+use crate::transport::DEFAULT_WINDOW_SIZE;
+use crate::general_use::ServerType;
+use crate::servers::content;
+use crate::servers::message_store::{DEFAULT_MESSAGE_CAPACITY, DEFAULT_MESSAGE_TTL};
+use crate::topology_validator;
+
+/// ###### Topology file extended with the node settings that `wg_2024::config::Config` does not carry.
+/// The base config is parsed untouched, the extra keys of every `[[server]]` and `[[client]]` entry
+/// are parsed from the same file and validated into `ServerSettings` and `ClientSettings`.
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    pub base: Config,
+    pub servers: HashMap<NodeId, ServerSettings>,
+    pub clients: HashMap<NodeId, ClientSettings>,
+}
+
+/// ###### Role, content and options of a server, as declared in the topology file.
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    pub server_type: ServerType,
+    pub content: ContentSource,
+    pub options: ServerOptions,
+}
+
+/// ###### Kind of a client, as declared in the topology file.
+/// The kind is a name registered in the `ClientFactory`, e.g. `chat` or `web`.
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub kind: String,
+    pub options: ClientOptions,
+}
+
+/// ###### Where a content server takes the data it serves from.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum ContentSource {
+    Random,                             // Text: random subset of the texts, Media: every media
+    Files { titles: Vec<String> },      // Texts with the given titles, or the media they reference
+    LinkedTo { server: NodeId },        // Media referenced by the texts of the given text server
+}
+
+/// ###### Optional server behaviour.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerOptions {
+    pub discover_on_start: bool,        // Start a flood as soon as the network is connected
+    pub rediscovery_interval: u64,      // Seconds between the floods started on its own, 0 to disable them
+    pub forward_floods: bool,           // Forward the flood requests like a drone, false to be a leaf
+    pub message_ttl: u64,               // Seconds an offline message waits for its recipient, 0 to keep it (communication server)
+    pub message_capacity: usize,        // Offline messages kept for every recipient (communication server)
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            discover_on_start: false,
+            forward_floods: true,
+            rediscovery_interval: DEFAULT_REDISCOVERY_INTERVAL.as_secs(),
+            message_ttl: DEFAULT_MESSAGE_TTL.as_secs(),
+            message_capacity: DEFAULT_MESSAGE_CAPACITY,
+        }
+    }
+}
+
+/// ###### Optional client behaviour.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClientOptions {
+    pub window_size: usize,             // Fragments of a message in flight at the same time (chat client)
+    pub rediscovery_interval: u64,      // Seconds between the floods started on its own, 0 to disable them
+    pub encrypt_chats: bool,            // Encrypt the direct messages end to end (chat client)
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            window_size: DEFAULT_WINDOW_SIZE,
+            rediscovery_interval: DEFAULT_REDISCOVERY_INTERVAL.as_secs(),
+            encrypt_chats: false,
+        }
+    }
+}
+
+/// ###### Interval of the periodic floods, from the `rediscovery_interval` option.
+pub fn rediscovery_interval(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+/// ###### Time an offline message waits for its recipient, from the `message_ttl` option.
+pub fn message_ttl(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    Read(String),
+    Parse(String),
+    MissingServerType(NodeId),
+    UnknownServerType(NodeId, String),
+    InvalidContent(NodeId, String),
+    MissingClientType(NodeId),
+    UnknownClientType(NodeId, String),
+    InvalidWindowSize(NodeId),
+
+    // Topology errors
+    DuplicateId(NodeId),
+    UnknownNeighbour(NodeId, NodeId),
+    AsymmetricEdge(NodeId, NodeId),
+    NotConnectedToDrone(NodeId, NodeId),
+    TooManyDroneLinks(NodeId, usize),
+    TooFewDroneLinks(NodeId, usize),
+    InvalidPdr(NodeId, f32),
+    Disconnected(Vec<NodeId>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(err) => write!(f, "Unable to read config file: {}", err),
+            ConfigError::Parse(err) => write!(f, "Unable to parse TOML: {}", err),
+            ConfigError::MissingServerType(id) =>
+                write!(f, "Server {}: missing `server_type` (expected Communication, Text or Media)", id),
+            ConfigError::UnknownServerType(id, server_type) =>
+                write!(f, "Server {}: unknown `server_type` \"{}\" (expected Communication, Text or Media)", id, server_type),
+            ConfigError::InvalidContent(id, reason) => write!(f, "Server {}: invalid `content`: {}", id, reason),
+            ConfigError::MissingClientType(id) => write!(f, "Client {}: missing `client_type`", id),
+            ConfigError::UnknownClientType(id, kind) =>
+                write!(f, "Client {}: unknown `client_type` \"{}\" (no client of this kind is registered)", id, kind),
+            ConfigError::InvalidWindowSize(id) => write!(f, "Client {}: `window_size` must be at least 1", id),
+            ConfigError::DuplicateId(id) => write!(f, "Node {}: ID declared more than once", id),
+            ConfigError::UnknownNeighbour(id, neighbour) =>
+                write!(f, "Node {}: connected to node {}, which is not declared", id, neighbour),
+            ConfigError::AsymmetricEdge(id, neighbour) =>
+                write!(f, "Node {}: connected to node {}, but node {} is not connected back", id, neighbour, neighbour),
+            ConfigError::NotConnectedToDrone(id, neighbour) =>
+                write!(f, "Node {}: clients and servers can only be connected to drones, not to node {}", id, neighbour),
+            ConfigError::TooManyDroneLinks(id, links) =>
+                write!(f, "Client {}: connected to {} drones (at most 2 allowed)", id, links),
+            ConfigError::TooFewDroneLinks(id, links) =>
+                write!(f, "Server {}: connected to {} drones (at least 2 required)", id, links),
+            ConfigError::InvalidPdr(id, pdr) => write!(f, "Drone {}: PDR {} is outside [0, 1]", id, pdr),
+            ConfigError::Disconnected(ids) => write!(f, "Nodes {:?} cannot be reached through the drones", ids),
+        }
+    }
+}
+
+// Extra keys of the topology file, parsed alongside the base config.
+#[derive(Deserialize)]
+struct ExtendedConfig {
+    #[serde(default)]
+    server: Vec<ServerEntry>,
+    #[serde(default)]
+    client: Vec<ClientEntry>,
+}
+
+#[derive(Deserialize)]
+struct ServerEntry {
+    id: NodeId,
+    server_type: Option<String>,
+    content: Option<ContentSource>,
+    #[serde(default)]
+    options: ServerOptions,
+}
+
+#[derive(Deserialize)]
+struct ClientEntry {
+    id: NodeId,
+    client_type: Option<String>,
+    #[serde(default)]
+    options: ClientOptions,
+}
+
+impl NetworkConfig {
+    /// ###### Reads and validates the topology file at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<NetworkConfig, Vec<ConfigError>> {
+        let config_data = fs::read_to_string(path)
+            .map_err(|err| vec![ConfigError::Read(err.to_string())])?;
+        Self::from_toml(&config_data)
+    }
+
+    /// ###### Parses the topology and collects every error found in the node declarations.
+    pub fn from_toml(config_data: &str) -> Result<NetworkConfig, Vec<ConfigError>> {
+        let base: Config = toml::from_str(config_data)
+            .map_err(|err| vec![ConfigError::Parse(err.to_string())])?;
+        let extended: ExtendedConfig = toml::from_str(config_data)
+            .map_err(|err| vec![ConfigError::Parse(err.to_string())])?;
+
+        let mut errors = topology_validator::validate(&base);
+        let mut servers = HashMap::new();
+
+        for entry in &extended.server {
+            match Self::server_settings(entry) {
+                Ok(settings) => { servers.insert(entry.id, settings); }
+                Err(err) => errors.push(err),
+            }
+        }
+
+        // Linked media servers need an existing text server to take the references from.
+        for (id, settings) in &servers {
+            if let ContentSource::LinkedTo { server } = &settings.content {
+                if servers.get(server).map(|linked| linked.server_type) != Some(ServerType::Text) {
+                    errors.push(ConfigError::InvalidContent(*id, format!("server {} is not a text server", server)));
+                }
+            }
+        }
+
+        let mut clients = HashMap::new();
+        for entry in &extended.client {
+            if entry.options.window_size == 0 {
+                errors.push(ConfigError::InvalidWindowSize(entry.id));
+            }
+            match &entry.client_type {
+                Some(kind) => {
+                    clients.insert(entry.id, ClientSettings { kind: kind.clone(), options: entry.options.clone() });
+                }
+                None => errors.push(ConfigError::MissingClientType(entry.id)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(NetworkConfig { base, servers, clients })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// ###### Checks that every client kind is known, e.g. registered in the `ClientFactory`.
+    pub fn check_client_kinds<F: Fn(&str) -> bool>(&self, is_known: F) -> Result<(), Vec<ConfigError>> {
+        let errors: Vec<ConfigError> = self.clients
+            .iter()
+            .filter(|(_, settings)| !is_known(&settings.kind))
+            .map(|(id, settings)| ConfigError::UnknownClientType(*id, settings.kind.clone()))
+            .collect();
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    fn server_settings(entry: &ServerEntry) -> Result<ServerSettings, ConfigError> {
+        let Some(declared_type) = &entry.server_type else {
+            return Err(ConfigError::MissingServerType(entry.id));
+        };
+
+        let server_type = match declared_type.parse::<ServerType>() {
+            Ok(ServerType::Undefined) | Err(_) =>
+                return Err(ConfigError::UnknownServerType(entry.id, declared_type.clone())),
+            Ok(server_type) => server_type,
+        };
+
+        let content = entry.content.clone().unwrap_or(ContentSource::Random);
+
+        // Check that the content can be served by a server of this type.
+        match (server_type, &content) {
+            (ServerType::Communication, _) if entry.content.is_some() => {
+                return Err(ConfigError::InvalidContent(entry.id, "communication servers do not serve content".to_string()));
+            }
+            (ServerType::Text, ContentSource::LinkedTo { .. }) => {
+                return Err(ConfigError::InvalidContent(entry.id, "only media servers can be linked to a text server".to_string()));
+            }
+            (_, ContentSource::Files { titles }) => {
+                if let Some(unknown) = titles.iter().find(|title| !content::has_text(title)) {
+                    return Err(ConfigError::InvalidContent(entry.id, format!("unknown file \"{}\"", unknown)));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(ServerSettings {
+            server_type,
+            content,
+            options: entry.options.clone(),
+        })
+    }
+}
@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
 use crossbeam_channel::*;
-use std::{env, fs, thread};
+use std::{env, thread};
+use std::time::Duration;
 
 use wg_2024::{
-    config::{Client, Config, Drone, Server},
+    config::{Client, Drone, Server},
     controller::DroneEvent,
     network::NodeId,
     packet::{NodeType, Packet},
@@ -14,20 +15,25 @@ use crate::servers::server::Server as ServerTrait;
 use krusty_drone::KrustyCrapDrone;
 use wg_2024::drone::Drone as TraitDrone;
 
-use crate::clients;
-use crate::clients::Client as ClientTrait;
-use crate::general_use::{ClientCommand, ClientEvent, ClientType, Response, ServerEvent, ServerType};
+use crate::clients::ClientFactory;
+use crate::general_use::{ClientCommand, ClientEvent, Response, ServerCommand, ServerEvent, ServerType};
+use crate::network_config::{message_ttl, rediscovery_interval, ClientSettings, ContentSource, NetworkConfig, ServerSettings};
 use crate::new_ui_test::UI;
 use crate::servers::communication_server::CommunicationServer;
 use crate::servers::content;
 use crate::servers::media_server::MediaServer;
 use crate::servers::text_server::TextServer;
-use crate::simulation_controller::SimulationController;
+use crate::simulation_controller::packet_history::PacketFilter;
+use crate::simulation_controller::scenario::ScenarioReport;
+use crate::simulation_controller::{Scenario, SimulationController};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct NetworkInit {
     drone_sender_channels: HashMap<NodeId, Sender<Packet>>,
     clients_sender_channels: HashMap<NodeId, Sender<Packet>>,
     servers_sender_channels: HashMap<NodeId, Sender<Packet>>,
+    client_factory: ClientFactory,
 }
 
 impl NetworkInit {
@@ -36,9 +42,73 @@ impl NetworkInit {
             drone_sender_channels: HashMap::new(),
             clients_sender_channels: HashMap::new(),
             servers_sender_channels: HashMap::new(),
+            client_factory: ClientFactory::default(),
         }
     }
+
+    /// Gives access to the client factory, to register other `Client` implementations
+    /// before the topology is parsed.
+    pub fn client_factory(&mut self) -> &mut ClientFactory {
+        &mut self.client_factory
+    }
     pub fn parse(&mut self, input: &str){
+        let Some((mut controller, ui_response_recv)) = self.setup(input) else {
+            return;
+        };
+
+        println!("Starting UI");
+        UI::new(&mut controller, ui_response_recv).run();
+
+        if let Err(err) = controller.shutdown(SHUTDOWN_TIMEOUT) {
+            eprintln!("{}", err);
+        }
+    }
+
+    /// Starts the topology of the scenario and runs its steps without any user input.
+    /// Returns the report, or `None` if the scenario or its topology could not be loaded.
+    pub fn run_scenario(&mut self, scenario_path: &str) -> Option<ScenarioReport> {
+        let scenario = match Scenario::from_file(scenario_path) {
+            Ok(scenario) => scenario,
+            Err(err) => {
+                eprintln!("{}", err);
+                return None;
+            }
+        };
+
+        let (mut controller, ui_response_recv) = self.setup(&scenario.topology)?;
+
+        //A JSON Lines history is appended while the scenario runs, so it is kept even if the run does not end;
+        //a CSV history is exported once the run is over
+        let history_csv = match scenario.history.as_deref() {
+            Some(history_path) if history_path.ends_with(".csv") => Some(history_path),
+            Some(history_path) => {
+                if let Err(err) = controller.log_packet_history(history_path) {
+                    eprintln!("{}", err);
+                }
+                None
+            }
+            None => None,
+        };
+
+        println!("Running scenario {}", scenario_path);
+        let report = scenario.run(&mut controller, &ui_response_recv);
+        println!("{}", controller.get_statistics());
+
+        if let Some(history_path) = history_csv {
+            if let Err(err) = controller.export_packet_history(history_path, &PacketFilter::default()) {
+                eprintln!("{}", err);
+            }
+        }
+
+        if let Err(err) = controller.shutdown(SHUTDOWN_TIMEOUT) {
+            eprintln!("{}", err);
+        }
+        Some(report)
+    }
+
+    /// Parses the topology file and starts every node.
+    /// Returns the controller and the channel of the responses received by the clients.
+    fn setup(&mut self, input: &str) -> Option<(SimulationController, Receiver<Response>)> {
 
         println!("{:?}", env::current_dir().expect("Failed to get current directory"));
 
@@ -46,10 +116,23 @@ impl NetworkInit {
         let current_dir = env::current_dir().expect("Failed to get current directory");
         let input_path = current_dir.join(input);  // This combines the current directory with the `input` file name
 
-        //Deserializing the TOML file
-        let config_data =
-            fs::read_to_string(input_path).expect("Unable to read config file");
-        let config: Config = toml::from_str(&config_data).expect("Unable to parse TOML");
+        //Deserializing the TOML file and validating the declared server roles and client kinds
+        let network_config = match NetworkConfig::from_file(input_path).and_then(|network_config| {
+            network_config.check_client_kinds(|kind| self.client_factory.contains(kind))?;
+            Ok(network_config)
+        }) {
+            Ok(network_config) => network_config,
+            Err(errors) => {
+                eprintln!("Invalid network configuration:");
+                for error in errors {
+                    eprintln!(" - {}", error);
+                }
+                return None;
+            }
+        };
+        let config = network_config.base;
+        let server_settings = network_config.servers;
+        let client_settings = network_config.clients;
 
         //Splitting information - getting data about neighbours
         let mut neighbours: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
@@ -80,7 +163,6 @@ impl NetworkInit {
         let mut controller = SimulationController::new(
             to_control_event_drone.clone(),
             control_get_event_drone,
-            to_control_event_client.clone(),
             control_get_event_client,
             to_control_event_server.clone(),
             control_get_event_server
@@ -93,20 +175,63 @@ impl NetworkInit {
         self.create_drones(config.drone, &mut controller, to_control_event_drone);
 
         //Looping through servers (we have to decide how to split since we have two)
-        self.create_clients(config.client, &mut controller, to_control_event_client, ui_response_send);
+        self.create_clients(config.client, &client_settings, &mut controller, to_control_event_client, ui_response_send);
 
         //Looping through Clients
-        self.create_servers(config.server, &mut controller, to_control_event_server);
+        self.create_servers(config.server, &server_settings, &mut controller, to_control_event_server);
 
         //Connecting the Nodes
         self.connect_nodes(&mut controller, neighbours);
 
-        // for (_, (sender, _)) in controller.command_senders_servers.iter(){
-        //     sender.send(ServerCommand::Discover).unwrap();
-        // }
+        //Handling the events of every node on a separate thread
+        controller.start_event_dispatcher();
 
-        println!("Starting UI");
-        UI::new(&mut controller, ui_response_recv).run();
+        //Applying the server options
+        for (server_id, settings) in server_settings.iter() {
+            if let Err(e) = controller.set_rediscovery_interval(*server_id, rediscovery_interval(settings.options.rediscovery_interval)) {
+                eprintln!("{}", e);
+            }
+            if let Err(e) = controller.set_flood_forwarding(*server_id, settings.options.forward_floods) {
+                eprintln!("{}", e);
+            }
+            if settings.server_type == ServerType::Communication {
+                let ttl = message_ttl(settings.options.message_ttl);
+                if let Err(e) = controller.set_message_store(*server_id, ttl, settings.options.message_capacity) {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+
+        //Starting the discovery on the servers that asked for it
+        for (server_id, settings) in server_settings.iter() {
+            if settings.options.discover_on_start {
+                if let Some((sender, _)) = controller.command_senders_servers.get(server_id) {
+                    sender.send(ServerCommand::Discover).unwrap();
+                }
+            }
+        }
+
+        //Applying the client options
+        for (client_id, settings) in client_settings.iter() {
+            if let Err(e) = controller.set_window_size(*client_id, settings.options.window_size) {
+                eprintln!("{}", e);
+            }
+            if let Err(e) = controller.set_rediscovery_interval(*client_id, rediscovery_interval(settings.options.rediscovery_interval)) {
+                eprintln!("{}", e);
+            }
+            if settings.options.encrypt_chats {
+                if let Err(e) = controller.set_encryption(*client_id, true) {
+                    eprintln!("{}", e);
+                }
+            }
+        }
+
+        //The nodes now own every packet sender: dropping ours lets them stop once disconnected
+        self.drone_sender_channels.clear();
+        self.clients_sender_channels.clear();
+        self.servers_sender_channels.clear();
+
+        Some((controller, ui_response_recv))
     }
 
 
@@ -117,7 +242,7 @@ impl NetworkInit {
 
             //Adding channel to controller
             let (to_drone_command_sender,drone_get_command_recv) = unbounded();
-            controller.register_drone(drone.id, to_drone_command_sender);
+            controller.register_drone(drone.id, to_drone_command_sender, drone.pdr);
 
             //Creating receiver for Drone
             let (packet_sender, packet_receiver) = unbounded();
@@ -129,6 +254,7 @@ impl NetworkInit {
             to_contr_event.clone();
 
             //Creating Drone
+            let drone_id = drone.id;
             let drone = controller.create_drone::<KrustyCrapDrone>(
                 drone.id,
                 drone_get_command_recv,
@@ -136,13 +262,14 @@ impl NetworkInit {
                 HashMap::new(),
                 drone.pdr);
 
-            thread::spawn(move || {
+            let handle = thread::spawn(move || {
 
                 match drone {
                     Ok(mut drone) => drone.run(),
                     Err(e) => panic!("{}",e),
                 }
             });
+            controller.register_join_handle(drone_id, handle);
         }
     }
 
@@ -151,11 +278,11 @@ impl NetworkInit {
     fn create_clients(
         &mut self,
         config_client: Vec<Client>,
+        client_settings: &HashMap<NodeId, ClientSettings>,
         controller: &mut SimulationController,
         to_contr_event: Sender<ClientEvent>,
         ui_response_sender: Sender<Response>,
     ) {
-        let mut counter = 0;
         for client in config_client {
 
             let (to_client_command_sender, client_get_command_recv):(Sender<ClientCommand>,Receiver<ClientCommand>) = unbounded();
@@ -163,49 +290,39 @@ impl NetworkInit {
 
             self.clients_sender_channels.insert(client.id, packet_sender);
 
-            //Copy of contrEvent
-            let copy_contr_event = to_contr_event.clone();
-            //Copy of contrEvent
-            let copy_ui_response_sender = ui_response_sender.clone();
-
+            //Kind of client declared in the topology, already checked against the factory
+            let kind = &client_settings[&client.id].kind;
+            let client_type = self.client_factory.get_client_type(kind).unwrap();
+            controller.register_client(client.id, to_client_command_sender, client_type);
 
-            if counter % 2 == 0 {
-                controller.register_client(client.id,to_client_command_sender, ClientType::Web);
-
-                thread::spawn(move || {
-                    let mut client = clients::client_chen::ClientChen::new(
-                        client.id,
-                        HashMap::new(),
-                        packet_receiver,
-                        copy_contr_event,
-                        client_get_command_recv,
-                        copy_ui_response_sender,
-                    );
-                    client.run();
-                });
-            } else {
-                controller.register_client(client.id,to_client_command_sender, ClientType::Chat);
-
-                thread::spawn(move || {
-                    let mut client = clients::client_danylo::ChatClientDanylo::new(
-                        client.id,
-                        HashMap::new(),
-                        packet_receiver,
-                        copy_contr_event,
-                        client_get_command_recv,
-                        copy_ui_response_sender,
-                    );
-                    client.run();
-                });
+            //Creating and running the client
+            match self.client_factory.spawn(
+                kind,
+                client.id,
+                packet_receiver,
+                to_contr_event.clone(),
+                client_get_command_recv,
+                ui_response_sender.clone(),
+            ) {
+                Ok(handle) => controller.register_join_handle(client.id, handle),
+                Err(e) => panic!("{}", e),
             }
-            counter += 1;
         }
     }
 
     /// SERVERS GENERATION
 
-    fn create_servers(&mut self, config_server: Vec<Server>, controller: &mut SimulationController, _to_contr_event: Sender<ServerEvent> ) {
-        let mut vec_files = Vec::new();
+    fn create_servers(
+        &mut self,
+        mut config_server: Vec<Server>,
+        server_settings: &HashMap<NodeId, ServerSettings>,
+        controller: &mut SimulationController,
+        _to_contr_event: Sender<ServerEvent>,
+    ) {
+        //Text servers first, so that the media servers linked to them know which files they serve
+        config_server.sort_by_key(|server| server_settings[&server.id].server_type != ServerType::Text);
+
+        let mut text_files: HashMap<NodeId, Vec<(String, String)>> = HashMap::new();
 
         for server in config_server {
             let (command_sender, command_receiver) = unbounded();
@@ -213,54 +330,63 @@ impl NetworkInit {
             let (packet_sender, packet_receiver) = unbounded();
 
             let server_events_sender_clone = controller.server_event_sender.clone();
-            let server_type;
+            let settings = &server_settings[&server.id];
+            let server_type = settings.server_type;
 
             let mut server_instance_comm: Option<CommunicationServer> = None;
             let mut server_instance_text: Option<TextServer>= None;
             let mut server_instance_media: Option<MediaServer>= None;
 
-            if server.id == 8 {
-                server_type = ServerType::Communication;
-
-                server_instance_comm = Some(CommunicationServer::new(
-                    server.id,
-                    server_events_sender_clone,
-                    command_receiver,
-                    packet_receiver,
-                    HashMap::new(),
-                ));
-
-            } else if server.id == 14 {
-                let content = content::get_media(vec_files.clone());
-                server_type = ServerType::Media;
-
-                server_instance_media = Some(MediaServer::new(
-                    server.id,
-                    content,
-                    server_events_sender_clone,
-                    command_receiver,
-                    packet_receiver,
-                    HashMap::new(),
-                ));
-            } else{
-                vec_files = content::choose_random_texts();
-                server_type = ServerType::Text;
-
-                server_instance_text = Some(TextServer::new(
-                    server.id,
-                    vec_files.iter().cloned().collect::<HashMap<String, String>>(),
-                    server_events_sender_clone,
-                    command_receiver,
-                    packet_receiver,
-                    HashMap::new(),
-                ));
-            };
+            match server_type {
+                ServerType::Communication => {
+                    server_instance_comm = Some(CommunicationServer::new(
+                        server.id,
+                        server_events_sender_clone,
+                        command_receiver,
+                        packet_receiver,
+                        HashMap::new(),
+                    ));
+                }
+                ServerType::Media => {
+                    let content = match &settings.content {
+                        ContentSource::Random => content::get_media(content::get_all_texts()),
+                        ContentSource::Files { titles } => content::get_media(content::get_texts(titles)),
+                        ContentSource::LinkedTo { server } => content::get_media(text_files[server].clone()),
+                    };
+
+                    server_instance_media = Some(MediaServer::new(
+                        server.id,
+                        content,
+                        server_events_sender_clone,
+                        command_receiver,
+                        packet_receiver,
+                        HashMap::new(),
+                    ));
+                }
+                ServerType::Text => {
+                    let vec_files = match &settings.content {
+                        ContentSource::Files { titles } => content::get_texts(titles),
+                        _ => content::choose_random_texts(),
+                    };
+                    text_files.insert(server.id, vec_files.clone());
+
+                    server_instance_text = Some(TextServer::new(
+                        server.id,
+                        vec_files.into_iter().collect::<HashMap<String, String>>(),
+                        server_events_sender_clone,
+                        command_receiver,
+                        packet_receiver,
+                        HashMap::new(),
+                    ));
+                }
+                ServerType::Undefined => unreachable!("server types are validated when the config is parsed"),
+            }
 
             controller.register_server(server.id, command_sender, server_type);
             self.servers_sender_channels.insert(server.id, packet_sender);
 
             // Create and run server
-            thread::spawn(move ||
+            let handle = thread::spawn(move ||
                 match server_type {
                     ServerType::Communication => {
                         if let Some(mut server_instance) = server_instance_comm {
@@ -280,6 +406,7 @@ impl NetworkInit {
                     ServerType::Undefined => panic!("what?")
                 }
             );
+            controller.register_join_handle(server.id, handle);
         }
     }
 
@@ -1,8 +1,9 @@
-use crate::general_use::{ClientCommand, ClientId, ClientType, Response, ServerId, ServerType};
+use crate::general_use::{ClientCommand, ClientId, ClientType, Conversation, MessageStatus, Response, ServerId, ServerType};
 use crate::simulation_controller::SimulationController;
 use crossbeam_channel::Receiver;
 use std::collections::HashMap;
 use std::io::{self, Write};
+use std::path::PathBuf;
 use std::time::Duration;
 use wg_2024::network::NodeId;
 use wg_2024::packet::NodeType;
@@ -34,6 +35,7 @@ impl<'a> UI<'a> {
                 "\nChoose an option\n\
                 1. Use clients\n\
                 2. Crash drone\n\
+                3. Show statistics\n\
                 0. Exit"
             );
             let user_choice = Self::ask_input_user();
@@ -41,6 +43,7 @@ impl<'a> UI<'a> {
             match user_choice {
                 1 => self.use_clients(),
                 2 => self.crash_drone(),
+                3 => println!("\n{}", self.controller.get_statistics()),
                 0 => break,
                 _ => println!("Not a valid option, choose again"),
             }
@@ -71,6 +74,26 @@ impl<'a> UI<'a> {
         user_input.trim().parse().ok()
     }
 
+    fn ask_text_user(prompt: &str) -> String {
+        loop {
+            print!("{} > ", prompt);
+            io::stdout().flush().unwrap();
+
+            let mut user_input = String::new();
+            if let Err(err) = io::stdin().read_line(&mut user_input) {
+                eprintln!("Error reading input: {}", err);
+                continue;
+            }
+
+            let text = user_input.trim();
+            if text.is_empty() {
+                println!("Invalid input. Please try again.");
+                continue;
+            }
+            return text.to_string();
+        }
+    }
+
     fn use_clients(&mut self) {
         let mut stay_inside = true;
         while stay_inside {
@@ -114,6 +137,14 @@ impl<'a> UI<'a> {
                 3. Register to a server\n\
                 4. List clients\n\
                 5. Send message\n\
+                6. List rooms\n\
+                7. Create room\n\
+                8. Join room\n\
+                9. Leave room\n\
+                10. Send message to room\n\
+                11. Unregister from a server\n\
+                12. Export a chat\n\
+                13. Trust the changed key of a client\n\
                 0. Go back"
             );
 
@@ -125,6 +156,14 @@ impl<'a> UI<'a> {
                 3 => self.register_to_server(client_id_chose),
                 4 => self.ask_list_clients(client_id_chose),
                 5 => self.send_message_to(client_id_chose),
+                6 => self.ask_list_rooms(client_id_chose),
+                7 => self.create_room(client_id_chose),
+                8 => self.join_room(client_id_chose),
+                9 => self.leave_room(client_id_chose),
+                10 => self.send_room_message(client_id_chose),
+                11 => self.unregister_from_server(client_id_chose),
+                12 => self.export_conversation(client_id_chose),
+                13 => self.trust_new_key(client_id_chose),
                 0 => stay_inside = false,
                 _ => println!("Not a valid option, choose again")
             }
@@ -242,6 +281,77 @@ impl<'a> UI<'a> {
         }
     }
 
+    fn unregister_from_server(&mut self, client_id: NodeId) {
+        let Some(server_id) = self.choose_server(client_id) else {
+            return;
+        };
+
+        println!("Asking to unregister from server {}", server_id);
+        self.send_client_command(client_id, ClientCommand::UnregisterFromServer(server_id));
+
+        match self.response_recv.recv() {
+            Ok(response) => {
+                match response {
+                    Response::ClientUnregistered => {
+                        println!("Client unregistered from server {} successfully", server_id);
+                        self.clients.remove(&client_id);
+                    }
+                    Response::Err(err) => {
+                        println!("Error unregistering from server: {}", err);
+                    }
+                    response => {
+                        println!("Unexpected response: {:?}", response);
+                    }
+                }
+            }
+            Err(err) => {
+                eprintln!("Error receiving response: {}", err);
+            }
+        }
+    }
+
+    fn trust_new_key(&mut self, client_id: NodeId) {
+        let Ok(peer) = Self::ask_text_user("Client ID").parse::<ClientId>() else {
+            println!("Not a valid client ID");
+            return;
+        };
+
+        if let Err(err) = self.controller.trust_new_key(client_id, peer) {
+            eprintln!("{}", err);
+            return;
+        }
+
+        // Only the confirmation of the client means the key is pinned
+        match self.response_recv.recv_timeout(Duration::from_secs(1)) {
+            Ok(Response::KeyTrusted(trusted)) if trusted == peer => println!("New key of client {} trusted", peer),
+            Ok(Response::Err(err)) => println!("Error trusting the key: {}", err),
+            Ok(response) => println!("Unexpected response, the key may not be trusted: {:?}", response),
+            Err(_) => println!("No answer from client {}, the key was not trusted", client_id),
+        }
+    }
+
+    fn export_conversation(&mut self, client_id: NodeId) {
+        // A number is a client, anything else a room
+        let chat = Self::ask_text_user("Client ID or room name");
+        let conversation = match chat.parse::<ClientId>() {
+            Ok(peer) => Conversation::Direct(peer),
+            Err(_) => Conversation::Room(chat),
+        };
+        let path = PathBuf::from(Self::ask_text_user("File"));
+
+        if let Err(err) = self.controller.export_conversation(client_id, conversation.clone(), path.clone()) {
+            eprintln!("{}", err);
+            return;
+        }
+
+        match self.response_recv.recv_timeout(Duration::from_secs(1)) {
+            Ok(Response::ConversationExported(exported, file)) => println!("Chat with {} exported to {}", exported, file.display()),
+            Ok(Response::Err(err)) => println!("Error exporting the chat: {}", err),
+            Ok(response) => println!("Unexpected response, the chat may not be exported: {:?}", response),
+            Err(_) => println!("No answer from client {}, the chat was not exported", client_id),
+        }
+    }
+
     fn ask_list_clients(&mut self, client_id: NodeId) {
         let Some(server_id) = self.choose_server(client_id) else {
             return;
@@ -366,18 +476,30 @@ impl<'a> UI<'a> {
 
                     println!("\nMessage sent to client {}", client_id_chose);
 
-                    match self.response_recv.recv() {
-                        Ok(response) => {
-                            match response {
-                                Response::MessageReceived(message) => {
-                                    println!("Client {} received message from client {}: {}", client_id_chose, message.get_sender(), message.get_content());
-                                }
-                                response => {
-                                    println!("Unexpected response: {:?}", response);
+                    // Wait for the message and its status, until the recipient sends the receipt or the sending fails
+                    loop {
+                        match self.response_recv.recv_timeout(Duration::from_secs(2)) {
+                            Ok(response) => {
+                                match response {
+                                    Response::MessageReceived(message) => {
+                                        println!("Client {} received message from client {}: {}", client_id_chose, message.get_sender(), message.get_content());
+                                    }
+                                    Response::MessageStatus(conversation, message_id, status) => {
+                                        println!("Message {} to {}: {}", message_id, conversation, status);
+                                        if status == MessageStatus::DeliveredToPeer || status == MessageStatus::Failed {
+                                            break;
+                                        }
+                                    }
+                                    response => {
+                                        println!("Unexpected response: {:?}", response);
+                                    }
                                 }
                             }
-                        }
-                        Err(_) => {}
+                            Err(_) => {
+                                println!("No receipt yet for the message to client {}", client_id_chose);
+                                break;
+                            }
+                        };
                     }
                 }
                 _ => println!("Not a valid option, choose again")
@@ -385,6 +507,83 @@ impl<'a> UI<'a> {
         }
     }
 
+    fn ask_list_rooms(&mut self, client_id: NodeId) {
+        let Some(server_id) = self.choose_server(client_id) else {
+            return;
+        };
+
+        println!("Requesting rooms list from server {}", server_id);
+        self.send_client_command(client_id, ClientCommand::AskListRooms(server_id));
+        self.print_room_responses();
+    }
+
+    fn create_room(&mut self, client_id: NodeId) {
+        let Some(server_id) = self.choose_server(client_id) else {
+            return;
+        };
+        let room = Self::ask_text_user("Room name");
+
+        println!("Creating room {} on server {}", room, server_id);
+        self.send_client_command(client_id, ClientCommand::CreateRoom(server_id, room));
+        self.print_room_responses();
+    }
+
+    fn join_room(&mut self, client_id: NodeId) {
+        let Some(server_id) = self.choose_server(client_id) else {
+            return;
+        };
+        let room = Self::ask_text_user("Room name");
+
+        println!("Joining room {} on server {}", room, server_id);
+        self.send_client_command(client_id, ClientCommand::JoinRoom(server_id, room));
+        self.print_room_responses();
+    }
+
+    fn leave_room(&mut self, client_id: NodeId) {
+        let room = Self::ask_text_user("Room name");
+
+        println!("Leaving room {}", room);
+        self.send_client_command(client_id, ClientCommand::LeaveRoom(room));
+        self.print_room_responses();
+    }
+
+    fn send_room_message(&mut self, client_id: NodeId) {
+        let room = Self::ask_text_user("Room name");
+        let content = Self::ask_text_user("Message");
+
+        self.send_client_command(client_id, ClientCommand::SendRoomMessage(room, content));
+        self.print_room_responses();
+    }
+
+    fn send_client_command(&mut self, client_id: NodeId, command: ClientCommand) {
+        self.controller
+            .command_senders_clients
+            .get(&client_id)
+            .unwrap()
+            .0
+            .send(command)
+            .unwrap();
+    }
+
+    // Every client shares the response channel: the notices and messages of the other members are printed too
+    fn print_room_responses(&mut self) {
+        while let Ok(response) = self.response_recv.recv_timeout(Duration::from_secs(1)) {
+            match response {
+                Response::ListRooms(rooms) => println!("Rooms list {:?}", rooms),
+                Response::JoinedRoom(room, client) => println!("Client {} joined room {}", client, room),
+                Response::LeftRoom(room, client) => println!("Client {} left room {}", client, room),
+                Response::RoomMessageReceived(message) => {
+                    println!("Room {}, client {}: {}", message.get_room(), message.get_sender(), message.get_content());
+                }
+                Response::MessageStatus(conversation, message_id, status) => {
+                    println!("Message {} to {}: {}", message_id, conversation, status);
+                }
+                Response::Err(err) => println!("Error: {}", err),
+                response => println!("Unexpected response: {:?}", response),
+            }
+        }
+    }
+
     fn ask_list_files(&mut self, client_id: NodeId) {
         let Some(server_id) = self.choose_server(client_id) else {
             return;
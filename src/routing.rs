@@ -0,0 +1,564 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use wg_2024::network::NodeId;
+use wg_2024::packet::NodeType;
+
+/// ###### Nodes and their neighbours, as discovered by the floods, with the type of every node.
+/// Only the nodes that forward packets can be crossed by a route:
+/// the drones, and the servers seen in the middle of a path trace, which forward like drones.
+/// Clients and the other servers are only the ends of a route.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    links: HashMap<NodeId, HashSet<NodeId>>,
+    types: HashMap<NodeId, NodeType>,
+    forwarding_servers: HashSet<NodeId>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ###### Adds the nodes and the links of a flood path trace, in both directions.
+    pub fn add_path(&mut self, path: &[(NodeId, NodeType)]) {
+        for (position, &(node, node_type)) in path.iter().enumerate() {
+            self.types.insert(node, node_type);
+            // A server inside the trace forwarded the flood
+            if matches!(node_type, NodeType::Server) && position > 0 && position + 1 < path.len() {
+                self.forwarding_servers.insert(node);
+            }
+        }
+        for hop in path.windows(2) {
+            let (current, next) = (hop[0].0, hop[1].0);
+            self.links.entry(current).or_default().insert(next);
+            self.links.entry(next).or_default().insert(current);
+        }
+    }
+
+    /// ###### Removes a node that failed and its links.
+    pub fn remove_node(&mut self, node: NodeId) {
+        for neighbours in self.links.values_mut() {
+            neighbours.remove(&node);
+        }
+        self.links.remove(&node);
+        self.types.remove(&node);
+        self.forwarding_servers.remove(&node);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    pub fn neighbours(&self, node: NodeId) -> Option<&HashSet<NodeId>> {
+        self.links.get(&node)
+    }
+
+    pub fn node_type(&self, node: NodeId) -> Option<NodeType> {
+        self.types.get(&node).copied()
+    }
+
+    /// ###### Returns true if a route can cross the node.
+    pub fn forwards(&self, node: NodeId) -> bool {
+        match self.types.get(&node) {
+            Some(NodeType::Drone) => true,
+            Some(NodeType::Server) => self.forwarding_servers.contains(&node),
+            _ => false,
+        }
+    }
+}
+
+/// ###### Routes kept per destination, the best one included.
+pub const DEFAULT_ALTERNATIVE_ROUTES: usize = 3;
+
+/// ###### How often the alternative routes are rebuilt from the topology.
+pub const ROUTE_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// ###### How often a node floods the network on its own, unless configured otherwise.
+pub const DEFAULT_REDISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// ###### Shortest time between two floods asked by events, so that a burst of events starts one flood.
+const MIN_REDISCOVERY_GAP: Duration = Duration::from_secs(1);
+
+/// ###### Drop rate assumed for a drone never observed, as if 1 packet out of 10 was dropped.
+const PRIOR_DROPPED: f64 = 1.0;
+const PRIOR_OBSERVATIONS: f64 = 10.0;
+
+/// ###### Observations kept per drone: past them the counts are halved,
+/// so that the estimate follows the changes of the drop rate.
+const MAX_OBSERVATIONS: f64 = 100.0;
+
+/// ###### A route to a destination with its estimated cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    pub destination: NodeId,
+    pub hops: Vec<NodeId>,
+    pub delivery_probability: f64,      // Probability that a fragment crosses every drone of the route
+    pub expected_transmissions: f64,    // Sendings needed on average for a fragment to be delivered
+}
+
+/// ###### Fragments forwarded and dropped by a drone, as seen by this node.
+#[derive(Debug, Clone, Copy, Default)]
+struct DropStats {
+    delivered: f64,
+    dropped: f64,
+}
+
+/// ###### Packet drop rate of the drones, estimated from the ACKs and the `Dropped` NACKs received.
+#[derive(Debug, Clone, Default)]
+pub struct Reliability {
+    stats: HashMap<NodeId, DropStats>,
+}
+
+impl Reliability {
+    /// ###### Records a fragment acknowledged by the destination: every drone of the route forwarded it.
+    pub fn record_delivered(&mut self, route: &[NodeId]) {
+        for &drone in intermediates(route) {
+            self.observe(drone, false);
+        }
+    }
+
+    /// ###### Records a fragment dropped by a drone of the route: the drones before it forwarded it.
+    /// Ignored if the drone is not an intermediate node of the route.
+    pub fn record_dropped(&mut self, route: &[NodeId], drone: NodeId) {
+        let intermediates = intermediates(route);
+        let Some(position) = intermediates.iter().position(|&node| node == drone) else {
+            return;
+        };
+        for &forwarder in &intermediates[..position] {
+            self.observe(forwarder, false);
+        }
+        self.observe(drone, true);
+    }
+
+    /// ###### Estimated probability that the drone drops a fragment.
+    pub fn drop_rate(&self, drone: NodeId) -> f64 {
+        let stats = self.stats.get(&drone).copied().unwrap_or_default();
+        (stats.dropped + PRIOR_DROPPED) / (stats.delivered + stats.dropped + PRIOR_OBSERVATIONS)
+    }
+
+    /// ###### Estimated probability that a fragment crosses every drone of the route.
+    pub fn delivery_probability(&self, route: &[NodeId]) -> f64 {
+        intermediates(route)
+            .iter()
+            .map(|&drone| 1.0 - self.drop_rate(drone))
+            .product()
+    }
+
+    /// ###### Estimated cost of the route: the sendings needed on average to deliver a fragment.
+    pub fn expected_transmissions(&self, route: &[NodeId]) -> f64 {
+        1.0 / self.delivery_probability(route)
+    }
+
+    // Weight of crossing the drone: summing them along a path multiplies the delivery probabilities.
+    fn weight(&self, drone: NodeId) -> f64 {
+        -(1.0 - self.drop_rate(drone)).ln()
+    }
+
+    fn observe(&mut self, drone: NodeId, dropped: bool) {
+        let stats = self.stats.entry(drone).or_default();
+        if dropped {
+            stats.dropped += 1.0;
+        } else {
+            stats.delivered += 1.0;
+        }
+        if stats.delivered + stats.dropped > MAX_OBSERVATIONS {
+            stats.delivered /= 2.0;
+            stats.dropped /= 2.0;
+        }
+    }
+}
+
+/// ###### Routes from a node to the destinations it knows, up to `max_routes` per destination.
+/// The routes of a destination are kept cheapest first, by expected transmissions:
+/// the first one is used to send, the others are the fallbacks used as soon as a node of the first one fails.
+#[derive(Debug, Clone)]
+pub struct RouteCache {
+    source: NodeId,
+    max_routes: usize,
+    routes: HashMap<NodeId, Vec<Vec<NodeId>>>,
+    reliability: Reliability,
+    last_refresh: Instant,
+}
+
+impl RouteCache {
+    pub fn new(source: NodeId) -> Self {
+        Self {
+            source,
+            max_routes: DEFAULT_ALTERNATIVE_ROUTES,
+            routes: HashMap::new(),
+            reliability: Reliability::default(),
+            last_refresh: Instant::now(),
+        }
+    }
+
+    pub fn contains(&self, destination: NodeId) -> bool {
+        self.routes.get(&destination).is_some_and(|routes| !routes.is_empty())
+    }
+
+    /// ###### Returns the route currently used to reach the destination.
+    pub fn best(&self, destination: NodeId) -> Option<&Vec<NodeId>> {
+        self.routes.get(&destination).and_then(|routes| routes.first())
+    }
+
+    /// ###### Returns every route to the destination, best first.
+    pub fn routes(&self, destination: NodeId) -> &[Vec<NodeId>] {
+        self.routes.get(&destination).map_or(&[], |routes| routes.as_slice())
+    }
+
+    /// ###### Adds a route, e.g. the path of a flood response.
+    /// Returns true if the route became the best one to its destination.
+    pub fn add(&mut self, destination: NodeId, route: Vec<NodeId>) -> bool {
+        if route.first() != Some(&self.source) || route.last() != Some(&destination) {
+            return false;
+        }
+
+        let routes = self.routes.entry(destination).or_default();
+        if routes.contains(&route) {
+            return false;
+        }
+        routes.push(route.clone());
+        rank(routes, &self.reliability, self.max_routes);
+        routes.first() == Some(&route)
+    }
+
+    /// ###### Records a fragment acknowledged through the route, and ranks the routes again.
+    pub fn record_delivered(&mut self, route: &[NodeId]) {
+        self.reliability.record_delivered(route);
+        self.rank_all();
+    }
+
+    /// ###### Records a fragment dropped by a drone of the route, and ranks the routes again.
+    pub fn record_dropped(&mut self, route: &[NodeId], drone: NodeId) {
+        self.reliability.record_dropped(route, drone);
+        self.rank_all();
+    }
+
+    /// ###### Returns the routes to the destination, best first, with their estimated cost.
+    pub fn route_costs(&self, destination: NodeId) -> Vec<RouteInfo> {
+        self.routes(destination)
+            .iter()
+            .map(|hops| RouteInfo {
+                destination,
+                hops: hops.clone(),
+                delivery_probability: self.reliability.delivery_probability(hops),
+                expected_transmissions: self.reliability.expected_transmissions(hops),
+            })
+            .collect()
+    }
+
+    /// ###### Drops the routes through a node that failed.
+    /// The next route of every destination takes over; returns the destinations left without routes.
+    pub fn remove_node(&mut self, node: NodeId) -> Vec<NodeId> {
+        let mut lost = Vec::new();
+        for (destination, routes) in self.routes.iter_mut() {
+            let before = routes.len();
+            routes.retain(|route| !route.contains(&node));
+            if routes.is_empty() && before > 0 {
+                lost.push(*destination);
+            }
+        }
+        lost.sort();
+        lost
+    }
+
+    /// ###### Rebuilds the routes to a destination from the topology.
+    /// The routes still valid are kept and completed with the cheapest paths sharing no intermediate node.
+    /// Returns true if the destination is reachable.
+    pub fn refresh(&mut self, destination: NodeId, topology: &Topology) -> bool {
+        let mut candidates: Vec<Vec<NodeId>> = self.routes(destination)
+            .iter()
+            .filter(|route| is_valid(route, topology))
+            .cloned()
+            .collect();
+        for path in disjoint_paths(topology, &self.reliability, self.source, destination, self.max_routes) {
+            if !candidates.contains(&path) {
+                candidates.push(path);
+            }
+        }
+
+        rank(&mut candidates, &self.reliability, self.max_routes);
+        let reachable = !candidates.is_empty();
+        self.routes.insert(destination, candidates);
+        reachable
+    }
+
+    /// ###### Rebuilds the routes to every known destination, at most once every `ROUTE_REFRESH_INTERVAL`.
+    pub fn refresh_if_due(&mut self, topology: &Topology) {
+        if self.last_refresh.elapsed() < ROUTE_REFRESH_INTERVAL {
+            return;
+        }
+        self.last_refresh = Instant::now();
+
+        let destinations: Vec<NodeId> = self.routes.keys().copied().collect();
+        for destination in destinations {
+            self.refresh(destination, topology);
+        }
+    }
+
+    fn rank_all(&mut self) {
+        for routes in self.routes.values_mut() {
+            rank(routes, &self.reliability, self.max_routes);
+        }
+    }
+}
+
+// Cheapest first; routes of the same cost keep their order, so the route in use stays in use.
+fn rank(routes: &mut Vec<Vec<NodeId>>, reliability: &Reliability, max_routes: usize) {
+    routes.sort_by(|a, b| {
+        reliability.expected_transmissions(a).total_cmp(&reliability.expected_transmissions(b))
+    });
+    routes.truncate(max_routes);
+}
+
+/// ###### Decides when a node floods the network again on its own:
+/// every `interval`, and shortly after an event asked for it, e.g. a neighbour added or removed
+/// or a route that could not be repaired.
+#[derive(Debug, Clone)]
+pub struct Rediscovery {
+    interval: Option<Duration>,     // None: no periodic flood
+    last_flood: Instant,
+    has_flooded: bool,
+    requested: bool,
+}
+
+impl Rediscovery {
+    pub fn new() -> Self {
+        Self {
+            interval: Some(DEFAULT_REDISCOVERY_INTERVAL),
+            last_flood: Instant::now(),
+            has_flooded: false,
+            requested: false,
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval;
+    }
+
+    /// ###### Records a flood, whatever started it.
+    pub fn flooded(&mut self) {
+        self.last_flood = Instant::now();
+        self.has_flooded = true;
+        self.requested = false;
+    }
+
+    /// ###### Asks for a flood as soon as possible.
+    /// Requests wait for the first flood: before it the node knows no network to update.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// ###### Returns true if the node should flood now.
+    pub fn is_due(&self) -> bool {
+        let elapsed = self.last_flood.elapsed();
+        (self.requested && self.has_flooded && elapsed >= MIN_REDISCOVERY_GAP)
+            || self.interval.is_some_and(|interval| elapsed >= interval)
+    }
+}
+
+impl Default for Rediscovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ###### Finds up to `k` paths from `source` to `destination` that share no intermediate node.
+/// Every path is the cheapest one avoiding the intermediate nodes of the paths found before it.
+pub fn disjoint_paths(topology: &Topology, reliability: &Reliability, source: NodeId, destination: NodeId, k: usize) -> Vec<Vec<NodeId>> {
+    let mut paths = Vec::new();
+    let mut excluded = HashSet::new();
+
+    while paths.len() < k {
+        let Some(path) = cheapest_path(topology, reliability, source, destination, &excluded) else {
+            break;
+        };
+        // A direct link leaves no intermediate node to exclude
+        let intermediates = intermediates(&path);
+        let is_direct = intermediates.is_empty();
+        excluded.extend(intermediates.iter().copied());
+        paths.push(path);
+        if is_direct {
+            break;
+        }
+    }
+    paths
+}
+
+/// ###### Dijkstra search of the path with the highest delivery probability, not crossing the excluded nodes.
+/// The search expands only through the nodes that forward, see [`Topology::forwards`].
+/// Crossing a drone costs `-ln(1 - drop rate)`, so the cheapest path is the most reliable one;
+/// with no observation every drone costs the same and the path with the fewest hops wins.
+pub fn cheapest_path(topology: &Topology, reliability: &Reliability, source: NodeId, destination: NodeId, excluded: &HashSet<NodeId>) -> Option<Vec<NodeId>> {
+    let mut costs: HashMap<NodeId, f64> = HashMap::from([(source, 0.0)]);
+    let mut previous: HashMap<NodeId, NodeId> = HashMap::new();
+    let mut visited: HashSet<NodeId> = HashSet::new();
+
+    // The networks are small: the next node is picked with a linear scan, ties by ID to stay deterministic
+    while let Some((current, cost)) = costs
+        .iter()
+        .filter(|(node, _)| !visited.contains(*node))
+        .min_by(|a, b| a.1.total_cmp(b.1).then(a.0.cmp(b.0)))
+        .map(|(node, cost)| (*node, *cost))
+    {
+        if current == destination {
+            let mut path = vec![destination];
+            while let Some(&node) = previous.get(path.last().unwrap()) {
+                path.push(node);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        visited.insert(current);
+
+        let Some(neighbours) = topology.neighbours(current) else {
+            continue;
+        };
+        for &neighbour in neighbours {
+            if visited.contains(&neighbour) {
+                continue;
+            }
+            // Only the destination may be a node that does not forward
+            if neighbour != destination && (excluded.contains(&neighbour) || !topology.forwards(neighbour)) {
+                continue;
+            }
+            // The destination does not forward the fragment, it cannot drop it
+            let step = if neighbour == destination { 0.0 } else { reliability.weight(neighbour) };
+            let candidate = cost + step;
+            let improves = costs.get(&neighbour).is_none_or(|&known| {
+                candidate < known || (candidate == known && previous.get(&neighbour).is_some_and(|&other| current < other))
+            });
+            if improves {
+                costs.insert(neighbour, candidate);
+                previous.insert(neighbour, current);
+            }
+        }
+    }
+    None
+}
+
+// The nodes that forward the fragments: every node of the route but the first and the last.
+fn intermediates(route: &[NodeId]) -> &[NodeId] {
+    if route.len() < 2 {
+        return &[];
+    }
+    &route[1..route.len() - 1]
+}
+
+// Every hop of the route is still a link of the topology, and every intermediate node forwards.
+fn is_valid(route: &[NodeId], topology: &Topology) -> bool {
+    route.windows(2).all(|hop| {
+        topology.neighbours(hop[0]).is_some_and(|neighbours| neighbours.contains(&hop[1]))
+    }) && intermediates(route).iter().all(|&node| topology.forwards(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use NodeType::{Client, Drone, Server};
+
+    // Client 1 reaches server 9 through drone 2, or through drones 3 and 4.
+    fn two_routes() -> Topology {
+        let mut topology = Topology::new();
+        topology.add_path(&[(1, Client), (2, Drone), (9, Server)]);
+        topology.add_path(&[(1, Client), (3, Drone), (4, Drone), (9, Server)]);
+        topology
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn unobserved_drone_drops_one_packet_in_ten() {
+        let reliability = Reliability::default();
+        assert_close(reliability.drop_rate(2), 0.1);
+        assert_close(reliability.delivery_probability(&[1, 2, 3, 9]), 0.81);
+        assert_close(reliability.expected_transmissions(&[1, 9]), 1.0);
+    }
+
+    #[test]
+    fn drops_count_only_for_the_drone_that_dropped() {
+        let mut reliability = Reliability::default();
+        reliability.record_dropped(&[1, 3, 4, 9], 4);
+        assert_close(reliability.drop_rate(3), 1.0 / 11.0);
+        assert_close(reliability.drop_rate(4), 2.0 / 11.0);
+
+        // Not on the route: ignored
+        reliability.record_dropped(&[1, 3, 4, 9], 7);
+        assert_close(reliability.drop_rate(7), 0.1);
+    }
+
+    #[test]
+    fn counts_are_halved_past_max_observations() {
+        let mut reliability = Reliability::default();
+        for _ in 0..100 {
+            reliability.record_delivered(&[1, 2, 9]);
+        }
+        assert_close(reliability.drop_rate(2), 1.0 / 110.0);
+
+        // The 101st observation halves the counts: 50 delivered, 0.5 dropped
+        reliability.record_dropped(&[1, 2, 9], 2);
+        assert_close(reliability.drop_rate(2), 1.5 / 60.5);
+    }
+
+    #[test]
+    fn fewest_hops_win_without_observations() {
+        let path = cheapest_path(&two_routes(), &Reliability::default(), 1, 9, &HashSet::new());
+        assert_eq!(path, Some(vec![1, 2, 9]));
+    }
+
+    #[test]
+    fn lossy_drone_moves_traffic_to_the_other_route() {
+        let mut routes = RouteCache::new(1);
+        assert!(routes.refresh(9, &two_routes()));
+        assert_eq!(routes.routes(9), &[vec![1, 2, 9], vec![1, 3, 4, 9]]);
+
+        for _ in 0..3 {
+            routes.record_dropped(&[1, 2, 9], 2);
+        }
+        assert_eq!(routes.best(9), Some(&vec![1, 3, 4, 9]));
+
+        let costs = routes.route_costs(9);
+        assert_eq!(costs[1].hops, vec![1, 2, 9]);
+        assert!(costs[0].expected_transmissions < costs[1].expected_transmissions);
+        assert_close(costs[1].delivery_probability, 1.0 - 4.0 / 13.0);
+    }
+
+    #[test]
+    fn next_route_takes_over_when_a_drone_fails() {
+        let mut routes = RouteCache::new(1);
+        routes.refresh(9, &two_routes());
+
+        assert!(routes.remove_node(2).is_empty());
+        assert_eq!(routes.best(9), Some(&vec![1, 3, 4, 9]));
+        assert_eq!(routes.remove_node(3), vec![9]);
+    }
+
+    #[test]
+    fn routes_never_cross_clients_or_leaf_servers() {
+        let mut topology = Topology::new();
+        topology.add_path(&[(1, Client), (2, Drone), (5, Client)]);
+        topology.add_path(&[(5, Client), (3, Drone), (9, Server)]);
+        topology.add_path(&[(2, Drone), (6, Server)]);
+        topology.add_path(&[(6, Server), (3, Drone)]);
+        assert_eq!(cheapest_path(&topology, &Reliability::default(), 1, 9, &HashSet::new()), None);
+        assert!(!RouteCache::new(1).refresh(9, &topology));
+
+        // Server 6 inside a path trace forwarded a flood, so it forwards
+        topology.add_path(&[(1, Client), (2, Drone), (6, Server), (3, Drone), (9, Server)]);
+        assert_eq!(
+            cheapest_path(&topology, &Reliability::default(), 1, 9, &HashSet::new()),
+            Some(vec![1, 2, 6, 3, 9])
+        );
+    }
+
+    #[test]
+    fn disjoint_paths_share_no_intermediate_node() {
+        let mut topology = two_routes();
+        topology.add_path(&[(1, Client), (2, Drone), (4, Drone), (9, Server)]);
+
+        let paths = disjoint_paths(&topology, &Reliability::default(), 1, 9, 3);
+        assert_eq!(paths, vec![vec![1, 2, 9], vec![1, 3, 4, 9]]);
+    }
+}
@@ -1,26 +1,30 @@
-use crossbeam_channel::{select_biased, Receiver, Sender};
+use crossbeam_channel::{Receiver, Sender};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     fmt::Debug,
 };
 use std::collections::VecDeque;
-use log::{info, warn};
-use crate::general_use::{DataScope, DisplayDataCommunicationServer, Message, Query, Response, ServerCommand, ServerEvent, ServerType};
+use log::{debug, error, info, warn};
+use crate::general_use::{unix_time, DisplayDataCommunicationServer, Message, MessageId, Presence, Query, Response, RoomMessage, RoomName, ServerCommand, ServerEvent, ServerType};
 //UI
 // use crate::ui_traits::Monitoring;    // todo: commented for test repo
 use wg_2024::{
     network::NodeId,
     packet::{
+        NodeType,
         Packet,
-        PacketType,
     },
 };
-use crate::general_use::DataScope::{UpdateAll, UpdateSelf};
+use crate::general_use::DataScope::UpdateSelf;
 use super::server::CommunicationServer as CharTrait;
 use super::server::Server as MainTrait;
+use super::server::SERVER_WINDOW_SIZE;
+use super::message_store::MessageStore;
+use crate::ids::SeenFloods;
+use crate::routing::{Rediscovery, RouteCache, Topology};
+use crate::transport::Transport;
 
 type FloodId = u64;
-type SessionId = u64;
 
 #[derive(Debug)]
 pub struct CommunicationServer{
@@ -29,15 +33,17 @@ pub struct CommunicationServer{
     pub id: NodeId,
 
     //Fragment-related
-    pub reassembling_messages: HashMap<SessionId, Vec<u8>>,
-    pub sending_messages: HashMap<SessionId, (Vec<u8>, NodeId)>,
+    pub transport: Transport,
 
     //Flood-related
     pub clients: Vec<NodeId>,                                   // Available clients
-    pub topology: HashMap<NodeId, HashSet<NodeId>>,             // Nodes and their neighbours
-    pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the servers
+    pub topology: Topology,                                     // Nodes and their neighbours
+    pub routes: RouteCache,                                     // Best and alternative routes to the clients
+    pub rediscovery: Rediscovery,                               // When to flood the network again
+    pub seen_floods: SeenFloods,                                // Flood requests already received
+    pub forward_floods: bool,                                   // False: the server is a leaf of the floods
     pub flood_ids: Vec<FloodId>,
-    pub counter: (FloodId, SessionId),
+    pub flood_counter: FloodId,
 
     //Channels
     pub to_controller_event: Sender<ServerEvent>,
@@ -47,6 +53,12 @@ pub struct CommunicationServer{
 
     //Characteristic-Server fields
     pub list_users: Vec<NodeId>,
+    pub stored_messages: MessageStore,                          // Messages waiting for their recipient
+    pub rooms: HashMap<RoomName, Vec<NodeId>>,                  // Chat rooms and their members
+    pub presence: HashMap<NodeId, Presence>,                    // Presence of every client that registered
+    pub last_seen: HashMap<NodeId, u64>,                        // Time of the last query of every client
+    pub peers: HashMap<NodeId, Vec<NodeId>>,                    // Other communication servers and their clients
+    pub server_types: HashMap<NodeId, ServerType>,              // Types of the other servers, as they answered AskType
 
     //Queries to process
     pub queries_to_process: VecDeque<(NodeId, Query)>,
@@ -64,14 +76,16 @@ impl CommunicationServer{
         CommunicationServer {
             id,
 
-            reassembling_messages: Default::default(),
-            sending_messages: Default::default(),
+            transport: Transport::new(id).with_window_size(SERVER_WINDOW_SIZE),
 
             clients: Default::default(),                                   // Available clients
             topology: Default::default(),
-            routes: Default::default(),
+            routes: RouteCache::new(id),
+            rediscovery: Rediscovery::new(),
+            seen_floods: SeenFloods::default(),
+            forward_floods: true,
             flood_ids: Default::default(),
-            counter: (0, 0),
+            flood_counter: 0,
 
             to_controller_event,
             from_controller_command,
@@ -79,10 +93,39 @@ impl CommunicationServer{
             packet_send,
 
             list_users: Vec::new(),
+            stored_messages: MessageStore::new(),
+            rooms: HashMap::new(),
+            presence: HashMap::new(),
+            last_seen: HashMap::new(),
+            peers: HashMap::new(),
+            server_types: HashMap::new(),
 
             queries_to_process: VecDeque::new(),
         }
     }
+
+    //Sending the monitoring data to the controller, with the offline messages waiting for every recipient
+    fn send_monitoring_data(&mut self) {
+        let neighbors = self.packet_send.keys().cloned().collect();
+        let routing_table = self.list_users.iter()
+            .filter_map(|client_id| self.routes.best(*client_id).map(|route| (*client_id, route.clone())))
+            .collect();
+        let display_data = DisplayDataCommunicationServer{
+            node_id: self.id,
+            node_type: "Communication Server".to_string(),
+            flood_id: self.flood_ids.last().cloned().unwrap_or(0),
+            connected_node_ids: neighbors,
+            routing_table,
+            registered_clients: self.list_users.clone(),
+            stored_messages: self.stored_messages.depth(),
+            presence: self.presence.clone(),
+            federated_clients: self.peers.clone(),
+        };
+
+        if self.to_controller_event.send(ServerEvent::CommunicationServerData(self.id, display_data, UpdateSelf)).is_err() {
+            error!("Server {}: Error sending monitoring data to controller", self.id);
+        }
+    }
 }
 
 
@@ -156,27 +199,26 @@ impl MainTrait for CommunicationServer{
     fn get_id(&self) -> NodeId{ self.id }
     fn get_server_type(&self) -> ServerType{ ServerType::Communication }
 
-    fn get_session_id(&mut self) -> u64{
-        self.counter.1 += 1;
-        self.counter.1
-    }
-
     fn get_flood_id(&mut self) -> u64{
-        self.counter.0 += 1;
-        self.counter.0
+        self.flood_counter += 1;
+        self.flood_counter
     }
 
     fn push_flood_id(&mut self, flood_id: FloodId){ self.flood_ids.push(flood_id); }
     fn get_clients(&mut self) -> &mut Vec<NodeId>{ &mut self.clients }
-    fn get_topology(&mut self) -> &mut HashMap<NodeId, HashSet<NodeId>>{ &mut self.topology }
-    fn get_routes(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>{ &mut self.routes }
+    fn get_topology(&mut self) -> &mut Topology{ &mut self.topology }
+    fn get_routes(&mut self) -> &mut RouteCache{ &mut self.routes }
+    fn get_rediscovery(&mut self) -> &mut Rediscovery{ &mut self.rediscovery }
+    fn get_seen_floods(&mut self) -> &mut SeenFloods{ &mut self.seen_floods }
+    fn get_forward_floods(&mut self) -> &mut bool{ &mut self.forward_floods }
 
     fn get_from_controller_command(&mut self) -> &mut Receiver<ServerCommand>{ &mut self.from_controller_command }
     fn get_packet_recv(&mut self) -> &mut Receiver<Packet>{ &mut self.packet_recv }
     fn get_packet_send(&mut self) -> &mut HashMap<NodeId, Sender<Packet>>{ &mut self.packet_send }
     fn get_packet_send_not_mutable(&self) -> &HashMap<NodeId, Sender<Packet>>{ &self.packet_send }
-    fn get_reassembling_messages(&mut self) -> &mut HashMap<u64, Vec<u8>>{ &mut self.reassembling_messages }
     fn process_query(&mut self, query: Query, src_id: NodeId) {
+        self.last_seen.insert(src_id, unix_time());
+
         // Check if the topology is empty and start the discovery process if it is.
         if self.topology.is_empty() {
             self.save_query_to_process(src_id, query);
@@ -186,101 +228,419 @@ impl MainTrait for CommunicationServer{
         match query {
             Query::AskType => self.give_type_back(src_id),
 
+            //A client registers and unregisters only itself
+            Query::RegisterClient(node_id) | Query::UnregisterClient(node_id) if node_id != src_id => {
+                warn!("Server {}: Node {} tried to change the registration of client {}", self.id, src_id, node_id);
+                self.send_response(src_id, Response::Err(format!("Client {} can't be changed by node {}", node_id, src_id)));
+            }
             Query::RegisterClient(node_id) => self.add_client(node_id),
+            Query::UnregisterClient(_) => self.remove_client(src_id),
             Query::AskListClients => self.give_list_back(src_id),
             Query::SendMessage(message) => self.forward_message_to(message),
+            Query::MessageDelivered(message_id, sender) => self.forward_receipt_to(sender, message_id, src_id),
+            Query::CreateRoom(room) => self.create_room(src_id, room),
+            Query::JoinRoom(room) => self.join_room(src_id, room),
+            Query::LeaveRoom(room) => self.leave_room(src_id, room),
+            Query::AskListRooms => self.give_rooms_back(src_id),
+            Query::SendRoomMessage(message) => self.forward_room_message(src_id, message),
+            //Federation queries are trusted only from the servers that said they are communication servers
+            Query::ServerDirectory(_) | Query::RelayMessage(_) | Query::RelayReceipt(..) if !self.is_communication_server(src_id) => {
+                warn!("Server {}: Federation query from node {} ignored, it is not a known communication server", self.id, src_id);
+            }
+            Query::ServerDirectory(clients) => self.update_peer_directory(src_id, clients),
+            Query::RelayMessage(message) => self.receive_relayed_message(message),
+            Query::RelayReceipt(message_id, sender, recipient) => self.receive_relayed_receipt(message_id, sender, recipient),
             _ => {}
         }
     }
-    fn get_sending_messages(&mut self) ->  &mut HashMap<u64, (Vec<u8>, u8)>{ &mut self.sending_messages }
-
-    fn get_sending_messages_not_mutable(&self) -> &HashMap<u64, (Vec<u8>, u8)>{ &self.sending_messages }
+    fn get_transport(&mut self) -> &mut Transport{ &mut self.transport }
+    fn get_to_controller_event(&self) -> &Sender<ServerEvent>{ &self.to_controller_event }
 
     fn get_queries_to_process(&mut self) -> &mut VecDeque<(NodeId, Query)>{ &mut self.queries_to_process }
+
+    fn handle_specific_command(&mut self, command: ServerCommand) {
+        match command {
+            ServerCommand::UpdateMonitoringData => self.send_monitoring_data(),
+            ServerCommand::SetMessageStore(ttl, capacity) => {
+                for message in self.stored_messages.set_limits(ttl, capacity) {
+                    warn!("Server {}: Message {} to client {} dropped, the store is full", self.id, message.get_id(), message.get_recipient());
+                }
+                info!("Server {}: Offline messages kept for {:?}, at most {} per client", self.id, ttl, capacity);
+            }
+            command => debug!("Server {}: Command {:?} not supported by communication servers", self.id, command),
+        }
+    }
+
+    //Delivering the stored messages whose recipient is reachable again, dropping the expired ones
+    fn handle_specific_tick(&mut self) {
+        self.update_presence();
+        self.drop_expired_messages();
+        for client_id in self.stored_messages.recipients() {
+            self.deliver_stored_messages(client_id);
+        }
+    }
+
+    //The directory goes only to communication servers, so the type of the new server is asked first
+    fn handle_new_server(&mut self, server_id: NodeId) {
+        debug!("Server {}: Asking the type of server {}", self.id, server_id);
+        self.send_query(server_id, Query::AskType);
+    }
+
+    //A communication server gets the directory and answers with its own
+    fn handle_server_response(&mut self, response: Response, src_id: NodeId) {
+        match response {
+            Response::ServerType(server_type) => {
+                debug!("Server {}: Server {} is a {} server", self.id, src_id, server_type);
+                self.server_types.insert(src_id, server_type);
+                if server_type == ServerType::Communication && !self.peers.contains_key(&src_id) {
+                    self.send_directory_to(src_id);
+                }
+            }
+            response => debug!("Server {}: Response {:?} from server {} ignored", self.id, response, src_id),
+        }
+    }
 }
 
 impl CharTrait for CommunicationServer {
     fn add_client(&mut self, client_id: NodeId) {
-        self.list_users.push(client_id);
+        //A client registering again is not listed twice
+        if !self.list_users.contains(&client_id) {
+            self.list_users.push(client_id);
+            self.share_directory();
+        }
 
         let response = Response::ClientRegistered;
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+        //Send response
+        self.send_response(client_id, response);
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
+        //Telling the other clients, only if the client was not already online
+        if self.presence.insert(client_id, Presence::Online) != Some(Presence::Online) {
+            self.notify_presence(client_id, Presence::Online);
         }
 
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
-
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
-
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
+        //Messages sent while the client was away
+        self.deliver_stored_messages(client_id);
     }
 
     fn give_list_back(&mut self, client_id: NodeId) {
 
-        //Get list
-        let list_clients = self.list_users.clone();
+        //Get list, with the clients of the other communication servers
+        let mut list_clients = self.list_users.clone();
+        for clients in self.peers.values() {
+            for client in clients {
+                if !list_clients.contains(client) {
+                    list_clients.push(*client);
+                }
+            }
+        }
 
         //Creating data to send
         let response = Response::ListClients(list_clients);
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+        //Send response
+        self.send_response(client_id, response);
+    }
+
+    fn forward_message_to(&mut self, message: Message) {
+
+        //Relaying the message to the communication server of the recipient
+        let recipient = message.get_recipient();
+        if !self.list_users.contains(&recipient) {
+            if let Some(peer) = self.peer_of(recipient) {
+                if self.has_route_to(peer) {
+                    info!("Server {}: Relaying message {} to server {}", self.id, message.get_id(), peer);
+                    self.send_query(peer, Query::RelayMessage(message));
+                    return;
+                }
+            }
+        }
+
+        //Keeping the message until the recipient is registered and reachable
+        if !self.list_users.contains(&recipient) || !self.routes.contains(recipient) {
+            self.store_message(message);
+            return;
+        }
+
+        //Creating data to send
+        let response = Response::MessageReceived(message);
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
+        //Send response
+        self.send_response(recipient, response);
+    }
+
+    fn forward_receipt_to(&mut self, sender: NodeId, message_id: MessageId, recipient: NodeId) {
+
+        //Relaying the receipt to the communication server of the sender
+        if !self.list_users.contains(&sender) {
+            if let Some(peer) = self.peer_of(sender) {
+                self.send_query(peer, Query::RelayReceipt(message_id, sender, recipient));
+                return;
+            }
         }
 
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
+        //Creating data to send
+        let response = Response::MessageDelivered(message_id, recipient);
 
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
+        //Send response
+        self.send_response(sender, response);
+    }
+
+    fn store_message(&mut self, message: Message) {
+        let recipient = message.get_recipient();
+        info!("Server {}: Client {} unreachable or not registered, storing message {}", self.id, recipient, message.get_id());
 
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
+        //A registered client, or the server of one, without route: looking for one
+        if self.list_users.contains(&recipient) || self.peer_of(recipient).is_some() {
+            self.rediscovery.request();
+        }
 
+        if let Some(dropped) = self.stored_messages.push(message) {
+            warn!("Server {}: Message {} to client {} dropped, the store is full", self.id, dropped.get_id(), recipient);
+        }
     }
 
-    fn forward_message_to(&mut self, message: Message) {
+    fn deliver_stored_messages(&mut self, client_id: NodeId) {
+        //Client registered on another communication server
+        if !self.list_users.contains(&client_id) {
+            let Some(peer) = self.peer_of(client_id) else {
+                return;
+            };
+            if !self.has_route_to(peer) {
+                return;
+            }
+            for message in self.stored_messages.take(client_id) {
+                info!("Server {}: Relaying stored message {} to server {}", self.id, message.get_id(), peer);
+                self.send_query(peer, Query::RelayMessage(message));
+            }
+            return;
+        }
+
+        if !self.routes.contains(client_id) {
+            return;
+        }
+
+        for message in self.stored_messages.take(client_id) {
+            info!("Server {}: Delivering stored message {} to client {}", self.id, message.get_id(), client_id);
+            self.send_response(client_id, Response::MessageReceived(message));
+        }
+    }
+
+    fn drop_expired_messages(&mut self) {
+        for message in self.stored_messages.remove_expired() {
+            warn!("Server {}: Message {} to client {} expired before delivery", self.id, message.get_id(), message.get_recipient());
+        }
+    }
+
+    fn create_room(&mut self, client_id: NodeId, room: RoomName) {
+        if self.rooms.contains_key(&room) {
+            self.send_response(client_id, Response::Err(format!("Room {} already exists", room)));
+            return;
+        }
+
+        //The creator is the first member
+        info!("Server {}: Client {} created room {}", self.id, client_id, room);
+        self.rooms.insert(room.clone(), Vec::new());
+        self.join_room(client_id, room);
+    }
+
+    fn join_room(&mut self, client_id: NodeId, room: RoomName) {
+        let Some(members) = self.rooms.get_mut(&room) else {
+            self.send_response(client_id, Response::Err(format!("Room {} does not exist", room)));
+            return;
+        };
+
+        //A member joining again is only told that it is in the room
+        if members.contains(&client_id) {
+            self.send_response(client_id, Response::JoinedRoom(room, client_id));
+            return;
+        }
+        members.push(client_id);
+
+        //Join notice to every member
+        info!("Server {}: Client {} joined room {}", self.id, client_id, room);
+        let notice = Response::JoinedRoom(room.clone(), client_id);
+        self.send_to_members(&room, notice, None);
+    }
+
+    fn leave_room(&mut self, client_id: NodeId, room: RoomName) {
+        let is_member = self.rooms.get(&room).is_some_and(|members| members.contains(&client_id));
+        if !is_member {
+            self.send_response(client_id, Response::Err(format!("Not a member of room {}", room)));
+            return;
+        }
+
+        //Leave notice to every member, before the client is removed so it gets the notice too
+        info!("Server {}: Client {} left room {}", self.id, client_id, room);
+        let notice = Response::LeftRoom(room.clone(), client_id);
+        self.send_to_members(&room, notice, None);
+
+        if let Some(members) = self.rooms.get_mut(&room) {
+            members.retain(|&member| member != client_id);
+        }
+    }
+
+    fn give_rooms_back(&mut self, client_id: NodeId) {
+
+        //Get list
+        let mut list_rooms: Vec<RoomName> = self.rooms.keys().cloned().collect();
+        list_rooms.sort();
 
         //Creating data to send
-        let response = Response::MessageReceived(message.clone());
+        let response = Response::ListRooms(list_rooms);
+
+        //Send response
+        self.send_response(client_id, response);
+    }
+
+    fn forward_room_message(&mut self, client_id: NodeId, message: RoomMessage) {
+        let room = message.get_room().to_string();
+        let is_member = self.rooms.get(&room).is_some_and(|members| members.contains(&client_id));
+        if !is_member {
+            self.send_response(client_id, Response::Err(format!("Not a member of room {}", room)));
+            return;
+        }
+
+        //Every member but the sender gets the message
+        let response = Response::RoomMessageReceived(message);
+        self.send_to_members(&room, response, Some(client_id));
+    }
+
+    fn remove_client(&mut self, client_id: NodeId) {
+        if !self.list_users.contains(&client_id) {
+            self.send_response(client_id, Response::Err("Not registered".to_string()));
+            return;
+        }
+
+        //Leaving every room, the other members get the leave notice
+        let mut joined_rooms: Vec<RoomName> = self.rooms.iter()
+            .filter(|(_, members)| members.contains(&client_id))
+            .map(|(room, _)| room.clone())
+            .collect();
+        joined_rooms.sort();
+        for room in joined_rooms {
+            self.leave_room(client_id, room);
+        }
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
+        self.list_users.retain(|&user| user != client_id);
+        self.share_directory();
+        info!("Server {}: Client {} unregistered", self.id, client_id);
 
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
+        //Send response
+        self.send_response(client_id, Response::ClientUnregistered);
+
+        //Telling the other clients
+        let presence = Presence::Unregistered(unix_time());
+        self.presence.insert(client_id, presence);
+        self.notify_presence(client_id, presence);
+    }
+
+    //A registered client is online while the server has a route to it
+    fn update_presence(&mut self) {
+        for client_id in self.list_users.clone() {
+            let reachable = self.routes.contains(client_id);
+            let presence = match self.presence.get(&client_id) {
+                Some(Presence::Online) if !reachable => {
+                    Presence::LastSeen(self.last_seen.get(&client_id).copied().unwrap_or_else(unix_time))
+                }
+                Some(Presence::LastSeen(_)) if reachable => Presence::Online,
+                _ => continue,
+            };
+
+            info!("Server {}: Client {} is now {}", self.id, client_id, presence);
+            self.presence.insert(client_id, presence);
+            self.notify_presence(client_id, presence);
         }
+    }
 
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(message.get_recipient());
-        let header = Self::create_source_routing(route);
+    //Sending the presence of the client to every other registered client the server can reach
+    fn notify_presence(&mut self, client_id: NodeId, presence: Presence) {
+        for user in self.list_users.clone() {
+            if user == client_id || !self.routes.contains(user) {
+                continue;
+            }
+            self.send_response(user, Response::PresenceChanged(client_id, presence));
+        }
+    }
 
-        // Generating fragment
-        let session_id = self.generate_unique_session_id();
+    fn send_directory_to(&mut self, server_id: NodeId) {
+        debug!("Server {}: Sending the client directory to server {}", self.id, server_id);
+        let directory = Query::ServerDirectory(self.list_users.clone());
+        self.send_query(server_id, directory);
+    }
 
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
+    //Sending the client directory to every other communication server, after a registration change
+    fn share_directory(&mut self) {
+        let mut peers: Vec<NodeId> = self.peers.keys().copied().collect();
+        peers.sort();
+        for peer in peers {
+            self.send_directory_to(peer);
+        }
+    }
+
+    fn update_peer_directory(&mut self, server_id: NodeId, clients: Vec<NodeId>) {
+        info!("Server {}: Server {} has clients {:?}", self.id, server_id, clients);
+
+        //A new communication server gets the directory back, an update does not need an answer
+        if self.peers.insert(server_id, clients).is_none() {
+            self.send_directory_to(server_id);
+        }
+    }
+
+    //Only a server of the topology that answered AskType as a communication server, never a client
+    fn is_communication_server(&self, node_id: NodeId) -> bool {
+        self.topology.node_type(node_id) == Some(NodeType::Server)
+            && self.server_types.get(&node_id) == Some(&ServerType::Communication)
+    }
+
+    //Communication server a client is registered on, the one with the lowest ID if many
+    fn peer_of(&self, client_id: NodeId) -> Option<NodeId> {
+        self.peers.iter()
+            .filter(|(_, clients)| clients.contains(&client_id))
+            .map(|(peer, _)| *peer)
+            .min()
+    }
+
+    //Looking for a route in the topology if there is none
+    fn has_route_to(&mut self, node_id: NodeId) -> bool {
+        if !self.routes.contains(node_id) {
+            self.routes.refresh(node_id, &self.topology);
+        }
+        self.routes.contains(node_id)
+    }
+
+    //The recipient may have left in the meantime: the message waits in the store like any other
+    fn receive_relayed_message(&mut self, message: Message) {
+        let recipient = message.get_recipient();
+        if self.list_users.contains(&recipient) && self.routes.contains(recipient) {
+            self.send_response(recipient, Response::MessageReceived(message));
+        } else {
+            self.store_message(message);
+        }
+    }
+
+    fn receive_relayed_receipt(&mut self, message_id: MessageId, sender: NodeId, recipient: NodeId) {
+        if !self.list_users.contains(&sender) {
+            warn!("Server {}: Receipt of message {} for client {}, not registered here", self.id, message_id, sender);
+            return;
+        }
+        self.send_response(sender, Response::MessageDelivered(message_id, recipient));
+    }
+
+    fn send_to_members(&mut self, room: &str, response: Response, skip: Option<NodeId>) {
+        let members = self.rooms.get(room).cloned().unwrap_or_default();
+        for member in members {
+            if Some(member) == skip {
+                continue;
+            }
+
+            //Without a route the member misses the response, looking for one
+            if !self.routes.contains(member) {
+                warn!("Server {}: No route to client {}, member of room {}", self.id, member, room);
+                self.rediscovery.request();
+                continue;
+            }
+            self.send_response(member, response.clone());
+        }
     }
 }
@@ -29,6 +29,21 @@ pub fn choose_random_texts() -> Vec<(String, String)> {
     vec_files
 }
 
+pub fn get_texts(titles: &[String]) -> Vec<(String, String)> {
+    TEXT.iter()
+        .filter(|(title, _)| titles.iter().any(|chosen_title| chosen_title == title))
+        .map(|(title, text)| (title.to_string(), text.to_string()))
+        .collect()
+}
+
+pub fn get_all_texts() -> Vec<(String, String)> {
+    TEXT.iter().map(|(title, text)| (title.to_string(), text.to_string())).collect()
+}
+
+pub fn has_text(title: &str) -> bool {
+    TEXT.iter().any(|(text_title, _)| *text_title == title)
+}
+
 pub fn get_media(vec_files: Vec<(String, String)>) -> HashMap<String, String> {
     IMAGE_PATHS.iter().filter_map(|(title, ref_s, media)| {
         // Check if the `title` exists in `vec_files`
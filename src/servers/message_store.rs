@@ -0,0 +1,102 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::general_use::{ClientId, Message};
+
+//Time a message waits for its recipient by default
+pub const DEFAULT_MESSAGE_TTL: Duration = Duration::from_secs(300);
+//Messages kept for every recipient by default, the oldest are dropped first
+pub const DEFAULT_MESSAGE_CAPACITY: usize = 100;
+
+//Messages waiting for a recipient that is unreachable or not registered, in arrival order
+#[derive(Debug)]
+pub struct MessageStore {
+    ttl: Option<Duration>,                                      // None: the messages never expire
+    capacity: usize,                                            // Messages kept for every recipient
+    queues: HashMap<ClientId, VecDeque<(Instant, Message)>>,
+}
+
+impl Default for MessageStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageStore {
+    pub fn new() -> Self {
+        Self {
+            ttl: Some(DEFAULT_MESSAGE_TTL),
+            capacity: DEFAULT_MESSAGE_CAPACITY,
+            queues: HashMap::new(),
+        }
+    }
+
+    //Changing the limits, returns the messages dropped to respect the new capacity
+    pub fn set_limits(&mut self, ttl: Option<Duration>, capacity: usize) -> Vec<Message> {
+        self.ttl = ttl;
+        self.capacity = capacity;
+
+        let mut dropped = Vec::new();
+        for queue in self.queues.values_mut() {
+            while queue.len() > capacity {
+                if let Some((_, message)) = queue.pop_front() {
+                    dropped.push(message);
+                }
+            }
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+        dropped
+    }
+
+    //Storing the message, returns the oldest message of the recipient if it was dropped to make room
+    pub fn push(&mut self, message: Message) -> Option<Message> {
+        if self.capacity == 0 {
+            return Some(message);
+        }
+
+        let queue = self.queues.entry(message.get_recipient()).or_default();
+        let dropped = if queue.len() >= self.capacity {
+            queue.pop_front().map(|(_, message)| message)
+        } else {
+            None
+        };
+        queue.push_back((Instant::now(), message));
+        dropped
+    }
+
+    pub fn recipients(&self) -> Vec<ClientId> {
+        self.queues.keys().copied().collect()
+    }
+
+    //Taking every message waiting for the recipient, oldest first
+    pub fn take(&mut self, recipient: ClientId) -> Vec<Message> {
+        self.queues
+            .remove(&recipient)
+            .map(|queue| queue.into_iter().map(|(_, message)| message).collect())
+            .unwrap_or_default()
+    }
+
+    //Removing the messages older than the TTL, returns them
+    pub fn remove_expired(&mut self) -> Vec<Message> {
+        let Some(ttl) = self.ttl else {
+            return Vec::new();
+        };
+
+        let mut expired = Vec::new();
+        for queue in self.queues.values_mut() {
+            //Messages are in arrival order, so the expired ones are at the front
+            while queue.front().is_some_and(|(stored_at, _)| stored_at.elapsed() >= ttl) {
+                if let Some((_, message)) = queue.pop_front() {
+                    expired.push(message);
+                }
+            }
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+        expired
+    }
+
+    //Messages waiting for every recipient, for the monitoring
+    pub fn depth(&self) -> HashMap<ClientId, usize> {
+        self.queues.iter().map(|(recipient, queue)| (*recipient, queue.len())).collect()
+    }
+}
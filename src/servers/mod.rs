@@ -1,6 +1,7 @@
 pub(crate) mod communication_server;
 pub(crate) mod content;
 pub(crate) mod media_server;
+pub(crate) mod message_store;
 pub(crate) mod text_server;
 
 pub(crate) mod server;
\ No newline at end of file
@@ -1,7 +1,7 @@
 //I am a god
 
-use crossbeam_channel::{select_biased, Receiver, Sender};
-use std::collections::{HashMap, HashSet, VecDeque};
+use crossbeam_channel::{select_biased, tick, Receiver, Sender};
+use std::collections::{HashMap, VecDeque};
 use log::{debug, error, info, warn};
 use wg_2024::{
     network::{NodeId, SourceRoutingHeader},
@@ -9,7 +9,13 @@ use wg_2024::{
         Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
     },
 };
-use crate::general_use::{FloodId, Message, Query, Response, ServerCommand, ServerType};
+use crate::general_use::{FloodId, Message, MessageId, Presence, Query, Response, RoomMessage, RoomName, ServerCommand, ServerEvent, ServerType};
+use crate::ids::{unique_id, SeenFloods};
+use crate::routing::{Rediscovery, RouteCache, Topology};
+use crate::transport::{deserialize, Reassembly, Transport, RETRANSMISSION_TICK};
+
+//Servers send every fragment of a response at once
+pub const SERVER_WINDOW_SIZE: usize = usize::MAX;
 
 
 ///SERVER TRAIT
@@ -17,69 +23,130 @@ pub trait Server{
     fn get_id(&self) -> NodeId;
     fn get_server_type(&self) -> ServerType;
 
-    fn get_session_id(&mut self) -> u64;
     fn get_flood_id(&mut self) -> u64;
 
     fn push_flood_id(&mut self, flood_id: FloodId);
     fn get_clients(&mut self) -> &mut Vec<NodeId>;
-    fn get_topology(&mut self) -> &mut HashMap<NodeId, HashSet<NodeId>>;
-    fn get_routes(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>;
+    fn get_topology(&mut self) -> &mut Topology;
+    fn get_routes(&mut self) -> &mut RouteCache;
+    fn get_rediscovery(&mut self) -> &mut Rediscovery;
+    fn get_seen_floods(&mut self) -> &mut SeenFloods;
+    fn get_forward_floods(&mut self) -> &mut bool;
 
     fn get_from_controller_command(&mut self) -> &mut Receiver<ServerCommand>;
     fn get_packet_recv(&mut self) -> &mut Receiver<Packet>;
     fn get_packet_send(&mut self) -> &mut HashMap<NodeId, Sender<Packet>>;
     fn get_packet_send_not_mutable(&self) -> &HashMap<NodeId, Sender<Packet>>;
 
-    fn get_reassembling_messages(&mut self) -> &mut HashMap<u64, Vec<u8>>;
     fn process_query(&mut self, query: Query, src_id: NodeId);
-    fn get_sending_messages(&mut self) -> &mut HashMap<u64, (Vec<u8>, u8)>;
-    fn get_sending_messages_not_mutable(&self) -> &HashMap<u64, (Vec<u8>, u8)>;
+    fn get_transport(&mut self) -> &mut Transport;
+    fn get_to_controller_event(&self) -> &Sender<ServerEvent>;
 
     fn get_queries_to_process(&mut self) -> &mut VecDeque<(NodeId, Query)>;
 
+    //Commands of a single kind of server, the others ignore them
+    fn handle_specific_command(&mut self, command: ServerCommand) {
+        debug!("Server {}: Command {:?} not supported by {} servers", self.get_id(), command, self.get_server_type());
+    }
+
+    //Periodic work of a single kind of server, nothing by default
+    fn handle_specific_tick(&mut self) {}
+
+    //First route to another server, nothing by default
+    fn handle_new_server(&mut self, _server_id: NodeId) {}
+
+    //Responses to the queries sent to other servers, ignored by default
+    fn handle_server_response(&mut self, response: Response, src_id: NodeId) {
+        debug!("Server {}: Response {:?} from node {} ignored", self.get_id(), response, src_id);
+    }
+
     fn run(&mut self) {
         info!("Running {} server with ID: {}", self.get_server_type(), self.get_id());
+        let ticker = tick(RETRANSMISSION_TICK);
         loop {
             select_biased! {
                 recv(self.get_from_controller_command()) -> command_res => {
-                    if let Ok(command) = command_res {
-                        info!("Server {}: Received command: {:?}", self.get_id(), command);
-                        match command {
-                            ServerCommand::AddSender(id, sender) => {
-                                self.get_packet_send().insert(id, sender);
-                                info!("Server {}: Added sender for node {}", self.get_id(), id);
-
-                            }
-                            ServerCommand::Discover => {
-                                self.discover();
-                            }
-                            ServerCommand::RemoveSender(id) => {
-                                self.get_packet_send().remove(&id);
-                                self.update_topology_and_routes(id);
-                                info!("Server {}: Removed sender for node {}", self.get_id(), id);
-                            }
-                            ServerCommand::ShortcutPacket(packet) => {
-                                info!("Server {}: Shortcut packet received from SC: {:?}", self.get_id(), packet);
-                                self.handle_packet(packet);
-                            }
-                            _ => {},
+                    let Ok(command) = command_res else {
+                        info!("Server {}: Controller disconnected, stopping", self.get_id());
+                        return;
+                    };
+                    info!("Server {}: Received command: {:?}", self.get_id(), command);
+                    match command {
+                        ServerCommand::AddSender(id, sender) => {
+                            self.get_packet_send().insert(id, sender);
+                            //The new neighbour may open shorter routes
+                            self.get_rediscovery().request();
+                            info!("Server {}: Added sender for node {}", self.get_id(), id);
+
+                        }
+                        ServerCommand::Discover => {
+                            self.discover();
+                        }
+                        ServerCommand::RemoveSender(id) => {
+                            self.get_packet_send().remove(&id);
+                            self.update_topology_and_routes(id);
+                            self.get_rediscovery().request();
+                            info!("Server {}: Removed sender for node {}", self.get_id(), id);
                         }
+                        ServerCommand::ShortcutPacket(packet) => {
+                            info!("Server {}: Shortcut packet received from SC: {:?}", self.get_id(), packet);
+                            self.handle_packet(packet);
+                        }
+                        ServerCommand::RequestRoutes(destination) => {
+                            self.send_route_costs(destination);
+                        }
+                        ServerCommand::SetFloodForwarding(forward_floods) => {
+                            *self.get_forward_floods() = forward_floods;
+                            info!("Server {}: Flood forwarding set to {}", self.get_id(), forward_floods);
+                        }
+                        ServerCommand::SetRediscoveryInterval(interval) => {
+                            self.get_rediscovery().set_interval(interval);
+                            info!("Server {}: Rediscovery interval set to {:?}", self.get_id(), interval);
+                        }
+                        ServerCommand::Shutdown => {
+                            info!("Server {}: Shutting down", self.get_id());
+                            return;
+                        }
+                        command => self.handle_specific_command(command),
                     }
                 },
                 recv(self.get_packet_recv()) -> packet_res => {
-                    if let Ok(packet) = packet_res {
-                        info!("Server {}: Received packet: {:?}", self.get_id(), packet);
-                        self.handle_packet(packet)
-                    }
+                    let Ok(packet) = packet_res else {
+                        info!("Server {}: Every packet sender disconnected, stopping", self.get_id());
+                        return;
+                    };
+                    info!("Server {}: Received packet: {:?}", self.get_id(), packet);
+                    self.handle_packet(packet)
+                },
+                recv(ticker) -> _ => {
+                    self.resend_expired_fragments();
+                    self.refresh_routes();
+                    self.rediscover_if_due();
+                    self.handle_specific_tick();
                 },
             }
         }
     }
 
     fn handle_packet(&mut self, packet: Packet) {
+        //Packets crossing the server on their way to another node, e.g. the responses of the floods it forwarded
+        let is_crossing = packet.routing_header.destination().is_some_and(|destination| destination != self.get_id());
+        if is_crossing && !matches!(packet.pack_type, PacketType::FloodRequest(_)) {
+            if *self.get_forward_floods() {
+                self.forward_packet(packet);
+            } else {
+                self.refuse_crossing_packet(packet);
+            }
+            return;
+        }
+
         match packet.pack_type {
-            PacketType::Nack(nack) => self.handle_nack(nack, packet.session_id),
-            PacketType::Ack(ack) => self.handle_ack(ack),
+            PacketType::Nack(nack) => {
+                //The NACK starts at the node that sent it
+                let source = packet.routing_header.hops.first().copied().unwrap_or_default();
+                self.handle_nack(nack, packet.session_id, source)
+            },
+            PacketType::Ack(ack) => self.handle_ack(ack, packet.session_id),
             PacketType::MsgFragment(fragment) => self.handle_fragment(fragment, packet.routing_header ,packet.session_id),
             PacketType::FloodRequest(flood_request) => self.handle_flood_request(flood_request, packet.session_id),
             PacketType::FloodResponse(flood_response) => self.handle_flood_response(flood_response),
@@ -87,11 +154,11 @@ pub trait Server{
     }
 
     //FLOOD
+    //The known topology and routes are kept and the flood responses merged into them,
+    //so the responses being sent keep their routes while the discovery runs
     fn discover(&mut self) {
         info!("Server {}: Starting discovery process", self.get_id());
-
-        self.get_routes().clear();
-        self.get_topology().clear();
+        self.get_rediscovery().flooded();
 
         let flood_id = self.generate_unique_flood_id();
         self.push_flood_id(flood_id);
@@ -113,17 +180,56 @@ pub trait Server{
         );
 
         // Attempt to send the flood request to all neighbors.
-        for (_, sender_channel) in self.get_packet_send_not_mutable() {
-            sender_channel.send(packet.clone()).expect("This error message shouldn't come out, (flooding error from server)");
+        let mut unreachable = Vec::new();
+        for (id, sender_channel) in self.get_packet_send_not_mutable() {
+            if sender_channel.send(packet.clone()).is_err() {
+                unreachable.push(*id);
+            }
+        }
+        for id in unreachable {
+            warn!("Server {}: Error sending flood request to node {}", self.get_id(), id);
+            self.remove_unreachable_neighbour(id);
         }
     }
 
+    //Flooding rules of the drones: the first copy of a flood is forwarded to every neighbour but the sender,
+    //copies already seen and floods with nowhere to go are answered. Servers set as leaves answer every flood.
     fn handle_flood_request(&mut self, mut flood_request: FloodRequest, session_id: u64) {
         debug!("Server {}: Handling flood request: {:?}", self.get_id(), flood_request);
 
+        //Own flood coming back through a loop
+        if flood_request.initiator_id == self.get_id() {
+            return;
+        }
+
+        let sender = flood_request.path_trace.last().map(|(id, _)| *id);
+        let first_time = self.get_seen_floods().first_time(flood_request.initiator_id, flood_request.flood_id);
+
         //Inserting self in flood request
         flood_request.increment(self.get_id(), NodeType::Server);
 
+        let neighbours: Vec<NodeId> = self
+            .get_packet_send_not_mutable()
+            .keys()
+            .filter(|id| Some(**id) != sender)
+            .copied()
+            .collect();
+
+        //Forwarding
+        if first_time && *self.get_forward_floods() && !neighbours.is_empty() {
+            let packet = Packet::new_flood_request(SourceRoutingHeader::empty_route(), session_id, flood_request);
+            for neighbour in neighbours {
+                let sent = self.get_packet_send_not_mutable()
+                    .get(&neighbour)
+                    .is_some_and(|sender_channel| sender_channel.send(packet.clone()).is_ok());
+                if !sent {
+                    warn!("Server {}: Error forwarding flood request to node {}", self.get_id(), neighbour);
+                    self.remove_unreachable_neighbour(neighbour);
+                }
+            }
+            return;
+        }
+
         //Creating and sending flood response
         let mut response = flood_request.generate_response(session_id);
         response.routing_header.increase_hop_index();
@@ -136,21 +242,15 @@ pub trait Server{
         let path = &flood_response.path_trace;
 
         self.update_routes_to_clients(path);
+        self.update_routes_to_servers(path);
         self.update_topology(path);
     }
 
     fn update_routes_to_clients(&mut self, path: &[(NodeId, NodeType)]) {
         if let Some((id, NodeType::Client)) = path.last() {
-            if self
-                .get_routes()
-                .get(id)
-                .map_or(true, |prev_path| prev_path.len() > path.len())
-            {
-                // Update the routing table with the new, shorter path.
-                self.get_routes().insert(
-                    *id,
-                    path.iter().map(|entry| entry.0.clone()).collect(),
-                );
+            // Every path is kept as an alternative route, only a new best route is worth logging
+            let route = path.iter().map(|entry| entry.0).collect();
+            if self.get_routes().add(*id, route) {
                 info!("Server {}: Updated route to client {}: {:?}", self.get_id(), id, path);
 
                 // Resend responses that were waiting for the route to the client.
@@ -161,12 +261,30 @@ pub trait Server{
         }
     }
 
+    //Servers forward the floods, so they can be anywhere in the path and not only at its end
+    fn update_routes_to_servers(&mut self, path: &[(NodeId, NodeType)]) {
+        for (index, (id, node_type)) in path.iter().enumerate() {
+            if *node_type != NodeType::Server || *id == self.get_id() {
+                continue;
+            }
+
+            let is_new = !self.get_routes().contains(*id);
+            let route = path[..=index].iter().map(|entry| entry.0).collect();
+            if self.get_routes().add(*id, route) {
+                info!("Server {}: Updated route to server {}: {:?}", self.get_id(), id, &path[..=index]);
+            }
+            if is_new && self.get_routes().contains(*id) {
+                self.handle_new_server(*id);
+            }
+        }
+    }
+
     fn reprocess_query(&mut self) {
         let queries = self.get_queries_to_process().clone();
 
         for (client_id, response) in queries {
 
-            if !self.get_routes().contains_key(&client_id) {
+            if !self.get_routes().contains(client_id) {
                 return;
             }
 
@@ -177,25 +295,11 @@ pub trait Server{
 
     fn update_topology(&mut self, path: &[(NodeId, NodeType)]) {
         info!("Server {}: Updating topology with path: {:?}", self.get_id(), path);
-        for i in 0..path.len() - 1 {
-            let current = path[i].0;
-            let next = path[i + 1].0;
-
-            // Add the connection between the current and next node in both directions.
-            self.get_topology()
-                .entry(current)
-                .or_insert_with(HashSet::new)
-                .insert(next);
-
-            self.get_topology()
-                .entry(next)
-                .or_insert_with(HashSet::new)
-                .insert(current);
-        }
+        self.get_topology().add_path(path);
     }
 
     //NACK
-    fn handle_nack(&mut self, nack: Nack, session_id: u64){
+    fn handle_nack(&mut self, nack: Nack, session_id: u64, source: NodeId){
         debug!("Server {}: Handling NACK for session {}: {:?}", self.get_id(), session_id, nack);
 
         match nack.nack_type {
@@ -203,6 +307,10 @@ pub trait Server{
                 self.send_again_fragment(session_id, nack.fragment_index);
             },
             NackType::Dropped => {
+                //The drone that dropped the fragment is the source of the NACK
+                if let Some(route) = self.get_transport().route(session_id).map(<[NodeId]>::to_vec) {
+                    self.get_routes().record_dropped(&route, source);
+                }
                 self.send_again_fragment(session_id, nack.fragment_index);
             },
             NackType::DestinationIsDrone => {
@@ -214,17 +322,29 @@ pub trait Server{
             }
         }
     }
-    fn send_nack(&self, nack: Nack, routing_header: SourceRoutingHeader, session_id: u64){
-        let packet= Self::create_packet(PacketType::Nack(nack), routing_header, session_id);
+    fn send_nack(&mut self, nack: Nack, routing_header: SourceRoutingHeader, session_id: u64){
+        let mut packet= Self::create_packet(PacketType::Nack(nack), routing_header, session_id);
+        packet.routing_header.increase_hop_index();
         self.send_packet(packet);
     }
 
     //ACK
-    fn handle_ack(&mut self, _ack: Ack){
-        //UI stuff I guess?
+    fn handle_ack(&mut self, ack: Ack, session_id: u64){
+        //Every drone of the route forwarded the fragment
+        if let Some(route) = self.get_transport().route(session_id).map(<[NodeId]>::to_vec) {
+            self.get_routes().record_delivered(&route);
+        }
+
+        //Every fragment acknowledged: the transport forgets the message
+        if self.get_transport().handle_ack(session_id, ack.fragment_index).is_some() {
+            debug!("Server {}: All fragments acknowledged for session {}", self.get_id(), session_id);
+        }
+
+        //Sending the fragments that entered the window
+        self.send_transport_packets();
     }
 
-    fn send_ack(&self, ack: Ack, routing_header: SourceRoutingHeader, session_id: u64) {
+    fn send_ack(&mut self, ack: Ack, routing_header: SourceRoutingHeader, session_id: u64) {
         let mut packet= Self::create_packet(PacketType::Ack(ack), routing_header, session_id);
         packet.routing_header.increase_hop_index();
         self.send_packet(packet);
@@ -239,23 +359,103 @@ pub trait Server{
         }
     }
 
-    fn send_packet(&self, packet: Packet) {
-        let first_carrier = self
-            .get_packet_send_not_mutable()
-            .get(&packet.routing_header.hops[1])
-            .unwrap();
-        first_carrier.send(packet).unwrap();
+    //Forwarding like a drone does, without dropping: a fragment with no way forward is nacked back to its source
+    fn forward_packet(&mut self, mut packet: Packet) {
+        packet.routing_header.increase_hop_index();
+        let Some(next_hop) = packet.routing_header.current_hop() else {
+            warn!("Server {}: No next hop for packet of session {}", self.get_id(), packet.session_id);
+            return;
+        };
+
+        if let Some(sender_channel) = self.get_packet_send_not_mutable().get(&next_hop) {
+            if sender_channel.send(packet).is_err() {
+                warn!("Server {}: Error forwarding packet to node {}", self.get_id(), next_hop);
+                self.remove_unreachable_neighbour(next_hop);
+            }
+            return;
+        }
+
+        warn!("Server {}: Node {} is not a neighbour, packet of session {} not forwarded", self.get_id(), next_hop, packet.session_id);
+        if let PacketType::MsgFragment(fragment) = &packet.pack_type {
+            //Route back to the source, from this server
+            let mut routing_header = packet.routing_header.clone();
+            routing_header.hops.truncate(routing_header.hop_index);
+            let nack = Nack {
+                fragment_index: fragment.fragment_index,
+                nack_type: NackType::ErrorInRouting(next_hop),
+            };
+            self.send_nack(nack, routing_header.get_reversed(), packet.session_id);
+        }
     }
 
-    fn find_path_to(&mut self, destination_id: NodeId) -> Vec<NodeId>{
-        self.get_routes().get(&destination_id).unwrap().clone()
+    //Leaf servers do not forward: a fragment crossing them is nacked back so its source picks another route,
+    //the other packets are dropped since they only cross a server that forwards floods
+    fn refuse_crossing_packet(&mut self, packet: Packet) {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            debug!("Server {}: Not forwarding packet of session {}", self.get_id(), packet.session_id);
+            return;
+        };
+
+        //Route back to the source, from this server
+        let mut routing_header = packet.routing_header.clone();
+        routing_header.hops.truncate(routing_header.hop_index + 1);
+        let nack = Nack {
+            fragment_index: fragment.fragment_index,
+            nack_type: NackType::UnexpectedRecipient(self.get_id()),
+        };
+        self.send_nack(nack, routing_header.get_reversed(), packet.session_id);
     }
 
-    fn create_source_routing(route: Vec<NodeId>) -> SourceRoutingHeader{
-        SourceRoutingHeader {
-            hop_index: 1,
-            hops: route,
+    //The packet goes to the current hop of its route, returns false if that neighbour can't be reached
+    fn send_packet(&mut self, packet: Packet) -> bool {
+        let Some(next_hop) = packet.routing_header.current_hop() else {
+            warn!("Server {}: No next hop for packet of session {}", self.get_id(), packet.session_id);
+            return false;
+        };
+
+        let sent = match self.get_packet_send_not_mutable().get(&next_hop) {
+            Some(sender_channel) => sender_channel.send(packet).is_ok(),
+            None => false,
+        };
+        if !sent {
+            warn!("Server {}: Node {} can't be reached, packet not sent", self.get_id(), next_hop);
+            self.remove_unreachable_neighbour(next_hop);
         }
+        sent
+    }
+
+    //A neighbour whose channel is gone (removed or crashed) is treated like a RemoveSender: the routes through it are repaired
+    fn remove_unreachable_neighbour(&mut self, id: NodeId) {
+        if self.get_packet_send().remove(&id).is_none() && self.get_topology().neighbours(id).is_none() {
+            return;
+        }
+        self.update_topology_and_routes(id);
+        self.get_rediscovery().request();
+    }
+
+    //Packets leave the transport with hop index 0. When the first hop can't be reached the session is moved
+    //to the repaired route, and the timer resends the fragments on it
+    fn send_transport_packets(&mut self) {
+        let packets = self.get_transport().take_packets();
+        for mut packet in packets {
+            packet.routing_header.increase_hop_index();
+            let session_id = packet.session_id;
+            if self.send_packet(packet) {
+                continue;
+            }
+
+            let Some(destination) = self.get_transport().destination(session_id) else {
+                continue;
+            };
+            let route = self.find_path_to(destination);
+            if !route.is_empty() {
+                self.get_transport().set_route(session_id, route);
+            }
+        }
+    }
+
+    fn find_path_to(&mut self, destination_id: NodeId) -> Vec<NodeId>{
+        self.get_routes().best(destination_id).cloned().unwrap_or_default()
     }
 
     //FRAGMENT TO DECIDE IF IMPLEMENTING DEFAULT FOR EACH ONE
@@ -270,83 +470,40 @@ pub trait Server{
             };
             self.send_nack(nack, routing_header.get_reversed(), session_id);
             return;
-        }else{
-            // Send Ack
-            let ack = Ack {
-                fragment_index: fragment.fragment_index,
-            };
-            self.send_ack(ack, routing_header.get_reversed(), session_id);
         }
 
         info!("Handling Fragment {:?}", fragment);
 
-        //Getting vec of data from fragment
-        let mut data_to_add :Vec<u8> = fragment.data.to_vec();
-        data_to_add.truncate(fragment.length as usize);
-
-        //Fragment reassembly
-        // Check if it exists already
-        if let Some(reassembling_message) = self.get_reassembling_messages().get_mut(&session_id) {
-
-            // Check for valid fragment index and length
-            let offset = reassembling_message.len();
-            if offset + fragment.length as usize > reassembling_message.capacity()
-            {
-                info!("Nack");
-                //Maybe cancelling also message in reassembling_message
-                // ... error handling logic ...
-                return;
-
-            }else{
-                // Copy data to the correct offset in the vector.
-                reassembling_message.splice(((fragment.fragment_index*128) as usize)..((fragment.fragment_index*128) as usize), data_to_add);
-
-                // Check if all fragments have been received
-                let reassembling_message_clone = reassembling_message.clone();
-                info!("N fragments + current fragment length{}", fragment.total_n_fragments*128 + fragment.length as u64);
-                self.if_all_fragments_received_process(&reassembling_message_clone, &fragment, session_id, routing_header);
-            }
-
-        }else {
-            //Check if it is only 1 fragment
-            if !self.if_all_fragments_received_process(&data_to_add, &fragment, session_id, routing_header)
-            {
-                // New message, create a new entry in the HashMap.
-                let mut reassembling_message = Vec::with_capacity(fragment.total_n_fragments as usize * 128);
+        //Fragment reassembly, sessions are told apart by their source since every client counts its own
+        let src_id = routing_header.hops[0];
+        let fragment_index = fragment.fragment_index;
+        let reassembly = self.get_transport().receive(src_id, session_id, fragment);
 
-                //Copying data
-                reassembling_message.splice(((fragment.fragment_index*128) as usize)..((fragment.fragment_index*128) as usize), data_to_add);
-
-                //Inserting message for future fragments
-                self.get_reassembling_messages()
-                    .insert(session_id, reassembling_message.clone());
-            }
+        // Malformed fragments are not acknowledged, they can never be part of a message
+        if reassembly == Reassembly::Discarded {
+            return;
         }
-    }
 
-    fn if_all_fragments_received_process(&mut self, message: &Vec<u8>, current_fragment: &Fragment, session_id: u64, routing_header: SourceRoutingHeader) -> bool {
-        // Message Processing
+        // Send Ack, also for duplicates: the previous Ack may have been lost
+        let ack = Ack {
+            fragment_index,
+        };
+        self.send_ack(ack, routing_header.get_reversed(), session_id);
 
-        info!("Message length {} n_fragments {} current.fragment length {}, fragment_index: {}", message.len(), current_fragment.total_n_fragments, current_fragment.length, current_fragment.fragment_index );
-        if(message.len() as u64) == ((current_fragment.total_n_fragments-1)*128 + current_fragment.length as u64)
-        {
-            let reassembled_data = message.clone(); // Take ownership of the data
-            self.get_reassembling_messages().remove(&session_id); // Remove from map
-            self.process_reassembled_message(reassembled_data, routing_header.hops[0]);
-            return true;
+        // The fragments still missing are retransmitted by the sender when their ACK is late
+        if let Reassembly::Complete(data) = reassembly {
+            self.process_reassembled_message(data, src_id);
         }
-        false
     }
 
+    //Clients send queries, other servers may also answer the queries of this one
     fn process_reassembled_message(&mut self, data: Vec<u8>, src_id: NodeId) {
-        match String::from_utf8(data.clone()) {
-            Ok(data_string) => match serde_json::from_str(&data_string) {
-                Ok(query) => self.process_query(query, src_id),
-                Err(_) => {
-                    panic!("Damn, not the right struct")
-                }
+        match deserialize::<Query>(&data) {
+            Ok(query) => self.process_query(query, src_id),
+            Err(e) => match deserialize::<Response>(&data) {
+                Ok(response) => self.handle_server_response(response, src_id),
+                Err(_) => error!("Server {}: Query of node {} not processed: {}", self.get_id(), src_id, e),
             },
-            Err(e) => println!("Argh, {:?}", e),
         }
     }
 
@@ -360,150 +517,119 @@ pub trait Server{
         return;
     }
 
-    fn send_fragments(&mut self, session_id: u64, n_fragments: usize, response_in_vec_bytes: &[u8], header: SourceRoutingHeader) {
-
-        //Storing the all the fragments to send
-        self.get_sending_messages().insert(session_id, (response_in_vec_bytes.to_vec(), header.destination().unwrap()));
+    fn send_response(&mut self, destination: NodeId, response: Response) {
+        //Generating session and fragments, the transport keeps them until every fragment is acknowledged
+        let route = self.find_path_to(destination);
+        match self.get_transport().send(&response, route) {
+            Ok(session) => info!("Server {}: Sending response to node {} in session {}", self.get_id(), destination, session.session_id),
+            Err(e) => {
+                error!("Server {}: Error sending response to node {}: {}", self.get_id(), destination, e);
+                return;
+            }
+        }
 
-        info!("Sending fragments n_fragments: {}", n_fragments);
         //Sending
-        for i in 0..n_fragments{
+        self.send_transport_packets();
+    }
 
-            info!("Sending fragment fragment: {}", i);
-            //Preparing data of fragment
-            let mut data:[u8;128] = [0;128];
-            if(i+1)*128>response_in_vec_bytes.len(){
-                data[0..(response_in_vec_bytes.len()-(i*128))].copy_from_slice(&response_in_vec_bytes[i*128..response_in_vec_bytes.len()]);
+    //Queries to other servers, a server known only from the topology gets a route from it
+    fn send_query(&mut self, destination: NodeId, query: Query) {
+        if !self.get_routes().contains(destination) {
+            let topology = self.get_topology().clone();
+            self.get_routes().refresh(destination, &topology);
+        }
 
-            }else{
-                data.copy_from_slice(&response_in_vec_bytes[i*128..(1+i)*128]);
+        //Generating session and fragments, the transport keeps them until every fragment is acknowledged
+        let route = self.find_path_to(destination);
+        match self.get_transport().send(&query, route) {
+            Ok(session) => info!("Server {}: Sending query to node {} in session {}", self.get_id(), destination, session.session_id),
+            Err(e) => {
+                error!("Server {}: Error sending query to node {}: {}", self.get_id(), destination, e);
+                return;
             }
+        }
 
-            //Generating fragment
-            let fragment = Fragment::new(
-                i as u64,
-                n_fragments as u64,
-                data,
-            );
+        //Sending
+        self.send_transport_packets();
+    }
 
-            //Generating packet
-            let packet = Self::create_packet(
-                PacketType::MsgFragment(fragment),
-                header.clone(),
-                session_id,
-            );
+    //Sending the routes to the destination, best first, with their estimated cost to the controller
+    fn send_route_costs(&mut self, destination: NodeId) {
+        let routes = self.get_routes().route_costs(destination);
+        let event = ServerEvent::RouteCosts(self.get_id(), destination, routes);
+        if self.get_to_controller_event().send(event).is_err() {
+            error!("Server {}: Error sending 'RouteCosts' event to controller", self.get_id());
+        }
+    }
 
-            self.send_packet(packet);
+    //RETRANSMISSION
+    fn resend_expired_fragments(&mut self) {
+        let failed = self.get_transport().check_timers();
+        for session in failed {
+            error!("Server {}: Session {} to node {} failed: no ACK after every retry",
+                self.get_id(), session.session_id, session.destination);
+            let event = ServerEvent::SessionFailed(self.get_id(), session.session_id, session.destination);
+            if self.get_to_controller_event().send(event).is_err() {
+                error!("Server {}: Error sending 'SessionFailed' event to controller", self.get_id());
+            }
         }
+
+        //Sending the fragments whose ACK is late
+        self.send_transport_packets();
     }
 
     fn update_topology_and_routes(&mut self, error_node: NodeId) {
         // Remove the node that caused the error from the topology.
-        for (_, neighbors) in self.get_topology().iter_mut() {
-            neighbors.remove(&error_node);
-        }
-        self.get_topology().remove(&error_node);
+        self.get_topology().remove_node(error_node);
         info!("Server {}: Removed node {} from the topology", self.get_id(), error_node);
 
-        // Replace the paths that contain the node that caused the error with an empty vector.
-        for route in self.get_routes().values_mut() {
-            if route.contains(&error_node) {
-                *route = Vec::new();
-            }
-        }
-        info!("Server {}: Routes with node {} cleared", self.get_id(), error_node);
-
-        // Collect server IDs that need new routes.
-        let clients_to_update: Vec<NodeId> = self
-            .get_routes()
-            .iter()
-            .filter(|(_, path)| path.is_empty())
-            .map(|(client_id, _)| *client_id)
-            .collect();
+        // Drop the routes that contain the node that caused the error, the next route of every client takes over.
+        let clients_to_update = self.get_routes().remove_node(error_node);
+        info!("Server {}: Routes with node {} dropped", self.get_id(), error_node);
 
-        // Find new routes for the collected client IDs.
+        // Find new routes for the clients left without one.
+        let topology = self.get_topology().clone();
         for client_id in clients_to_update {
-            if let Some(new_path) = self.bfs_search(client_id) {
-                if let Some(path) = self.get_routes().get_mut(&client_id) {
-                    *path = new_path;
-                }
-            } else {
-                error!("Server {}: No route found to the client {}", self.get_id(), client_id);
+            if !self.get_routes().refresh(client_id, &topology) {
+                error!("Server {}: No route found to the client {}, requesting a new discovery", self.get_id(), client_id);
+                self.get_rediscovery().request();
             }
         }
     }
 
-    fn bfs_search(&mut self, client_id: NodeId) -> Option<Vec<NodeId>> {
-        // Initialize a queue for breadth-first search and a set to track visited nodes.
-        let mut queue: VecDeque<(NodeId, Vec<NodeId>)> = VecDeque::new();
-        let mut visited: HashSet<NodeId> = HashSet::new();
-
-        // Start from the current node with an initial path containing just the current node.
-        queue.push_back((self.get_id(), vec![self.get_id()]));
-
-        // Perform breadth-first search.
-        while let Some((current, path)) = queue.pop_front() {
-            // If the destination node is reached, return the path.
-            if current == client_id {
-                return Some(path);
-            }
-
-            // Mark the current node as visited.
-            visited.insert(current);
-
-            // Explore the neighbors of the current node.
-            if let Some(neighbors) = self.get_topology().get(&current) {
-                for neighbor in neighbors {
-                    // Only visit unvisited neighbors.
-                    if !visited.contains(&neighbor) {
-                        let mut new_path = path.clone();
-                        new_path.push(*neighbor); // Extend the path to include the neighbor.
-                        queue.push_back((*neighbor, new_path)); // Add the neighbor to the queue.
-                    }
-                }
-            }
+    //Flooding again when it is time to, see Rediscovery
+    fn rediscover_if_due(&mut self) {
+        if self.get_rediscovery().is_due() {
+            debug!("Server {}: Rediscovering the network", self.get_id());
+            self.discover();
         }
-        None    // Return None if no path to the server is found.
     }
 
-    fn send_again_fragment(&mut self, session_id: u64, fragment_index: u64){
-
-        //Getting right message and destination id
-        let message_and_destination = self.get_sending_messages_not_mutable().get(&session_id).unwrap();
+    //Rebuilding the alternative routes from the topology, when it is time to
+    fn refresh_routes(&mut self) {
+        let topology = self.get_topology().clone();
+        self.get_routes().refresh_if_due(&topology);
+    }
 
-        //Preparing fields for Fragment
-        let length_response = message_and_destination.0.len();
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
+    fn send_again_fragment(&mut self, session_id: u64, fragment_index: u64){
 
-        let offset = fragment_index*128;
-        let mut data:[u8;128] = [0;128];
-        if (offset+128) > length_response as u64 {
-            data[0..(message_and_destination.0.len() as u64 - offset) as usize].copy_from_slice(&message_and_destination.0[offset as usize..message_and_destination.0.len()]);
-        }else{
-            data.copy_from_slice(&message_and_destination.0[offset as usize..(offset+128) as usize]);
+        //Getting destination id, the session may be already completed or failed
+        let Some(destination) = self.get_transport().destination(session_id) else {
+            return;
+        };
+
+        //Finding route, without one the timer retries later
+        let route = self.find_path_to(destination);
+        if route.is_empty() {
+            warn!("Server {}: No route to node {}, fragment {} of session {} not resent", self.get_id(), destination, fragment_index, session_id);
+            self.get_rediscovery().request();
+        } else {
+            self.get_transport().set_route(session_id, route);
         }
 
-        //Generating fragment
-        let fragment = Fragment::new(
-            fragment_index,
-            n_fragments as u64,
-            data,
-        );
-
-        //Finding route
-        let route = self.find_path_to(message_and_destination.1);
-
-        //Generating packet
-        let packet = Self::create_packet(
-            PacketType::MsgFragment(fragment),
-            Self::create_source_routing(route),
-            session_id,
-        );
-        self.send_packet(packet);
-
+        //Sending
+        self.get_transport().resend(session_id, fragment_index);
+        self.send_transport_packets();
     }
 
     //Common functions
@@ -514,45 +640,19 @@ pub trait Server{
         //Get data
         let response = Response::ServerType(self.get_server_type());
 
-        //Serializing type
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
-
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
-
-        //Generating header
-        let route = self.find_path_to(src_id);
-        let header = Self::create_source_routing(route);
-
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
-
-        //Send fragments
-        info!("Sending fragments");
-        self.send_fragments(session_id, n_fragments, response_in_vec_bytes, header);
+        //Send response
+        self.send_response(src_id, response);
     }
 
+    //Node ID in the high bits, so the flood IDs of two nodes never collide
     fn generate_unique_flood_id(&mut self) -> u64 {
         let counter_flood_id = self.get_flood_id();
-        let id = self.get_id();
-        match format!("{}{}", id, counter_flood_id).parse() {
-            Ok(id) => id,
-            Err(e) => panic!("{}, Not right number", e)
-        }
+        unique_id(self.get_id(), counter_flood_id)
     }
 
+    //The transport owns the session counter, so flood sessions never collide with response sessions
     fn generate_unique_session_id(&mut self) -> u64 {
-        let counter_session_id = self.get_session_id();
-        let id = self.get_id();
-        match format!("{}{}", id, counter_session_id).parse() {
-            Ok(id) => id,
-            Err(e) => panic!("{}, Not right number", e)
-        }
+        self.get_transport().new_session_id()
     }
 }
 
@@ -562,6 +662,27 @@ pub trait CommunicationServer {
     fn add_client(&mut self, client_id: NodeId);
     fn give_list_back(&mut self, client_id: NodeId);
     fn forward_message_to(&mut self, message: Message);
+    fn forward_receipt_to(&mut self, sender: NodeId, message_id: MessageId, recipient: NodeId);
+    fn store_message(&mut self, message: Message);
+    fn deliver_stored_messages(&mut self, client_id: NodeId);
+    fn drop_expired_messages(&mut self);
+    fn create_room(&mut self, client_id: NodeId, room: RoomName);
+    fn join_room(&mut self, client_id: NodeId, room: RoomName);
+    fn leave_room(&mut self, client_id: NodeId, room: RoomName);
+    fn give_rooms_back(&mut self, client_id: NodeId);
+    fn forward_room_message(&mut self, client_id: NodeId, message: RoomMessage);
+    fn send_to_members(&mut self, room: &str, response: Response, skip: Option<NodeId>);
+    fn remove_client(&mut self, client_id: NodeId);
+    fn update_presence(&mut self);
+    fn notify_presence(&mut self, client_id: NodeId, presence: Presence);
+    fn send_directory_to(&mut self, server_id: NodeId);
+    fn share_directory(&mut self);
+    fn update_peer_directory(&mut self, server_id: NodeId, clients: Vec<NodeId>);
+    fn is_communication_server(&self, node_id: NodeId) -> bool;
+    fn peer_of(&self, client_id: NodeId) -> Option<NodeId>;
+    fn has_route_to(&mut self, node_id: NodeId) -> bool;
+    fn receive_relayed_message(&mut self, message: Message);
+    fn receive_relayed_receipt(&mut self, message_id: MessageId, sender: NodeId, recipient: NodeId);
 }
 
 ///Content Server functions
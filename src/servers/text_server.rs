@@ -1,10 +1,14 @@
 use super::server::Server as MainTrait;
+use super::server::SERVER_WINDOW_SIZE;
+use crate::ids::SeenFloods;
+use crate::routing::{Rediscovery, RouteCache, Topology};
+use crate::transport::Transport;
 use super::server::TextServer as CharTrait;
 use crate::general_use::DataScope::{UpdateAll, UpdateSelf};
 use crate::general_use::{DataScope, DisplayDataTextServer, Query, Response, ServerCommand, ServerEvent, ServerType};
 // use crate::ui_traits::Monitoring;
 use crossbeam_channel::{select_biased, Receiver, Sender};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use wg_2024::{
     network::NodeId,
@@ -15,7 +19,6 @@ use wg_2024::{
 };
 
 type FloodId = u64;
-type SessionId = u64;
 #[derive(Debug)]
 pub struct TextServer{
 
@@ -23,15 +26,17 @@ pub struct TextServer{
     pub id: NodeId,
 
     //Fragment-related
-    pub reassembling_messages: HashMap<SessionId, Vec<u8>>,
-    pub sending_messages: HashMap<SessionId, (Vec<u8>, NodeId)>,
+    pub transport: Transport,
 
     //Flood-related
     pub clients: Vec<NodeId>,                                   // Available clients
-    pub topology: HashMap<NodeId, HashSet<NodeId>>,             // Nodes and their neighbours
-    pub routes: HashMap<NodeId, Vec<NodeId>>,                   // Routes to the servers
+    pub topology: Topology,                                     // Nodes and their neighbours
+    pub routes: RouteCache,                                     // Best and alternative routes to the clients
+    pub rediscovery: Rediscovery,                               // When to flood the network again
+    pub seen_floods: SeenFloods,                                // Flood requests already received
+    pub forward_floods: bool,                                   // False: the server is a leaf of the floods
     pub flood_ids: Vec<FloodId>,
-    pub counter: (FloodId, SessionId),
+    pub flood_counter: FloodId,
 
     //Channels
     pub to_controller_event: Sender<ServerEvent>,
@@ -58,14 +63,16 @@ impl TextServer{
         TextServer {
             id,
 
-            reassembling_messages: Default::default(),
-            sending_messages: Default::default(),
+            transport: Transport::new(id).with_window_size(SERVER_WINDOW_SIZE),
 
             clients: Default::default(),                                   // Available clients
             topology: Default::default(),
-            routes: Default::default(),
+            routes: RouteCache::new(id),
+            rediscovery: Rediscovery::new(),
+            seen_floods: SeenFloods::default(),
+            forward_floods: true,
             flood_ids: Default::default(),
-            counter: (0, 0),
+            flood_counter: 0,
 
             to_controller_event,
             from_controller_command,
@@ -152,26 +159,23 @@ impl MainTrait for TextServer{
     fn get_id(&self) -> NodeId{ self.id }
     fn get_server_type(&self) -> ServerType{ ServerType::Text }
 
-    fn get_session_id(&mut self) -> u64{
-        self.counter.1 += 1;
-        self.counter.1
-    }
-
     fn get_flood_id(&mut self) -> u64{
-        self.counter.0 += 1;
-        self.counter.0
+        self.flood_counter += 1;
+        self.flood_counter
     }
 
     fn push_flood_id(&mut self, flood_id: FloodId){ self.flood_ids.push(flood_id); }
     fn get_clients(&mut self) -> &mut Vec<NodeId>{ &mut self.clients }
-    fn get_topology(&mut self) -> &mut HashMap<NodeId, HashSet<NodeId>>{ &mut self.topology }
-    fn get_routes(&mut self) -> &mut HashMap<NodeId, Vec<NodeId>>{ &mut self.routes }
+    fn get_topology(&mut self) -> &mut Topology{ &mut self.topology }
+    fn get_routes(&mut self) -> &mut RouteCache{ &mut self.routes }
+    fn get_rediscovery(&mut self) -> &mut Rediscovery{ &mut self.rediscovery }
+    fn get_seen_floods(&mut self) -> &mut SeenFloods{ &mut self.seen_floods }
+    fn get_forward_floods(&mut self) -> &mut bool{ &mut self.forward_floods }
 
     fn get_from_controller_command(&mut self) -> &mut Receiver<ServerCommand>{ &mut self.from_controller_command }
     fn get_packet_recv(&mut self) -> &mut Receiver<Packet>{ &mut self.packet_recv }
     fn get_packet_send(&mut self) -> &mut HashMap<NodeId, Sender<Packet>>{ &mut self.packet_send }
     fn get_packet_send_not_mutable(&self) -> &HashMap<NodeId, Sender<Packet>>{ &self.packet_send }
-    fn get_reassembling_messages(&mut self) -> &mut HashMap<u64, Vec<u8>>{ &mut self.reassembling_messages }
     fn process_query(&mut self, query: Query, src_id: NodeId) {
         // Check if the topology is empty, save query and start the discovery process if it is.
         if self.topology.is_empty() {
@@ -187,8 +191,8 @@ impl MainTrait for TextServer{
             _ => {}
         }
     }
-    fn get_sending_messages(&mut self) ->  &mut HashMap<u64, (Vec<u8>, u8)>{ &mut self.sending_messages }
-    fn get_sending_messages_not_mutable(&self) -> &HashMap<u64, (Vec<u8>, u8)>{ &self.sending_messages }
+    fn get_transport(&mut self) -> &mut Transport{ &mut self.transport }
+    fn get_to_controller_event(&self) -> &Sender<ServerEvent>{ &self.to_controller_event }
 
     fn get_queries_to_process(&mut self) -> &mut VecDeque<(NodeId, Query)>{ &mut self.queries_to_process }
 }
@@ -202,27 +206,8 @@ impl CharTrait for TextServer{
         //Creating data to send
         let response = Response::ListFiles(list_files.keys().cloned().collect::<Vec<String>>());
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
-
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
-
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
-
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
-
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
-
+        //Send response
+        self.send_response(client_id, response);
     }
 
     fn give_file_back(&mut self, client_id: NodeId, file_key: String) {
@@ -233,26 +218,7 @@ impl CharTrait for TextServer{
         //Creating data to send
         let response = Response::File(file.clone());
 
-        //Serializing message to send
-        let response_as_string = serde_json::to_string(&response).unwrap();
-        let response_in_vec_bytes = response_as_string.as_bytes();
-        let length_response = response_in_vec_bytes.len();
-
-        //Counting fragments
-        let mut n_fragments = length_response / 128+1;
-        if n_fragments == 0 {
-            n_fragments -= 1;
-        }
-
-        //Generating header
-        let route: Vec<NodeId> = self.find_path_to(client_id);
-        let header = Self::create_source_routing(route);
-
-        // Generating ids
-        let session_id = self.generate_unique_session_id();
-
-        //Send fragments
-        self.send_fragments(session_id, n_fragments,response_in_vec_bytes, header);
-
+        //Send response
+        self.send_response(client_id, response);
     }
 }
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::{select, Receiver, Sender};
+use log::{debug, warn};
+use wg_2024::{
+    controller::DroneEvent,
+    network::NodeId,
+    packet::{Packet, PacketType},
+};
+
+use crate::general_use::{ClientCommand, ClientEvent, ServerCommand, ServerEvent, SessionId};
+use crate::simulation_controller::packet_history::{get_destination_from_packet, PacketHistory};
+use crate::simulation_controller::simulation_controller::{KnownServers, RouteCosts};
+use crate::simulation_controller::statistics::NetworkStatistics;
+
+/// ###### Single consumer of every event sent to the controller.
+/// Runs on its own thread, next to the UI: every `DroneEvent`, `ClientEvent` and `ServerEvent`
+/// is received here, so that no event is lost or taken by the wrong handler.
+pub struct EventDispatcher {
+    // Event channels
+    pub(super) drone_event_recv: Receiver<DroneEvent>,
+    pub(super) client_event_recv: Receiver<ClientEvent>,
+    pub(super) server_event_recv: Receiver<ServerEvent>,
+    pub(super) stop_recv: Receiver<()>,
+
+    // Shared with the controller
+    pub(super) packet_history: Arc<Mutex<PacketHistory>>,
+    pub(super) statistics: Arc<Mutex<NetworkStatistics>>,
+    pub(super) client_data: Arc<Mutex<HashMap<NodeId, ClientEvent>>>,
+    pub(super) server_data: Arc<Mutex<HashMap<NodeId, ServerEvent>>>,
+    pub(super) route_costs: Arc<Mutex<RouteCosts>>,
+    pub(super) known_servers: Arc<Mutex<Option<KnownServers>>>,
+
+    // Destinations of the packets sent through the controller
+    pub(super) client_command_senders: HashMap<NodeId, Sender<ClientCommand>>,
+    pub(super) server_command_senders: HashMap<NodeId, Sender<ServerCommand>>,
+}
+
+impl EventDispatcher {
+    /// ###### Handles events until the controller asks to stop or is dropped.
+    /// The ready channels are picked at random, so a busy one cannot starve the others;
+    /// the events still pending when the stop request arrives are handled before returning.
+    pub fn run(&mut self) {
+        loop {
+            select! {
+                recv(self.drone_event_recv) -> event_res => {
+                    let Ok(event) = event_res else { return; };
+                    self.handle_drone_event(event);
+                },
+                recv(self.client_event_recv) -> event_res => {
+                    let Ok(event) = event_res else { return; };
+                    self.handle_client_event(event);
+                },
+                recv(self.server_event_recv) -> event_res => {
+                    let Ok(event) = event_res else { return; };
+                    self.handle_server_event(event);
+                },
+                recv(self.stop_recv) -> _ => {
+                    self.handle_pending_events();
+                    return;
+                },
+            }
+        }
+    }
+
+    fn handle_pending_events(&mut self) {
+        loop {
+            let mut handled = false;
+            if let Ok(event) = self.drone_event_recv.try_recv() {
+                self.handle_drone_event(event);
+                handled = true;
+            }
+            if let Ok(event) = self.client_event_recv.try_recv() {
+                self.handle_client_event(event);
+                handled = true;
+            }
+            if let Ok(event) = self.server_event_recv.try_recv() {
+                self.handle_server_event(event);
+                handled = true;
+            }
+            if !handled {
+                return;
+            }
+        }
+    }
+
+    fn handle_drone_event(&mut self, event: DroneEvent) {
+        match event {
+            DroneEvent::PacketSent(packet) => self.record_packet(&packet, false),
+            DroneEvent::PacketDropped(packet) => self.record_packet(&packet, true),
+            DroneEvent::ControllerShortcut(packet) => self.forward_shortcut(packet),
+        }
+    }
+
+    fn handle_client_event(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::PacketSent(packet) => self.record_packet(&packet, false),
+            ClientEvent::KnownServers(servers) => {
+                *self.known_servers.lock().unwrap() = Some(servers);
+            }
+            ClientEvent::ChatClientData(client_id, _, _) | ClientEvent::WebClientData(client_id, _, _) => {
+                self.client_data.lock().unwrap().insert(client_id, event);
+            }
+            ClientEvent::SessionFailed(client_id, session_id, destination) => {
+                self.record_failure(client_id, session_id, destination);
+            }
+            ClientEvent::RouteCosts(client_id, destination, routes) => {
+                self.route_costs.lock().unwrap().insert((client_id, destination), routes);
+            }
+        }
+    }
+
+    fn handle_server_event(&mut self, event: ServerEvent) {
+        let server_id = match &event {
+            ServerEvent::CommunicationServerData(server_id, _, _)
+            | ServerEvent::TextServerData(server_id, _, _)
+            | ServerEvent::MediaServerData(server_id, _, _) => *server_id,
+            ServerEvent::SessionFailed(server_id, session_id, destination) => {
+                self.record_failure(*server_id, *session_id, *destination);
+                return;
+            }
+            ServerEvent::RouteCosts(server_id, destination, routes) => {
+                self.route_costs.lock().unwrap().insert((*server_id, *destination), routes.clone());
+                return;
+            }
+        };
+        self.server_data.lock().unwrap().insert(server_id, event);
+    }
+
+    fn record_failure(&self, source: NodeId, session_id: SessionId, destination: NodeId) {
+        warn!("Node {}: session {} to node {} failed, retransmissions exhausted", source, session_id, destination);
+        self.statistics.lock().unwrap().record_failure(source, session_id, destination);
+    }
+
+    fn record_packet(&self, packet: &Packet, dropped: bool) {
+        self.packet_history.lock().unwrap().record(packet, dropped);
+        self.statistics.lock().unwrap().record(packet, dropped);
+    }
+
+    /// ###### Delivers a packet that a drone could not forward directly to its destination.
+    /// Only `Ack`, `Nack` and `FloodResponse` can be sent through the controller.
+    fn forward_shortcut(&self, packet: Packet) {
+        if !matches!(packet.pack_type, PacketType::Ack(_) | PacketType::Nack(_) | PacketType::FloodResponse(_)) {
+            eprintln!("Unexpected packet type in ControllerShortcut: {:?}", packet.pack_type);
+            return;
+        }
+
+        let Some(destination) = get_destination_from_packet(&packet) else {
+            eprintln!("Could not determine destination for ControllerShortcut");
+            return;
+        };
+
+        debug!("Forwarding ControllerShortcut to node {}", destination);
+        if let Some(client_sender) = self.client_command_senders.get(&destination) {          //If it's client
+            if let Err(e) = client_sender.send(ClientCommand::ShortcutPacket(packet)) {
+                eprintln!("Error sending to client {}: {:?}", destination, e);
+            }
+        } else if let Some(server_sender) = self.server_command_senders.get(&destination) {   // If it's server
+            if let Err(e) = server_sender.send(ServerCommand::ShortcutPacket(packet)) {
+                eprintln!("Error sending to server {}: {:?}", destination, e);
+            }
+        } else {
+            eprintln!("Invalid destination or unknown node type: {}", destination);
+        }
+    }
+}
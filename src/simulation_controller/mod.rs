@@ -1,2 +1,7 @@
 pub mod simulation_controller;
-pub use simulation_controller::SimulationController;
\ No newline at end of file
+pub mod scenario;
+pub mod event_dispatcher;
+pub mod packet_history;
+pub mod statistics;
+pub use simulation_controller::SimulationController;
+pub use scenario::Scenario;
\ No newline at end of file
@@ -0,0 +1,301 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, LineWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use wg_2024::{
+    network::{NodeId, SourceRoutingHeader},
+    packet::{Packet, PacketType},
+};
+
+use crate::general_use::SessionId;
+
+/// ###### A packet seen by the controller, as reported by a `PacketSent` or `PacketDropped` event.
+#[derive(Debug, Clone)]
+pub struct PacketInfo {
+    pub timestamp_ms: u64,                      // Since the start of the simulation
+    pub source: NodeId,
+    pub destination: NodeId,
+    pub session_id: SessionId,
+    pub routing_header: SourceRoutingHeader,
+    pub packet_type: PacketType,
+    pub dropped: bool,
+}
+
+/// ###### Kind of a packet, without its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PacketKind {
+    MsgFragment,
+    Ack,
+    Nack,
+    FloodRequest,
+    FloodResponse,
+}
+
+/// ###### Criteria to select entries of the history. Unset fields match every entry.
+#[derive(Debug, Clone, Default)]
+pub struct PacketFilter {
+    pub node: Option<NodeId>,                   // Anywhere in the route: source, destination or forwarding node
+    pub session_id: Option<SessionId>,
+    pub kind: Option<PacketKind>,
+    pub dropped: Option<bool>,
+    pub from_ms: Option<u64>,                   // Inclusive
+    pub to_ms: Option<u64>,                     // Inclusive
+}
+
+/// ###### Every packet reported to the controller, in the order the events were received.
+/// The entries are kept in memory for the queries and the exports, and lost when the controller stops
+/// unless they are also logged to a file as they arrive, see `log_to`.
+pub struct PacketHistory {
+    start: Instant,
+    entries: Vec<PacketInfo>,
+    log: Option<LineWriter<File>>,              // JSON Lines file every new entry is appended to
+}
+
+// Flat representation of an entry, shared by the JSON Lines and CSV exports.
+#[derive(Serialize)]
+struct PacketRecord<'a> {
+    timestamp_ms: u64,
+    source: NodeId,
+    destination: NodeId,
+    session_id: SessionId,
+    kind: PacketKind,
+    hop_index: usize,
+    hops: &'a [NodeId],
+    dropped: bool,
+    details: String,
+}
+
+const CSV_HEADER: &str = "timestamp_ms,source,destination,session_id,kind,hop_index,hops,dropped,details";
+
+impl PacketKind {
+    pub fn of(packet_type: &PacketType) -> PacketKind {
+        match packet_type {
+            PacketType::MsgFragment(_) => PacketKind::MsgFragment,
+            PacketType::Ack(_) => PacketKind::Ack,
+            PacketType::Nack(_) => PacketKind::Nack,
+            PacketType::FloodRequest(_) => PacketKind::FloodRequest,
+            PacketType::FloodResponse(_) => PacketKind::FloodResponse,
+        }
+    }
+}
+
+impl Display for PacketKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FromStr for PacketKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "msgfragment" | "fragment" => Ok(PacketKind::MsgFragment),
+            "ack" => Ok(PacketKind::Ack),
+            "nack" => Ok(PacketKind::Nack),
+            "floodrequest" => Ok(PacketKind::FloodRequest),
+            "floodresponse" => Ok(PacketKind::FloodResponse),
+            _ => Err(format!("Unknown packet kind: {}", s)),
+        }
+    }
+}
+
+impl PacketFilter {
+    pub fn matches(&self, entry: &PacketInfo) -> bool {
+        self.node.is_none_or(|node| {
+            entry.source == node || entry.destination == node || entry.routing_header.hops.contains(&node)
+        })
+            && self.session_id.is_none_or(|session_id| entry.session_id == session_id)
+            && self.kind.is_none_or(|kind| PacketKind::of(&entry.packet_type) == kind)
+            && self.dropped.is_none_or(|dropped| entry.dropped == dropped)
+            && self.from_ms.is_none_or(|from_ms| entry.timestamp_ms >= from_ms)
+            && self.to_ms.is_none_or(|to_ms| entry.timestamp_ms <= to_ms)
+    }
+}
+
+impl Default for PacketHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketHistory {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            entries: Vec::new(),
+            log: None,
+        }
+    }
+
+    /// ###### Starts writing the history to a JSON Lines file, the entries already recorded first.
+    /// Every new entry is then appended and flushed as soon as it is recorded, so the file survives a crash.
+    pub fn log_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|err| format!("Unable to create {}: {}", path.display(), err))?;
+        let mut writer = LineWriter::new(file);
+
+        self.export_jsonl(&PacketFilter::default(), &mut writer)?;
+        self.log = Some(writer);
+        Ok(())
+    }
+
+    /// ###### Adds the packet to the history, timestamped with the time elapsed since the start.
+    pub fn record(&mut self, packet: &Packet, dropped: bool) {
+        self.entries.push(PacketInfo {
+            timestamp_ms: self.start.elapsed().as_millis() as u64,
+            source: get_source_from_packet(packet),
+            destination: get_destination_from_packet(packet).unwrap_or(255), // 255 is a valid default
+            session_id: packet.session_id,
+            routing_header: packet.routing_header.clone(),
+            packet_type: packet.pack_type.clone(),
+            dropped,
+        });
+
+        if let (Some(writer), Some(entry)) = (self.log.as_mut(), self.entries.last()) {
+            let result = serde_json::to_string(&PacketRecord::from(entry))
+                .map_err(|err| err.to_string())
+                .and_then(|line| writeln!(writer, "{}", line).map_err(|err| err.to_string()));
+            if let Err(err) = result {
+                warn!("Packet history no longer logged: {}", err);
+                self.log = None;
+            }
+        }
+    }
+
+    /// ###### Returns the entries matching the filter, oldest first.
+    pub fn query(&self, filter: &PacketFilter) -> Vec<&PacketInfo> {
+        self.entries.iter().filter(|entry| filter.matches(entry)).collect()
+    }
+
+    /// ###### Writes the matching entries as one JSON object per line.
+    pub fn export_jsonl<W: Write>(&self, filter: &PacketFilter, mut writer: W) -> Result<(), String> {
+        for entry in self.query(filter) {
+            let line = serde_json::to_string(&PacketRecord::from(entry))
+                .map_err(|err| format!("Failed to serialize packet: {}", err))?;
+            writeln!(writer, "{}", line).map_err(|err| format!("Failed to write packet history: {}", err))?;
+        }
+        writer.flush().map_err(|err| format!("Failed to write packet history: {}", err))
+    }
+
+    /// ###### Writes the matching entries as CSV, with a header line.
+    pub fn export_csv<W: Write>(&self, filter: &PacketFilter, mut writer: W) -> Result<(), String> {
+        let write_error = |err: std::io::Error| format!("Failed to write packet history: {}", err);
+
+        writeln!(writer, "{}", CSV_HEADER).map_err(write_error)?;
+        for entry in self.query(filter) {
+            let record = PacketRecord::from(entry);
+            let hops: Vec<String> = record.hops.iter().map(|hop| hop.to_string()).collect();
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{}",
+                record.timestamp_ms,
+                record.source,
+                record.destination,
+                record.session_id,
+                record.kind,
+                record.hop_index,
+                csv_field(&hops.join(" ")),
+                record.dropped,
+                csv_field(&record.details),
+            ).map_err(write_error)?;
+        }
+        writer.flush().map_err(write_error)
+    }
+
+    /// ###### Exports the matching entries to a file: CSV for a `.csv` path, JSON Lines otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P, filter: &PacketFilter) -> Result<(), String> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|err| format!("Unable to create {}: {}", path.display(), err))?;
+        let writer = BufWriter::new(file);
+
+        if path.extension().is_some_and(|extension| extension == "csv") {
+            self.export_csv(filter, writer)
+        } else {
+            self.export_jsonl(filter, writer)
+        }
+    }
+}
+
+impl<'a> From<&'a PacketInfo> for PacketRecord<'a> {
+    fn from(entry: &'a PacketInfo) -> Self {
+        let details = match &entry.packet_type {
+            PacketType::MsgFragment(fragment) =>
+                format!("fragment {} of {}", fragment.fragment_index, fragment.total_n_fragments),
+            PacketType::Ack(ack) => format!("fragment {}", ack.fragment_index),
+            PacketType::Nack(nack) => format!("fragment {}: {:?}", nack.fragment_index, nack.nack_type),
+            PacketType::FloodRequest(request) =>
+                format!("flood {} from {}, path {:?}", request.flood_id, request.initiator_id, request.path_trace),
+            PacketType::FloodResponse(response) =>
+                format!("flood {}, path {:?}", response.flood_id, response.path_trace),
+        };
+
+        PacketRecord {
+            timestamp_ms: entry.timestamp_ms,
+            source: entry.source,
+            destination: entry.destination,
+            session_id: entry.session_id,
+            kind: PacketKind::of(&entry.packet_type),
+            hop_index: entry.routing_header.hop_index,
+            hops: &entry.routing_header.hops,
+            dropped: entry.dropped,
+            details,
+        }
+    }
+}
+
+// Quotes the field if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', ' ']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn get_source_from_packet(packet: &Packet) -> NodeId {
+    if let Some(first_hop) = packet.routing_header.hops.first() {
+        return *first_hop;
+    }
+
+    match &packet.pack_type {
+        PacketType::FloodRequest(flood_req) => flood_req.initiator_id,
+        PacketType::FloodResponse(flood_res) => flood_res.path_trace.last().map_or(255, |(id, _)| *id),
+        _ => 255,
+    }
+}
+
+pub(super) fn get_destination_from_packet(packet: &Packet) -> Option<NodeId> {
+    packet.routing_header.hops.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logged_history_is_written_as_it_is_recorded() {
+        let path = std::env::temp_dir().join(format!("packet_history_test_{}.jsonl", std::process::id()));
+        let packet = |session_id| Packet::new_ack(SourceRoutingHeader::initialize(vec![1, 2, 3]), session_id, 0);
+
+        let mut history = PacketHistory::new();
+        history.record(&packet(1), false);
+        history.log_to(&path).unwrap();
+        history.record(&packet(2), true);
+
+        // The second entry is on disk before the history is dropped
+        let lines: Vec<String> = std::fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"session_id\":1"));
+        assert!(lines[1].contains("\"session_id\":2") && lines[1].contains("\"dropped\":true"));
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use serde::Deserialize;
+use wg_2024::network::NodeId;
+
+use crate::general_use::{Query, Response};
+use crate::simulation_controller::SimulationController;
+
+const DEFAULT_EXPECT_TIMEOUT_MS: u64 = 2000;
+
+/// ###### Non-interactive list of timed steps executed through the `SimulationController`.
+/// A scenario is written in TOML or JSON (chosen by the file extension), e.g.:
+/// ```toml
+/// topology = "topologies/butterfly.toml"
+/// history = "butterfly_smoke.jsonl"
+///
+/// [[step]]
+/// action = "flood"
+/// client = 1
+///
+/// [[step]]
+/// at_ms = 500
+/// action = "expect"
+/// response = "ServerType"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    pub topology: String,
+    pub history: Option<String>,            // Packet history: JSON Lines written during the run, `.csv` exported after it
+    #[serde(default, rename = "step")]
+    pub steps: Vec<Step>,
+}
+
+/// ###### Action executed once `at_ms` milliseconds have passed since the start of the scenario.
+/// Steps run in the order they are declared: a step scheduled in the past runs immediately.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    #[serde(default)]
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    //Network
+    Flood { client: NodeId },
+    CrashDrone { drone: NodeId },
+    SetPdr { drone: NodeId, pdr: f32 },
+    SetWindow { client: NodeId, size: usize },
+
+    //Common-shared
+    AskType { client: NodeId, server: NodeId },
+
+    //Communication Server
+    Register { client: NodeId, server: NodeId },
+    ListClients { client: NodeId, server: NodeId },
+    SendMessage { client: NodeId, to: NodeId, content: String },
+
+    //Content Server
+    ListFiles { client: NodeId, server: NodeId },
+    AskFile { client: NodeId, server: NodeId, file: String },
+    AskMedia { client: NodeId, server: NodeId, media: String },
+
+    //Waits for a response with the given variant name (e.g. `ListFiles`),
+    //optionally containing the given text. Responses not matching are discarded.
+    Expect {
+        response: String,
+        contains: Option<String>,
+        #[serde(default = "default_expect_timeout")]
+        timeout_ms: u64,
+    },
+}
+
+fn default_expect_timeout() -> u64 {
+    DEFAULT_EXPECT_TIMEOUT_MS
+}
+
+/// ###### Outcome of every step of a scenario, in execution order.
+#[derive(Debug, Clone)]
+pub struct ScenarioReport {
+    pub results: Vec<StepResult>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub index: usize,
+    pub action: Action,
+    pub outcome: Result<(), String>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.outcome.is_ok())
+    }
+
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|result| result.outcome.is_err()).count()
+    }
+}
+
+impl Display for ScenarioReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "[PASS] step {}: {:?}", result.index + 1, result.action)?,
+                Err(err) => writeln!(f, "[FAIL] step {}: {:?}: {}", result.index + 1, result.action, err)?,
+            }
+        }
+        let verdict = if self.passed() { "PASSED" } else { "FAILED" };
+        write!(f, "Scenario {}: {} steps, {} failed", verdict, self.results.len(), self.failures())
+    }
+}
+
+impl Scenario {
+    /// ###### Reads a scenario from a `.json` file, or from a TOML file for any other extension.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scenario, String> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read scenario {}: {}", path.display(), err))?;
+
+        if path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::from_str(&data).map_err(|err| format!("Unable to parse scenario JSON: {}", err))
+        } else {
+            toml::from_str(&data).map_err(|err| format!("Unable to parse scenario TOML: {}", err))
+        }
+    }
+
+    /// ###### Executes every step and collects the outcomes.
+    /// A failing step does not stop the scenario, so that the report lists every failure.
+    pub fn run(&self, controller: &mut SimulationController, response_recv: &Receiver<Response>) -> ScenarioReport {
+        let start = Instant::now();
+        let mut results = Vec::new();
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let scheduled = start + Duration::from_millis(step.at_ms);
+            let now = Instant::now();
+            if scheduled > now {
+                sleep(scheduled - now);
+            }
+
+            let outcome = Self::execute(&step.action, controller, response_recv);
+            if let Err(err) = &outcome {
+                eprintln!("Scenario step {} failed: {}", index + 1, err);
+            }
+            results.push(StepResult {
+                index,
+                action: step.action.clone(),
+                outcome,
+            });
+        }
+
+        ScenarioReport { results }
+    }
+
+    fn execute(action: &Action, controller: &mut SimulationController, response_recv: &Receiver<Response>) -> Result<(), String> {
+        match action {
+            Action::Flood { client } => controller.start_flooding_on_client(*client),
+            Action::CrashDrone { drone } => controller.request_drone_crash(*drone),
+            Action::SetPdr { drone, pdr } => controller.set_packet_drop_rate(*drone, *pdr),
+            Action::SetWindow { client, size } => controller.set_window_size(*client, *size),
+            Action::AskType { client, server } => controller.ask_server_type_with_client_id(*client, *server),
+            Action::Register { client, server } => controller.register_client_on_server(*client, *server),
+            Action::ListClients { client, server } => controller.request_clients_list(*client, *server),
+            Action::SendMessage { client, to, content } => controller.send_message(*client, *to, content.clone()),
+            Action::ListFiles { client, server } => controller.ask_list_files(*client, *server),
+            Action::AskFile { client, server, file } => controller.ask_file_from_server(*client, *server, file.clone()),
+            Action::AskMedia { client, server, media } => {
+                controller.ask_media_from_server(*client, *server, Query::AskMedia(media.clone()))
+            }
+            Action::Expect { response, contains, timeout_ms } => {
+                Self::expect_response(response_recv, response, contains.as_deref(), *timeout_ms)
+            }
+        }
+    }
+
+    fn expect_response(
+        response_recv: &Receiver<Response>,
+        expected: &str,
+        contains: Option<&str>,
+        timeout_ms: u64,
+    ) -> Result<(), String> {
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+        let mut discarded = Vec::new();
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match response_recv.recv_timeout(remaining) {
+                Ok(response) => {
+                    let description = format!("{:?}", response);
+                    if Self::matches(&description, expected, contains) {
+                        return Ok(());
+                    }
+                    discarded.push(description);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!(
+                        "no {} response{} within {} ms (received: {:?})",
+                        expected,
+                        contains.map(|text| format!(" containing \"{}\"", text)).unwrap_or_default(),
+                        timeout_ms,
+                        discarded
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("response channel disconnected".to_string());
+                }
+            }
+        }
+    }
+
+    //The Debug output of a response starts with its variant name, e.g. `ListFiles(["..."])`
+    fn matches(description: &str, expected: &str, contains: Option<&str>) -> bool {
+        let variant = description.split(['(', ' ', '{']).next().unwrap_or_default();
+        variant.eq_ignore_ascii_case(expected) && contains.is_none_or(|text| description.contains(text))
+    }
+}
@@ -1,28 +1,36 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use std::collections::HashMap;
-use std::thread::sleep;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
 use wg_2024::{
     controller::{DroneCommand, DroneEvent},
     drone::Drone,
     network::NodeId,
-    packet::{NodeType, Packet, PacketType}
+    packet::{NodeType, Packet}
 };
-use crate::general_use::{ClientCommand, ClientEvent, ServerCommand, ServerEvent, ServerType, ClientType, ServerId, Query};
+use crate::general_use::{ClientCommand, ClientEvent, ServerCommand, ServerEvent, ServerType, ClientType, ServerId, Query, FileRef, DestinationId, Conversation, ClientId};
+use crate::routing::RouteInfo;
+use crate::simulation_controller::event_dispatcher::EventDispatcher;
+use crate::simulation_controller::packet_history::{PacketFilter, PacketHistory, PacketInfo};
+use crate::simulation_controller::statistics::NetworkStatistics;
+
+/// Last routes reported by every node, by node and destination.
+pub type RouteCosts = HashMap<(NodeId, DestinationId), Vec<RouteInfo>>;
+/// Servers known by a client: ID, type and whether the client is registered to it.
+pub type KnownServers = Vec<(NodeId, ServerType, bool)>;
 
 pub struct SimulationState {
     pub nodes: HashMap<NodeId, NodeType>,
     pub topology: HashMap<NodeId, Vec<NodeId>>,
-    pub packet_history: Vec<PacketInfo>,
-}
-
-
-#[derive(Debug, Clone)]
-pub struct PacketInfo {
-    pub source: NodeId,
-    pub destination: NodeId,
-    pub packet_type: PacketType,
-    pub dropped: bool,
+    // Written by the event dispatcher
+    pub packet_history: Arc<Mutex<PacketHistory>>,
+    pub statistics: Arc<Mutex<NetworkStatistics>>,
+    pub client_data: Arc<Mutex<HashMap<NodeId, ClientEvent>>>,  // Last monitoring data of every client
+    pub server_data: Arc<Mutex<HashMap<NodeId, ServerEvent>>>,  // Last monitoring data of every server
+    pub route_costs: Arc<Mutex<RouteCosts>>,  // Last routes reported by every node
+    pub known_servers: Arc<Mutex<Option<KnownServers>>>,  // Last servers reported by a client, not yet taken
 }
 
 
@@ -30,7 +38,6 @@ pub struct SimulationController {
     pub state: SimulationState,
     pub drone_event_sender: Sender<DroneEvent>,
     pub drone_event_receiver: Receiver<DroneEvent>,
-    pub client_event_sender: Sender<ClientEvent>,
     pub client_event_receiver: Receiver<ClientEvent>,
     pub server_event_sender: Sender<ServerEvent>,
     pub server_event_receiver: Receiver<ServerEvent>,
@@ -38,6 +45,8 @@ pub struct SimulationController {
     pub command_senders_clients: HashMap<NodeId, (Sender<ClientCommand>, ClientType)>,
     pub command_senders_servers: HashMap<NodeId, (Sender<ServerCommand>, ServerType)>,
     pub packet_senders: HashMap<NodeId, Sender<Packet>>,
+    pub join_handles: HashMap<NodeId, JoinHandle<()>>,
+    event_dispatcher: Option<(Sender<()>, JoinHandle<()>)>,
 }
 
 
@@ -45,7 +54,6 @@ impl SimulationController {
     pub fn new(
         drone_event_sender: Sender<DroneEvent>,
         drone_event_receiver: Receiver<DroneEvent>,
-        client_event_sender: Sender<ClientEvent>,
         client_event_receiver: Receiver<ClientEvent>,
         server_event_sender: Sender<ServerEvent>,
         server_event_receiver: Receiver<ServerEvent>,
@@ -54,36 +62,101 @@ impl SimulationController {
             state: SimulationState {
                 nodes: HashMap::new(),
                 topology: HashMap::new(),
-                packet_history: Vec::new(),
+                packet_history: Arc::new(Mutex::new(PacketHistory::new())),
+                statistics: Arc::new(Mutex::new(NetworkStatistics::default())),
+                client_data: Arc::new(Mutex::new(HashMap::new())),
+                server_data: Arc::new(Mutex::new(HashMap::new())),
+                route_costs: Arc::new(Mutex::new(HashMap::new())),
+                known_servers: Arc::new(Mutex::new(None)),
             },
             command_senders_drones: HashMap::new(),
             command_senders_clients: HashMap::new(),
             command_senders_servers: HashMap::new(),
             drone_event_sender,
             drone_event_receiver,
-            client_event_sender,
             client_event_receiver,
             server_event_sender,
             server_event_receiver,
             packet_senders: HashMap::new(),
+            join_handles: HashMap::new(),
+            event_dispatcher: None,
         }
     }
 
-    /// Runs the main simulation loop.
-    /// This function continuously processes events, updates the GUI (not implemented), and sleeps briefly.
-    pub fn run(&mut self) {  // Note: &mut self since we're modifying state directly
-        loop {
-            self.process_packet_sent_events();
-            self.process_packet_dropped_events();
-            self.process_controller_shortcut_events();
-            // GUI updates and user input...                                                            TODO
-            sleep(Duration::from_millis(100));
+    /// Starts the thread that handles every event sent by the nodes.
+    /// Must be called once every node is registered, since packets sent through
+    /// the controller can only be delivered to the clients and servers known at this point.
+    pub fn start_event_dispatcher(&mut self) {
+        let (stop_send, stop_recv) = unbounded();
+
+        let mut dispatcher = EventDispatcher {
+            drone_event_recv: self.drone_event_receiver.clone(),
+            client_event_recv: self.client_event_receiver.clone(),
+            server_event_recv: self.server_event_receiver.clone(),
+            stop_recv,
+            packet_history: Arc::clone(&self.state.packet_history),
+            statistics: Arc::clone(&self.state.statistics),
+            client_data: Arc::clone(&self.state.client_data),
+            server_data: Arc::clone(&self.state.server_data),
+            route_costs: Arc::clone(&self.state.route_costs),
+            known_servers: Arc::clone(&self.state.known_servers),
+            client_command_senders: self.command_senders_clients
+                .iter()
+                .map(|(&id, (sender, _))| (id, sender.clone()))
+                .collect(),
+            server_command_senders: self.command_senders_servers
+                .iter()
+                .map(|(&id, (sender, _))| (id, sender.clone()))
+                .collect(),
+        };
+
+        let handle = thread::spawn(move || dispatcher.run());
+        self.event_dispatcher = Some((stop_send, handle));
+    }
+
+    /// Returns a copy of the recorded packets matching the filter, oldest first.
+    pub fn query_packet_history(&self, filter: &PacketFilter) -> Vec<PacketInfo> {
+        self.state.packet_history.lock().unwrap().query(filter).into_iter().cloned().collect()
+    }
+
+    /// Returns a snapshot of the traffic counters.
+    pub fn get_statistics(&self) -> NetworkStatistics {
+        self.state.statistics.lock().unwrap().clone()
+    }
+
+    /// Asks a client or a server for its routes to the destination.
+    /// The answer arrives asynchronously and is returned by `get_route_costs`.
+    pub fn request_route_costs(&self, node_id: NodeId, destination: DestinationId) -> Result<(), String> {
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&node_id) {
+            client_sender.send(ClientCommand::RequestRoutes(destination))
+                .map_err(|e| format!("Failed to send RequestRoutes command to client {}: {:?}", node_id, e))
+        } else if let Some((server_sender, _)) = self.command_senders_servers.get(&node_id) {
+            server_sender.send(ServerCommand::RequestRoutes(destination))
+                .map_err(|e| format!("Failed to send RequestRoutes command to server {}: {:?}", node_id, e))
+        } else {
+            Err(format!("Node {} is neither a client nor a server", node_id))
         }
     }
 
+    /// Returns the last routes from the node to the destination, best first, with their estimated cost.
+    pub fn get_route_costs(&self, node_id: NodeId, destination: DestinationId) -> Vec<RouteInfo> {
+        self.state.route_costs.lock().unwrap().get(&(node_id, destination)).cloned().unwrap_or_default()
+    }
+
+    /// Writes the packet history to a JSON Lines file and keeps appending every new packet to it.
+    pub fn log_packet_history(&self, path: &str) -> Result<(), String> {
+        self.state.packet_history.lock().unwrap().log_to(path)
+    }
+
+    /// Exports the recorded packets matching the filter: CSV for a `.csv` path, JSON Lines otherwise.
+    pub fn export_packet_history(&self, path: &str, filter: &PacketFilter) -> Result<(), String> {
+        self.state.packet_history.lock().unwrap().save(path, filter)
+    }
+
     /// Registers a drone with the simulation controller.
-    pub fn register_drone(&mut self, node_id: NodeId, command_sender: Sender<DroneCommand>) {
+    pub fn register_drone(&mut self, node_id: NodeId, command_sender: Sender<DroneCommand>, pdr: f32) {
         self.command_senders_drones.insert(node_id, command_sender);
+        self.state.statistics.lock().unwrap().add_drone(node_id, pdr);
     }
 
     pub fn register_server(&mut self, node_id: NodeId, command_sender: Sender<ServerCommand>, server_type: ServerType) {
@@ -95,6 +168,69 @@ impl SimulationController {
         self.command_senders_clients.insert(node_id, (command_sender, client_type));
     }
 
+    /// Keeps the handle of the thread running the node, so that it can be joined on shutdown.
+    pub fn register_join_handle(&mut self, node_id: NodeId, handle: JoinHandle<()>) {
+        self.join_handles.insert(node_id, handle);
+    }
+
+    /// Stops every node and joins its thread.
+    /// Clients and servers receive `Shutdown`; drones are detached from their neighbours and crashed,
+    /// so they stop once every sender of their packet channel is dropped.
+    /// Returns the nodes whose thread panicked or did not stop within the timeout.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<(), String> {
+        for (client_id, (command_sender, _)) in self.command_senders_clients.drain() {
+            if let Err(e) = command_sender.send(ClientCommand::Shutdown) {
+                eprintln!("Failed to send Shutdown command to client {}: {:?}", client_id, e);
+            }
+        }
+        for (server_id, (command_sender, _)) in self.command_senders_servers.drain() {
+            if let Err(e) = command_sender.send(ServerCommand::Shutdown) {
+                eprintln!("Failed to send Shutdown command to server {}: {:?}", server_id, e);
+            }
+        }
+        for (drone_id, command_sender) in self.command_senders_drones.drain() {
+            for neighbor in self.state.topology.get(&drone_id).into_iter().flatten() {
+                if let Err(e) = command_sender.send(DroneCommand::RemoveSender(*neighbor)) {
+                    eprintln!("Failed to send RemoveSender command to drone {}: {:?}", drone_id, e);
+                }
+            }
+            if let Err(e) = command_sender.send(DroneCommand::Crash) {
+                eprintln!("Failed to send Crash command to drone {}: {:?}", drone_id, e);
+            }
+        }
+        self.packet_senders.clear();
+
+        // Wait for the threads to stop, without blocking forever on a stuck node
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && self.join_handles.values().any(|handle| !handle.is_finished()) {
+            sleep(Duration::from_millis(10));
+        }
+
+        let mut failed: Vec<String> = Vec::new();
+        for (node_id, handle) in self.join_handles.drain() {
+            if !handle.is_finished() {
+                failed.push(format!("node {} is still running", node_id));
+            } else if handle.join().is_err() {
+                failed.push(format!("node {} panicked", node_id));
+            }
+        }
+
+        // Stop the dispatcher last, so the events sent while the nodes stop are handled too
+        if let Some((stop_send, handle)) = self.event_dispatcher.take() {
+            let _ = stop_send.send(());
+            if handle.join().is_err() {
+                failed.push("event dispatcher panicked".to_string());
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            failed.sort();
+            Err(format!("Shutdown incomplete: {}", failed.join(", ")))
+        }
+    }
+
     pub fn register_client_on_server(&mut self, client_id: NodeId, server_id: NodeId) -> Result<(), String> {
         if let Some((client_command_sender, _)) = self.command_senders_clients.get(&client_id) {
             if let Err(e) = client_command_sender.send(ClientCommand::RegisterToServer(server_id)) {
@@ -106,6 +242,15 @@ impl SimulationController {
         }
     }
 
+    pub fn unregister_client_from_server(&mut self, client_id: NodeId, server_id: NodeId) -> Result<(), String> {
+        if let Some((client_command_sender, _)) = self.command_senders_clients.get(&client_id) {
+            client_command_sender.send(ClientCommand::UnregisterFromServer(server_id))
+                .map_err(|e| format!("Failed to send UnregisterFromServer command to client {}: {:?}", client_id, e))
+        } else {
+            Err(format!("Client with id {} not found", client_id))
+        }
+    }
+
     pub fn request_clients_list(&self, client_id: NodeId, server_id: NodeId) -> Result<(), String> {
         if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
             if client_sender.send(ClientCommand::AskListClients(server_id)).is_err() {
@@ -140,19 +285,16 @@ impl SimulationController {
         }
     }
 
-    // pub fn ask_file_from_server(&mut self, client_id: NodeId, server_id: NodeId, query: Query) -> Result<(), String> {
-    //     if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
-    //         if let Err(e) = client_sender.send(ClientCommand::RequestText(server_id, match query {
-    //             Query::AskFile(file) => file.parse().unwrap(),
-    //             _ => panic!("Wrong type of Query, supposed to be AskFile"),
-    //         })) {
-    //             return Err(format!("Failed to send command AskFile to client {}: {:?}", client_id, e));
-    //         }
-    //         Ok(())
-    //     }else {
-    //         Err(format!("Client with id {} not found", client_id))
-    //     }
-    // }
+    pub fn ask_file_from_server(&self, client_id: NodeId, server_id: NodeId, file: FileRef) -> Result<(), String> {
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
+            if let Err(e) = client_sender.send(ClientCommand::RequestText(server_id, file)) {
+                return Err(format!("Failed to send command AskFile to client {}: {:?}", client_id, e));
+            }
+            Ok(())
+        }else {
+            Err(format!("Client with id {} not found", client_id))
+        }
+    }
 
     pub fn ask_media_from_server(&mut self, client_id: NodeId, server_id: NodeId, query: Query) -> Result<(), String> {
         if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
@@ -188,110 +330,6 @@ impl SimulationController {
         Ok(drone)
     }
 
-    /// Processes incoming events from drones.
-    /// This function handles `PacketSent`, `PacketDropped`, and `ControllerShortcut` events.
-    fn process_packet_sent_events(&mut self) {
-        if let Ok(event) = self.drone_event_receiver.try_recv() {
-            if let DroneEvent::PacketSent(packet) = event {
-                self.handle_packet_sent(packet);
-            }
-        }
-    }
-
-    fn process_packet_dropped_events(&mut self) {
-        if let Ok(event) = self.drone_event_receiver.try_recv() {
-            if let DroneEvent::PacketDropped(packet) = event {
-                self.handle_packet_dropped(packet);
-            }
-        }
-    }
-
-    fn process_controller_shortcut_events(&mut self) {
-        if let Ok(event) = self.drone_event_receiver.try_recv() {  // Receive event or continue if none is available
-            if let DroneEvent::ControllerShortcut(packet) = event {   // Check event type
-
-                match packet.pack_type {
-                    PacketType::Ack(_) | PacketType::Nack(_) | PacketType::FloodResponse(_) => {
-                        if let Some(destination) = self.get_destination_from_packet(&packet) {  // Try to get destination
-
-                            // Determine where to send the packet based on the destination ID and node type
-                            if self.command_senders_clients.contains_key(&destination) {          //If it's client
-
-                                if let Some((client_sender, _)) = self.command_senders_clients.get(&destination) {
-                                    if let Err(e) = client_sender.send(ClientCommand::ShortcutPacket(packet.clone())) {
-                                        eprintln!("Error sending to client {}: {:?}", destination, e);
-                                    }
-                                } else {
-
-                                    eprintln!("No sender found for client {}", destination);
-                                }
-                            } else if self.command_senders_servers.contains_key(&destination) {   // If it's server
-                                if let Some((server_sender, _)) = self.command_senders_servers.get(&destination) {
-                                    if let Err(e) = server_sender.send(ServerCommand::ShortcutPacket(packet.clone())) {
-                                        eprintln!("Error sending to server {}: {:?}", destination, e);
-                                    }
-                                } else {
-                                    eprintln!("No sender found for server {}", destination);
-                                }
-                            } else {
-                                eprintln!("Invalid destination or unknown node type: {}", destination);
-                            }
-                        } else {
-                            eprintln!("Could not determine destination for ControllerShortcut");
-                        }
-                    }
-                    _ => eprintln!("Unexpected packet type in ControllerShortcut: {:?}", packet.pack_type),
-                }
-            }
-        }
-    }
-
-    fn get_source_from_packet(&self, packet: &Packet) -> NodeId {
-        if let Some(first_hop) = packet.routing_header.hops.first() {
-            return *first_hop;
-        }
-
-        match &packet.pack_type {
-            PacketType::MsgFragment(_) => {
-                if packet.routing_header.hop_index == 1 {
-                    *packet.routing_header.hops.first().unwrap()
-                } else {
-                    packet.routing_header.hops[packet.routing_header.hop_index - 2]
-                }
-            }
-            PacketType::FloodRequest(flood_req) => flood_req.initiator_id,
-            PacketType::FloodResponse(flood_res) => flood_res.path_trace.last().unwrap().0,
-            PacketType::Ack(_) | PacketType::Nack(_) => 255,
-        }
-    }
-
-
-    fn get_destination_from_packet(&self, packet: &Packet) -> Option<NodeId> {
-        packet.routing_header.hops.last().copied()
-    }
-
-    /// Handles `PacketSent` events, adding packet information to the history.
-    fn handle_packet_sent(&mut self, packet: Packet) {
-        let destination = self.get_destination_from_packet(&packet).unwrap_or(255); // Provide default if None
-
-        self.state.packet_history.push(PacketInfo {
-            source: self.get_source_from_packet(&packet),
-            destination,
-            packet_type: packet.pack_type.clone(),
-            dropped: false,
-        });
-    }
-
-    /// Handles `PacketDropped` events, adding packet information to the history.
-    fn handle_packet_dropped(&mut self, packet: Packet) {
-        self.state.packet_history.push(PacketInfo {
-            source: self.get_source_from_packet(&packet),
-            destination: self.get_destination_from_packet(&packet).unwrap_or(255), // 255 is a valid default
-            packet_type: packet.pack_type.clone(),
-            dropped: true,
-        });
-    }
-
     pub fn add_sender(&mut self, node_id: NodeId, node_type: NodeType, connected_node_id: NodeId, sender: Sender<Packet>) {
         match node_type {
             NodeType::Drone => {
@@ -364,13 +402,103 @@ impl SimulationController {
         }
     }
 
-    pub fn set_packet_drop_rate(&mut self, drone_id: NodeId, pdr: f32) {
+    pub fn set_packet_drop_rate(&mut self, drone_id: NodeId, pdr: f32) -> Result<(), String> {
         if let Some(command_sender) = self.command_senders_drones.get(&drone_id) {
             if let Err(e) = command_sender.send(DroneCommand::SetPacketDropRate(pdr)) { // Error handling
-                eprintln!("Failed to send SetPacketDropRate command to drone {}: {:?}", drone_id, e);
+                return Err(format!("Failed to send SetPacketDropRate command to drone {}: {:?}", drone_id, e));
+            }
+            self.state.statistics.lock().unwrap().set_pdr(drone_id, pdr);
+            Ok(())
+        } else {
+            Err(format!("Drone {} not found in controller", drone_id))
+        }
+    }
+
+    /// Sets how many fragments of a message the client sends before waiting for their ACKs.
+    pub fn set_window_size(&self, client_id: NodeId, window_size: usize) -> Result<(), String> {
+        if window_size == 0 {
+            return Err("Window size must be at least 1".to_string());
+        }
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
+            if let Err(e) = client_sender.send(ClientCommand::SetWindowSize(window_size)) {
+                return Err(format!("Failed to send SetWindowSize command to client {}: {:?}", client_id, e));
             }
+            Ok(())
+        } else {
+            Err(format!("Client with ID {} not found", client_id))
+        }
+    }
+
+    /// Sets whether the chat client encrypts its direct messages end to end.
+    pub fn set_encryption(&self, client_id: NodeId, enabled: bool) -> Result<(), String> {
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
+            client_sender.send(ClientCommand::SetEncryption(enabled))
+                .map_err(|e| format!("Failed to send SetEncryption command to client {}: {:?}", client_id, e))
+        } else {
+            Err(format!("Client with ID {} not found", client_id))
+        }
+    }
+
+    /// Makes the chat client accept the changed key of a peer, once the user compared its fingerprint.
+    pub fn trust_new_key(&self, client_id: NodeId, peer: ClientId) -> Result<(), String> {
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
+            client_sender.send(ClientCommand::TrustNewKey(peer))
+                .map_err(|e| format!("Failed to send TrustNewKey command to client {}: {:?}", client_id, e))
         } else {
-            eprintln!("Drone {} not found in controller", drone_id);
+            Err(format!("Client with ID {} not found", client_id))
+        }
+    }
+
+    /// Asks the chat client to write the chat history of a conversation to a text file.
+    pub fn export_conversation(&self, client_id: NodeId, conversation: Conversation, path: PathBuf) -> Result<(), String> {
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&client_id) {
+            client_sender.send(ClientCommand::ExportConversation(conversation, path))
+                .map_err(|e| format!("Failed to send ExportConversation command to client {}: {:?}", client_id, e))
+        } else {
+            Err(format!("Client with ID {} not found", client_id))
+        }
+    }
+
+    /// Sets how often the client or the server floods the network on its own, None to stop it.
+    pub fn set_rediscovery_interval(&self, node_id: NodeId, interval: Option<Duration>) -> Result<(), String> {
+        if let Some((client_sender, _)) = self.command_senders_clients.get(&node_id) {
+            client_sender.send(ClientCommand::SetRediscoveryInterval(interval))
+                .map_err(|e| format!("Failed to send SetRediscoveryInterval command to client {}: {:?}", node_id, e))
+        } else if let Some((server_sender, _)) = self.command_senders_servers.get(&node_id) {
+            server_sender.send(ServerCommand::SetRediscoveryInterval(interval))
+                .map_err(|e| format!("Failed to send SetRediscoveryInterval command to server {}: {:?}", node_id, e))
+        } else {
+            Err(format!("Node {} is neither a client nor a server", node_id))
+        }
+    }
+
+    /// Sets whether the server forwards the flood requests like a drone or answers them as a leaf.
+    pub fn set_flood_forwarding(&self, server_id: NodeId, forward_floods: bool) -> Result<(), String> {
+        if let Some((server_sender, _)) = self.command_senders_servers.get(&server_id) {
+            server_sender.send(ServerCommand::SetFloodForwarding(forward_floods))
+                .map_err(|e| format!("Failed to send SetFloodForwarding command to server {}: {:?}", server_id, e))
+        } else {
+            Err(format!("Server with ID {} not found", server_id))
+        }
+    }
+
+    /// Sets how long the communication server keeps the messages of unreachable clients and how many per client.
+    pub fn set_message_store(&self, server_id: NodeId, ttl: Option<Duration>, capacity: usize) -> Result<(), String> {
+        if let Some((server_sender, _)) = self.command_senders_servers.get(&server_id) {
+            server_sender.send(ServerCommand::SetMessageStore(ttl, capacity))
+                .map_err(|e| format!("Failed to send SetMessageStore command to server {}: {:?}", server_id, e))
+        } else {
+            Err(format!("Server with ID {} not found", server_id))
+        }
+    }
+
+    /// Asks the server for its monitoring data, stored in `server_data` when it arrives.
+    pub fn request_server_data(&self, server_id: NodeId) -> Result<(), String> {
+        if let Some((server_sender, _)) = self.command_senders_servers.get(&server_id) {
+            server_sender.send(ServerCommand::UpdateMonitoringData)
+                .map_err(|e| format!("Failed to send UpdateMonitoringData command to server {}: {:?}", server_id, e))
+        } else {
+            Err(format!("Server with ID {} not found", server_id))
         }
     }
 
@@ -438,28 +566,34 @@ It uses the command_senders map to find the appropriate sender channel.
     pub fn request_known_servers(&mut self, client_id: NodeId) -> Result<Vec<(ServerType, NodeId)>, String> {
         if let Some((client_command_sender, _)) = self.command_senders_clients.get(&client_id) {
 
+            //An answer left from an earlier request must not be taken for this one
+            self.state.known_servers.lock().unwrap().take();
+
             if client_command_sender.send(ClientCommand::GetKnownServers).is_err() {
                 return Err(format!("Client {} disconnected", client_id));
             }
 
-            //wait for KnownServers event
-            let timeout = Duration::from_secs(1);
-
-            //Using recv_client_event_timeout
-            if let Some(event) = self.recv_client_event_timeout(timeout) {
-                match event {
-                    ClientEvent::KnownServers(servers) => {
-                        self.update_known_servers(servers);
-                        let server_options: Vec<(ServerType, NodeId)> = self.command_senders_servers   //Clone servers
-                            .clone()   // Clone the servers vector to avoid the move
-                            .iter()
-                            .map(|(&id, &(_, server_type))| (server_type, id))
-                            .collect();
-                        // Update known servers in the controller and return the list for UI
-                        return Ok(server_options); // Return the processed server list
-                    },
-                    _ => return Err("Unexpected client event".to_string()),
+            //wait for KnownServers event, stored by the event dispatcher
+            let deadline = Instant::now() + Duration::from_secs(1);
+            let received = loop {
+                if let Some(servers) = self.state.known_servers.lock().unwrap().take() {
+                    break Some(servers);
+                }
+                if Instant::now() >= deadline {
+                    break None;
                 }
+                sleep(Duration::from_millis(10));
+            };
+
+            if let Some(servers) = received {
+                self.update_known_servers(servers);
+                let server_options: Vec<(ServerType, NodeId)> = self.command_senders_servers   //Clone servers
+                    .clone()   // Clone the servers vector to avoid the move
+                    .iter()
+                    .map(|(&id, &(_, server_type))| (server_type, id))
+                    .collect();
+                // Update known servers in the controller and return the list for UI
+                return Ok(server_options); // Return the processed server list
             } else {
                 return Err(format!("Timeout waiting for KnownServers from client {}", client_id))
             }
@@ -469,25 +603,7 @@ It uses the command_senders map to find the appropriate sender channel.
     }
 
 
-    fn recv_client_event_timeout(&self, timeout: Duration) -> Option<ClientEvent> {
-        let start = std::time::Instant::now();
-
-        loop {
-            if let Ok(event) = self.client_event_receiver.try_recv() {
-                return Some(event);
-            }
-
-            if start.elapsed() >= timeout {
-                return None; // Timeout
-            }
-            sleep(Duration::from_millis(10));
-        }
-
-    }
-
-
-
-    fn update_known_servers(&mut self, servers: Vec<(NodeId, ServerType, bool)>) {
+    fn update_known_servers(&mut self, servers: KnownServers) {
         for (server_id, server_type, _) in servers { // Iterate over servers and their types
 
             if let Some((sender, _)) = self.command_senders_servers.get(&server_id) { // Check if server already exists in controller
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use wg_2024::{
+    network::NodeId,
+    packet::{Packet, PacketType},
+};
+
+use crate::general_use::SessionId;
+
+/// ###### Traffic of a drone, counted from the `PacketSent` and `PacketDropped` events.
+#[derive(Debug, Clone, Default)]
+pub struct DroneStats {
+    pub configured_pdr: f32,
+    pub packets_forwarded: u64,         // Every kind of packet
+    pub fragments_forwarded: u64,
+    pub fragments_dropped: u64,         // Only fragments can be dropped
+}
+
+/// ###### Transmissions of the fragments of a session, counted on the first link of the route,
+/// and time between the first fragment and the last ACK delivered to the source.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    pub fragment_attempts: HashMap<u64, u64>,  // Fragment index -> times it entered the network
+    pub bytes: u64,                             // Data of the distinct fragments
+    pub first_fragment_at: Option<Instant>,
+    pub last_ack_at: Option<Instant>,
+}
+
+/// ###### Session abandoned by its source after every retransmission of a fragment went unacknowledged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionFailure {
+    pub source: NodeId,
+    pub session_id: SessionId,
+    pub destination: NodeId,
+}
+
+/// ###### Live counters per drone, per link and per session.
+/// A link is identified by the node sending the packet and the one receiving it.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkStatistics {
+    pub drones: HashMap<NodeId, DroneStats>,
+    pub links: HashMap<(NodeId, NodeId), u64>,                  // Fragments carried
+    pub sessions: HashMap<(NodeId, SessionId), SessionStats>,   // Keyed by source node and session ID
+    pub failed_sessions: Vec<SessionFailure>,                   // In the order they were reported
+}
+
+impl DroneStats {
+    /// ###### Fraction of the fragments received by the drone that it dropped.
+    pub fn observed_drop_rate(&self) -> f32 {
+        let received = self.fragments_forwarded + self.fragments_dropped;
+        if received == 0 {
+            0.0
+        } else {
+            self.fragments_dropped as f32 / received as f32
+        }
+    }
+}
+
+impl SessionStats {
+    pub fn transmissions(&self) -> u64 {
+        self.fragment_attempts.values().sum()
+    }
+
+    pub fn retransmissions(&self) -> u64 {
+        self.transmissions() - self.fragment_attempts.len() as u64
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.last_ack_at?.saturating_duration_since(self.first_fragment_at?))
+    }
+
+    /// ###### Bytes acknowledged per second, once at least one ACK reached the source.
+    pub fn throughput(&self) -> Option<f64> {
+        let seconds = self.duration()?.as_secs_f64();
+        (seconds > 0.0).then(|| self.bytes as f64 / seconds)
+    }
+}
+
+impl NetworkStatistics {
+    /// ###### Starts counting the traffic of a drone.
+    pub fn add_drone(&mut self, drone_id: NodeId, pdr: f32) {
+        self.drones.entry(drone_id).or_default().configured_pdr = pdr;
+    }
+
+    pub fn set_pdr(&mut self, drone_id: NodeId, pdr: f32) {
+        if let Some(stats) = self.drones.get_mut(&drone_id) {
+            stats.configured_pdr = pdr;
+        }
+    }
+
+    /// ###### Updates the counters with a packet sent or dropped by a node.
+    /// When the event is raised the hop index already points to the next hop,
+    /// so the node that sent or dropped the packet is the previous one.
+    pub fn record(&mut self, packet: &Packet, dropped: bool) {
+        let hops = &packet.routing_header.hops;
+        let hop_index = packet.routing_header.hop_index;
+
+        // Flood requests have no route: the sender is the last node of the path trace
+        if let PacketType::FloodRequest(flood_request) = &packet.pack_type {
+            if let Some(stats) = flood_request.path_trace.last().and_then(|(id, _)| self.drones.get_mut(id)) {
+                stats.packets_forwarded += 1;
+            }
+            return;
+        }
+
+        if hop_index == 0 || hop_index >= hops.len() {
+            return;
+        }
+        let sender = hops[hop_index - 1];
+        let receiver = hops[hop_index];
+        let is_fragment = matches!(packet.pack_type, PacketType::MsgFragment(_));
+
+        if let Some(stats) = self.drones.get_mut(&sender) {
+            match (dropped, is_fragment) {
+                (true, _) => stats.fragments_dropped += 1,
+                (false, true) => {
+                    stats.packets_forwarded += 1;
+                    stats.fragments_forwarded += 1;
+                }
+                (false, false) => stats.packets_forwarded += 1,
+            }
+        }
+
+        // An ACK delivered to the source of the session
+        if let PacketType::Ack(_) = &packet.pack_type {
+            if !dropped && hop_index == hops.len() - 1 {
+                if let Some(stats) = self.sessions.get_mut(&(receiver, packet.session_id)) {
+                    stats.last_ack_at = Some(Instant::now());
+                }
+            }
+            return;
+        }
+
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            return;
+        };
+
+        if !dropped {
+            *self.links.entry((sender, receiver)).or_default() += 1;
+        }
+
+        // Every time a fragment reaches the first drone, it was sent again by the source
+        if hop_index == 2 {
+            let stats = self.sessions.entry((hops[0], packet.session_id)).or_default();
+            stats.first_fragment_at.get_or_insert_with(Instant::now);
+
+            let attempts = stats.fragment_attempts.entry(fragment.fragment_index).or_default();
+            if *attempts == 0 {
+                stats.bytes += fragment.length as u64;
+            }
+            *attempts += 1;
+        }
+    }
+
+    pub fn record_failure(&mut self, source: NodeId, session_id: SessionId, destination: NodeId) {
+        self.failed_sessions.push(SessionFailure { source, session_id, destination });
+    }
+
+    /// ###### Drones sorted by observed drop rate, then by traffic: the likely bottlenecks first.
+    pub fn busiest_drones(&self) -> Vec<(NodeId, &DroneStats)> {
+        let mut drones: Vec<(NodeId, &DroneStats)> = self.drones.iter().map(|(id, stats)| (*id, stats)).collect();
+        drones.sort_by(|(id_a, a), (id_b, b)| {
+            b.observed_drop_rate().total_cmp(&a.observed_drop_rate())
+                .then(b.packets_forwarded.cmp(&a.packets_forwarded))
+                .then(id_a.cmp(id_b))
+        });
+        drones
+    }
+}
+
+impl Display for NetworkStatistics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Drones (forwarded / fragments forwarded / fragments dropped / observed drop rate / PDR):")?;
+        for (id, stats) in self.busiest_drones() {
+            writeln!(
+                f,
+                "  drone {:>3}: {:>6} / {:>6} / {:>6} / {:>5.1}% / {:>5.1}%",
+                id,
+                stats.packets_forwarded,
+                stats.fragments_forwarded,
+                stats.fragments_dropped,
+                stats.observed_drop_rate() * 100.0,
+                stats.configured_pdr * 100.0
+            )?;
+        }
+
+        writeln!(f, "Links (fragments carried):")?;
+        let mut links: Vec<_> = self.links.iter().collect();
+        links.sort_by(|(link_a, count_a), (link_b, count_b)| count_b.cmp(count_a).then(link_a.cmp(link_b)));
+        for ((from, to), count) in links {
+            writeln!(f, "  {:>3} -> {:<3}: {}", from, to, count)?;
+        }
+
+        write!(f, "Sessions with retransmissions:")?;
+        let mut sessions: Vec<_> = self.sessions.iter().filter(|(_, stats)| stats.retransmissions() > 0).collect();
+        sessions.sort_by_key(|(key, _)| **key);
+        for ((source, session_id), stats) in sessions {
+            write!(
+                f,
+                "\n  node {:>3}, session {}: {} retransmissions over {} fragments",
+                source,
+                session_id,
+                stats.retransmissions(),
+                stats.fragment_attempts.len()
+            )?;
+        }
+
+        write!(f, "\nSessions throughput (until the last ACK received):")?;
+        let mut acknowledged: Vec<_> = self.sessions.iter().filter(|(_, stats)| stats.duration().is_some()).collect();
+        acknowledged.sort_by_key(|(key, _)| **key);
+        for ((source, session_id), stats) in acknowledged {
+            let duration = stats.duration().unwrap_or_default();
+            write!(
+                f,
+                "\n  node {:>3}, session {}: {} bytes in {} ms{}",
+                source,
+                session_id,
+                stats.bytes,
+                duration.as_millis(),
+                stats.throughput().map(|throughput| format!(" ({:.1} kB/s)", throughput / 1000.0)).unwrap_or_default()
+            )?;
+        }
+
+        write!(f, "\nFailed sessions:")?;
+        for failure in &self.failed_sessions {
+            write!(
+                f,
+                "\n  node {:>3}, session {}: no ACK from the route to node {}",
+                failure.source,
+                failure.session_id,
+                failure.destination
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use wg_2024::{
+    config::Config,
+    network::NodeId,
+    packet::NodeType,
+};
+
+use crate::network_config::ConfigError;
+
+const MAX_CLIENT_DRONE_LINKS: usize = 2;
+const MIN_SERVER_DRONE_LINKS: usize = 2;
+
+/// ###### Checks the topology before any node is started.
+/// Returns every problem found, so that a bad config is rejected as a whole
+/// instead of failing one node at a time once the network is running.
+pub fn validate(config: &Config) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+
+    // Collect the nodes, keeping the first declaration of every ID.
+    let mut nodes: HashMap<NodeId, NodeType> = HashMap::new();
+    let mut neighbours: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+    let mut reported_duplicates: HashSet<NodeId> = HashSet::new();
+
+    let declarations = config.drone.iter().map(|drone| (drone.id, NodeType::Drone, &drone.connected_node_ids))
+        .chain(config.client.iter().map(|client| (client.id, NodeType::Client, &client.connected_drone_ids)))
+        .chain(config.server.iter().map(|server| (server.id, NodeType::Server, &server.connected_drone_ids)));
+
+    for (id, node_type, connected_ids) in declarations {
+        if nodes.contains_key(&id) {
+            if reported_duplicates.insert(id) {
+                errors.push(ConfigError::DuplicateId(id));
+            }
+            continue;
+        }
+        nodes.insert(id, node_type);
+        neighbours.insert(id, connected_ids.clone());
+    }
+
+    // Drop rates must be probabilities.
+    for drone in &config.drone {
+        if !(0.0..=1.0).contains(&drone.pdr) {
+            errors.push(ConfigError::InvalidPdr(drone.id, drone.pdr));
+        }
+    }
+
+    // Edges must point to existing nodes, be declared on both ends,
+    // and clients and servers can only be connected to drones.
+    let mut sorted_ids: Vec<NodeId> = nodes.keys().copied().collect();
+    sorted_ids.sort();
+
+    for id in &sorted_ids {
+        for neighbour in &neighbours[id] {
+            let Some(neighbour_type) = nodes.get(neighbour) else {
+                errors.push(ConfigError::UnknownNeighbour(*id, *neighbour));
+                continue;
+            };
+
+            if !neighbours[neighbour].contains(id) {
+                errors.push(ConfigError::AsymmetricEdge(*id, *neighbour));
+            }
+
+            if nodes[id] != NodeType::Drone && *neighbour_type != NodeType::Drone {
+                errors.push(ConfigError::NotConnectedToDrone(*id, *neighbour));
+            }
+        }
+
+        let drone_links = neighbours[id]
+            .iter()
+            .filter(|neighbour| nodes.get(neighbour) == Some(&NodeType::Drone))
+            .collect::<HashSet<_>>()
+            .len();
+
+        match nodes[id] {
+            NodeType::Client if drone_links > MAX_CLIENT_DRONE_LINKS => {
+                errors.push(ConfigError::TooManyDroneLinks(*id, drone_links));
+            }
+            NodeType::Server if drone_links < MIN_SERVER_DRONE_LINKS => {
+                errors.push(ConfigError::TooFewDroneLinks(*id, drone_links));
+            }
+            _ => {}
+        }
+    }
+
+    // Every node must be reachable through the drones.
+    let unreachable = find_unreachable_nodes(&sorted_ids, &nodes, &neighbours);
+    if !unreachable.is_empty() {
+        errors.push(ConfigError::Disconnected(unreachable));
+    }
+
+    errors
+}
+
+/// ###### Returns the nodes that cannot be reached from the first drone.
+/// Only drones forward packets, so clients and servers are reached but never explored.
+fn find_unreachable_nodes(
+    sorted_ids: &[NodeId],
+    nodes: &HashMap<NodeId, NodeType>,
+    neighbours: &HashMap<NodeId, Vec<NodeId>>,
+) -> Vec<NodeId> {
+    let Some(start) = sorted_ids.iter().find(|id| nodes[id] == NodeType::Drone) else {
+        // Without drones nothing can be delivered, unless there is at most one node.
+        return if sorted_ids.len() > 1 { sorted_ids.to_vec() } else { Vec::new() };
+    };
+
+    let mut visited: HashSet<NodeId> = HashSet::from([*start]);
+    let mut queue: VecDeque<NodeId> = VecDeque::from([*start]);
+
+    while let Some(current) = queue.pop_front() {
+        if nodes[&current] != NodeType::Drone {
+            continue;
+        }
+
+        // Edges are followed in both directions: asymmetric ones are reported separately.
+        let linked = neighbours[&current]
+            .iter()
+            .copied()
+            .chain(neighbours.iter().filter(|(_, ids)| ids.contains(&current)).map(|(id, _)| *id));
+
+        for neighbour in linked {
+            if nodes.contains_key(&neighbour) && visited.insert(neighbour) {
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    sorted_ids.iter().copied().filter(|id| !visited.contains(id)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use wg_2024::config::{Client, Drone, Server};
+
+    use super::*;
+
+    fn drone(id: NodeId, connected_node_ids: &[NodeId]) -> Drone {
+        Drone { id, connected_node_ids: connected_node_ids.to_vec(), pdr: 0.1 }
+    }
+
+    fn client(id: NodeId, connected_drone_ids: &[NodeId]) -> Client {
+        Client { id, connected_drone_ids: connected_drone_ids.to_vec() }
+    }
+
+    fn server(id: NodeId, connected_drone_ids: &[NodeId]) -> Server {
+        Server { id, connected_drone_ids: connected_drone_ids.to_vec() }
+    }
+
+    // Drones 1 and 2 linked together, client 10 on drone 1, server 20 on both drones
+    fn valid_config() -> Config {
+        Config {
+            drone: vec![drone(1, &[2, 10, 20]), drone(2, &[1, 20])],
+            client: vec![client(10, &[1])],
+            server: vec![server(20, &[1, 2])],
+        }
+    }
+
+    #[test]
+    fn valid_config_has_no_errors() {
+        assert_eq!(validate(&valid_config()), vec![]);
+    }
+
+    #[test]
+    fn duplicate_id_is_reported() {
+        let mut config = valid_config();
+        config.drone.push(drone(1, &[2]));
+        assert_eq!(validate(&config), vec![ConfigError::DuplicateId(1)]);
+    }
+
+    #[test]
+    fn asymmetric_edge_is_reported() {
+        let mut config = valid_config();
+        config.drone[0] = drone(1, &[10, 20]);
+        assert_eq!(validate(&config), vec![ConfigError::AsymmetricEdge(2, 1)]);
+    }
+
+    #[test]
+    fn client_linked_to_server_is_reported() {
+        let mut config = valid_config();
+        config.client[0] = client(10, &[1, 20]);
+        config.server[0] = server(20, &[1, 2, 10]);
+        assert_eq!(validate(&config), vec![
+            ConfigError::NotConnectedToDrone(10, 20),
+            ConfigError::NotConnectedToDrone(20, 10),
+        ]);
+    }
+
+    #[test]
+    fn client_with_three_drones_is_reported() {
+        let mut config = valid_config();
+        config.drone = vec![drone(1, &[2, 3, 10, 20]), drone(2, &[1, 10, 20]), drone(3, &[1, 10])];
+        config.client[0] = client(10, &[1, 2, 3]);
+        assert_eq!(validate(&config), vec![ConfigError::TooManyDroneLinks(10, 3)]);
+    }
+
+    #[test]
+    fn server_with_one_drone_is_reported() {
+        let mut config = valid_config();
+        config.drone[1] = drone(2, &[1]);
+        config.server[0] = server(20, &[1]);
+        assert_eq!(validate(&config), vec![ConfigError::TooFewDroneLinks(20, 1)]);
+    }
+
+    #[test]
+    fn pdr_outside_probabilities_is_reported() {
+        let mut config = valid_config();
+        config.drone[0].pdr = 1.5;
+        assert_eq!(validate(&config), vec![ConfigError::InvalidPdr(1, 1.5)]);
+    }
+
+    #[test]
+    fn disconnected_graph_is_reported() {
+        let mut config = valid_config();
+        config.drone.push(drone(3, &[]));
+        assert_eq!(validate(&config), vec![ConfigError::Disconnected(vec![3])]);
+    }
+}
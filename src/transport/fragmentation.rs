@@ -0,0 +1,32 @@
+use serde::{de::DeserializeOwned, Serialize};
+use wg_2024::packet::{Fragment, FRAGMENT_DSIZE};
+
+/// ###### Serializes a message into the bytes carried by the fragments.
+pub fn serialize<T: Serialize>(message: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(message).map_err(|err| format!("Failed to serialize message: {}", err))
+}
+
+/// ###### Deserializes a message from the bytes of its reassembled fragments.
+pub fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T, String> {
+    serde_json::from_slice(data).map_err(|err| format!("Failed to deserialize message: {}", err))
+}
+
+/// ###### Splits the data into fragments of at most `FRAGMENT_DSIZE` bytes.
+/// Every fragment carries the exact length of its data: the last one is not padded.
+pub fn fragment(data: &[u8]) -> Vec<Fragment> {
+    let total_n_fragments = data.len().div_ceil(FRAGMENT_DSIZE) as u64;
+
+    data.chunks(FRAGMENT_DSIZE)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment_data = [0u8; FRAGMENT_DSIZE];
+            fragment_data[..chunk.len()].copy_from_slice(chunk);
+            Fragment {
+                fragment_index: index as u64,
+                total_n_fragments,
+                length: chunk.len() as u8,
+                data: fragment_data,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use wg_2024::{network::NodeId, packet::Fragment};
+
+use crate::general_use::{FragmentIndex, SessionId};
+
+/// ###### Delivered sessions remembered by a node, older ones are forgotten first.
+const MAX_DELIVERED_SESSIONS: usize = 1024;
+
+/// ###### Fragments of a message being received, by index.
+#[derive(Debug, Clone)]
+pub(super) struct IncomingMessage {
+    pub(super) total_n_fragments: u64,
+    fragments: BTreeMap<FragmentIndex, Vec<u8>>,
+    last_received: Instant,             // To drop the messages abandoned by their sender
+}
+
+impl IncomingMessage {
+    pub(super) fn new(total_n_fragments: u64) -> Self {
+        Self {
+            total_n_fragments,
+            fragments: BTreeMap::new(),
+            last_received: Instant::now(),
+        }
+    }
+
+    /// ###### Stores the data of the fragment. Returns false if the fragment was already received.
+    pub(super) fn insert(&mut self, fragment: Fragment) -> bool {
+        if self.fragments.contains_key(&fragment.fragment_index) {
+            return false;
+        }
+        self.fragments.insert(fragment.fragment_index, fragment.data[..fragment.length as usize].to_vec());
+        self.last_received = Instant::now();
+        true
+    }
+
+    pub(super) fn is_complete(&self) -> bool {
+        self.fragments.len() as u64 == self.total_n_fragments
+    }
+
+    pub(super) fn is_abandoned(&self, timeout: Duration) -> bool {
+        self.last_received.elapsed() >= timeout
+    }
+
+    /// ###### Joins the data in index order.
+    pub(super) fn into_data(self) -> Vec<u8> {
+        self.fragments.into_values().flatten().collect()
+    }
+}
+
+/// ###### Sessions whose message was delivered, by source, to ignore the late duplicates.
+/// Older ones are forgotten first.
+#[derive(Debug, Clone, Default)]
+pub(super) struct DeliveredSessions {
+    delivered: HashSet<(NodeId, SessionId)>,
+    order: VecDeque<(NodeId, SessionId)>,
+}
+
+impl DeliveredSessions {
+    pub(super) fn insert(&mut self, source: NodeId, session_id: SessionId) {
+        if !self.delivered.insert((source, session_id)) {
+            return;
+        }
+        self.order.push_back((source, session_id));
+        if self.order.len() > MAX_DELIVERED_SESSIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.delivered.remove(&oldest);
+            }
+        }
+    }
+
+    pub(super) fn contains(&self, source: NodeId, session_id: SessionId) -> bool {
+        self.delivered.contains(&(source, session_id))
+    }
+}
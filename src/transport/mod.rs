@@ -0,0 +1,383 @@
+mod fragmentation;
+mod incoming;
+mod outgoing;
+pub mod retransmission;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::Serialize;
+use wg_2024::{
+    network::NodeId,
+    packet::{Fragment, Packet, FRAGMENT_DSIZE},
+};
+
+use crate::general_use::{FragmentIndex, SessionId};
+use crate::ids::unique_id;
+use incoming::{DeliveredSessions, IncomingMessage};
+use outgoing::OutgoingMessage;
+use retransmission::{Expired, RetransmissionTimers};
+
+pub use fragmentation::deserialize;
+pub use retransmission::RETRANSMISSION_TICK;
+
+/// ###### Fragments of a message that can wait for their ACK, unless configured otherwise.
+pub const DEFAULT_WINDOW_SIZE: usize = 1;
+
+/// ###### Largest message accepted, in fragments (128 MiB): the fragments claiming more are discarded.
+pub const MAX_FRAGMENTS: u64 = 1 << 20;
+
+/// ###### Time without fragments after which a message being received is dropped.
+/// Longer than the retries of the sender, see `RetransmissionConfig`.
+const INCOMING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// ###### Session of a message sent through the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionHandle {
+    pub session_id: SessionId,
+    pub destination: NodeId,
+}
+
+/// ###### Outcome of a received fragment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reassembly {
+    Discarded,                      // Malformed or too large, it can never be part of a message: not to be acknowledged
+    Ignored,                        // Duplicate or part of an already delivered message
+    Pending,                        // Stored, other fragments are still expected, the sender retransmits the lost ones
+    Complete(Vec<u8>),              // Data of the whole message, in index order
+}
+
+/// ###### Transport layer of a node.
+/// Owns the sessions of the messages sent and received: it splits messages into fragments,
+/// sends them through a sliding window, keeps track of ACKs and NACKs, retransmits the fragments
+/// whose ACK is late and reassembles the received fragments.
+///
+/// The transport never touches the channels: the packets it produces are queued, ready to leave
+/// the node (hop index 0), and the node sends them with `take_packets`.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    node_id: NodeId,
+    session_counter: u64,
+    window_size: usize,
+    outgoing: HashMap<SessionId, OutgoingMessage>,
+    incoming: HashMap<(NodeId, SessionId), IncomingMessage>,    // Sessions are told apart by their source
+    delivered: DeliveredSessions,
+    timers: RetransmissionTimers,
+    to_send: VecDeque<Packet>,
+}
+
+impl Transport {
+    pub fn new(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            session_counter: 0,
+            window_size: DEFAULT_WINDOW_SIZE,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            delivered: DeliveredSessions::default(),
+            timers: RetransmissionTimers::default(),
+            to_send: VecDeque::new(),
+        }
+    }
+
+    /// ###### Sets the window of the messages sent from now on. A size of 1 is stop-and-wait.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        self.window_size = window_size.max(1);
+    }
+
+    pub fn with_window_size(mut self, window_size: usize) -> Self {
+        self.set_window_size(window_size);
+        self
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// ###### Generates a new session ID, unique across the nodes, see `unique_id`.
+    pub fn new_session_id(&mut self) -> SessionId {
+        self.session_counter += 1;
+        unique_id(self.node_id, self.session_counter)
+    }
+
+    /// ###### Serializes the message and queues the fragments of its first window.
+    /// The route starts at this node and ends at the destination.
+    pub fn send<T: Serialize>(&mut self, message: &T, route: Vec<NodeId>) -> Result<SessionHandle, String> {
+        if route.len() < 2 {
+            return Err(format!("Invalid route {:?}", route));
+        }
+
+        let data = fragmentation::serialize(message)?;
+        let session_id = self.new_session_id();
+        let outgoing = OutgoingMessage::new(fragmentation::fragment(&data), route, self.window_size);
+        let handle = SessionHandle {
+            session_id,
+            destination: outgoing.destination(),
+        };
+
+        self.outgoing.insert(session_id, outgoing);
+        self.queue_window(session_id);
+        Ok(handle)
+    }
+
+    /// ###### Records the ACK of a fragment and queues the fragments entering the window.
+    /// Returns the session once every fragment of its message is acknowledged.
+    pub fn handle_ack(&mut self, session_id: SessionId, fragment_index: FragmentIndex) -> Option<SessionHandle> {
+        // Duplicate and late ACKs do not move the message forward
+        if !self.timers.acknowledged(session_id, fragment_index) {
+            debug!("Node {}: Ignoring ACK for fragment {} of session {}", self.node_id, fragment_index, session_id);
+            return None;
+        }
+
+        let outgoing = self.outgoing.get_mut(&session_id)?;
+        outgoing.acknowledge(fragment_index);
+
+        if outgoing.is_complete() {
+            let outgoing = self.outgoing.remove(&session_id)?;
+            return Some(SessionHandle {
+                session_id,
+                destination: outgoing.destination(),
+            });
+        }
+
+        self.queue_window(session_id);
+        None
+    }
+
+    /// ###### Queues the fragment again, e.g. after a NACK.
+    /// Returns false if the session is already completed or failed.
+    pub fn resend(&mut self, session_id: SessionId, fragment_index: FragmentIndex) -> bool {
+        let Some(packet) = self.outgoing
+            .get(&session_id)
+            .and_then(|outgoing| outgoing.packet(session_id, fragment_index)) else {
+            return false;
+        };
+
+        self.timers.sent(session_id, fragment_index);
+        self.to_send.push_back(packet);
+        true
+    }
+
+    pub fn destination(&self, session_id: SessionId) -> Option<NodeId> {
+        self.outgoing.get(&session_id).map(|outgoing| outgoing.destination())
+    }
+
+    pub fn route(&self, session_id: SessionId) -> Option<&[NodeId]> {
+        self.outgoing.get(&session_id).map(|outgoing| outgoing.route())
+    }
+
+    /// ###### Replaces the route of a session, used by the fragments sent from now on.
+    pub fn set_route(&mut self, session_id: SessionId, route: Vec<NodeId>) -> bool {
+        match self.outgoing.get_mut(&session_id) {
+            Some(outgoing) => {
+                outgoing.set_route(route);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// ###### Abandons a session: its fragments are no longer sent nor retransmitted.
+    pub fn cancel(&mut self, session_id: SessionId) {
+        self.outgoing.remove(&session_id);
+        self.timers.cancel_session(session_id);
+    }
+
+    /// ###### Queues the fragments whose ACK is late, and drops the messages abandoned by their sender.
+    /// Returns the sessions that failed: a fragment ran out of retries and the message was dropped.
+    pub fn check_timers(&mut self) -> Vec<SessionHandle> {
+        let node_id = self.node_id;
+        self.incoming.retain(|(source, session_id), incoming| {
+            let abandoned = incoming.is_abandoned(INCOMING_TIMEOUT);
+            if abandoned {
+                warn!("Node {}: Dropping session {} of node {}: no fragment for {:?}", node_id, session_id, source, INCOMING_TIMEOUT);
+            }
+            !abandoned
+        });
+
+        let mut failed = Vec::new();
+        for expired in self.timers.expired() {
+            match expired {
+                Expired::Retransmit(session_id, fragment_index) => {
+                    debug!("Node {}: No ACK for fragment {} of session {}, resending", self.node_id, fragment_index, session_id);
+                    self.resend(session_id, fragment_index);
+                }
+                Expired::Failed(session_id, fragment_index) => {
+                    let Some(outgoing) = self.outgoing.remove(&session_id) else {
+                        continue;
+                    };
+                    warn!("Node {}: Session {} failed: no ACK for fragment {} after every retry", self.node_id, session_id, fragment_index);
+                    failed.push(SessionHandle {
+                        session_id,
+                        destination: outgoing.destination(),
+                    });
+                }
+            }
+        }
+        failed
+    }
+
+    /// ###### Takes the packets waiting to leave the node, in order.
+    pub fn take_packets(&mut self) -> Vec<Packet> {
+        self.to_send.drain(..).collect()
+    }
+
+    /// ###### Stores a received fragment.
+    /// Returns the data of the message once every fragment is received; the ACK is up to the node.
+    pub fn receive(&mut self, source: NodeId, session_id: SessionId, fragment: Fragment) -> Reassembly {
+        // Discarding malformed fragments, they can never be part of a message
+        if fragment.fragment_index >= fragment.total_n_fragments
+            || fragment.total_n_fragments > MAX_FRAGMENTS
+            || fragment.length as usize > FRAGMENT_DSIZE
+        {
+            warn!("Node {}: Discarding malformed fragment {} of session {} ({} fragments, length {})",
+                self.node_id, fragment.fragment_index, session_id, fragment.total_n_fragments, fragment.length);
+            return Reassembly::Discarded;
+        }
+
+        let fragment_index = fragment.fragment_index;
+        let total_n_fragments = fragment.total_n_fragments;
+        if self.delivered.contains(source, session_id) {
+            debug!("Node {}: Ignoring fragment {} of the already delivered session {}", self.node_id, fragment_index, session_id);
+            return Reassembly::Ignored;
+        }
+
+        let incoming = self.incoming
+            .entry((source, session_id))
+            .or_insert_with(|| IncomingMessage::new(total_n_fragments));
+        if incoming.total_n_fragments != total_n_fragments {
+            warn!("Node {}: Session {} of node {} restarted with {} fragments instead of {}",
+                self.node_id, session_id, source, total_n_fragments, incoming.total_n_fragments);
+            *incoming = IncomingMessage::new(total_n_fragments);
+        }
+        if !incoming.insert(fragment) {
+            debug!("Node {}: Ignoring duplicate fragment {} of session {}", self.node_id, fragment_index, session_id);
+            return Reassembly::Ignored;
+        }
+
+        if incoming.is_complete() {
+            self.delivered.insert(source, session_id);
+            let data = self.incoming.remove(&(source, session_id)).map(IncomingMessage::into_data).unwrap_or_default();
+            Reassembly::Complete(data)
+        } else {
+            Reassembly::Pending
+        }
+    }
+
+    // Queues the fragments that entered the window of the session and starts their timers.
+    fn queue_window(&mut self, session_id: SessionId) {
+        let Some(outgoing) = self.outgoing.get_mut(&session_id) else {
+            return;
+        };
+        for fragment_index in outgoing.next_in_window() {
+            if let Some(packet) = outgoing.packet(session_id, fragment_index) {
+                self.timers.sent(session_id, fragment_index);
+                self.to_send.push_back(packet);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wg_2024::packet::PacketType;
+
+    // Data spread over `n` fragments once serialized
+    fn message(n: usize) -> Vec<u8> {
+        vec![1u8; n * FRAGMENT_DSIZE / 2 - 8]
+    }
+
+    fn fragments(transport: &mut Transport) -> Vec<Fragment> {
+        transport.take_packets()
+            .into_iter()
+            .filter_map(|packet| match packet.pack_type {
+                PacketType::MsgFragment(fragment) => Some(fragment),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn indexes(fragments: &[Fragment]) -> Vec<FragmentIndex> {
+        fragments.iter().map(|fragment| fragment.fragment_index).collect()
+    }
+
+    #[test]
+    fn window_slides_with_the_acks() {
+        let mut transport = Transport::new(1).with_window_size(2);
+        let session = transport.send(&message(4), vec![1, 2, 9]).unwrap();
+        assert_eq!(indexes(&fragments(&mut transport)), vec![0, 1]);
+
+        // The window starts at the first fragment not acknowledged
+        assert_eq!(transport.handle_ack(session.session_id, 1), None);
+        assert!(fragments(&mut transport).is_empty());
+        assert_eq!(transport.handle_ack(session.session_id, 0), None);
+        assert_eq!(indexes(&fragments(&mut transport)), vec![2, 3]);
+
+        transport.handle_ack(session.session_id, 2);
+        assert_eq!(transport.handle_ack(session.session_id, 3), Some(session));
+        assert_eq!(transport.destination(session.session_id), None);
+    }
+
+    #[test]
+    fn duplicate_and_late_acks_do_not_move_the_window() {
+        let mut transport = Transport::new(1);
+        let session = transport.send(&message(3), vec![1, 2, 9]).unwrap();
+        fragments(&mut transport);
+
+        transport.handle_ack(session.session_id, 0);
+        assert_eq!(indexes(&fragments(&mut transport)), vec![1]);
+        assert_eq!(transport.handle_ack(session.session_id, 0), None);
+        assert_eq!(transport.handle_ack(session.session_id, 2), None);
+        assert!(fragments(&mut transport).is_empty());
+
+        transport.handle_ack(session.session_id, 1);
+        transport.handle_ack(session.session_id, 2);
+        assert_eq!(transport.handle_ack(session.session_id, 2), None);
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order() {
+        let data = message(3);
+        let mut sender = Transport::new(1).with_window_size(3);
+        let session = sender.send(&data, vec![1, 2, 9]).unwrap();
+        let mut sent = fragments(&mut sender);
+        assert_eq!(sent.len(), 3);
+
+        let mut receiver = Transport::new(9);
+        let last = sent.pop().unwrap();
+        let middle = sent.pop().unwrap();
+        let first = sent.pop().unwrap();
+        assert_eq!(receiver.receive(1, session.session_id, last.clone()), Reassembly::Pending);
+        assert_eq!(receiver.receive(1, session.session_id, first), Reassembly::Pending);
+        assert_eq!(receiver.receive(1, session.session_id, last.clone()), Reassembly::Ignored);
+
+        let Reassembly::Complete(received) = receiver.receive(1, session.session_id, middle) else {
+            panic!("message not reassembled");
+        };
+        assert_eq!(deserialize::<Vec<u8>>(&received).unwrap(), data);
+
+        // Late duplicate of a delivered message
+        assert_eq!(receiver.receive(1, session.session_id, last.clone()), Reassembly::Ignored);
+        // Same session ID from another source: another message
+        assert_eq!(receiver.receive(2, session.session_id, last), Reassembly::Pending);
+    }
+
+    #[test]
+    fn malformed_and_oversized_fragments_are_discarded() {
+        let fragment = |fragment_index, total_n_fragments, length| Fragment {
+            fragment_index,
+            total_n_fragments,
+            length,
+            data: [0; FRAGMENT_DSIZE],
+        };
+
+        let mut receiver = Transport::new(9);
+        assert_eq!(receiver.receive(1, 1, fragment(2, 2, 10)), Reassembly::Discarded);
+        assert_eq!(receiver.receive(1, 1, fragment(0, u64::MAX, 10)), Reassembly::Discarded);
+        assert_eq!(receiver.receive(1, 1, fragment(0, MAX_FRAGMENTS + 1, 10)), Reassembly::Discarded);
+        assert_eq!(receiver.receive(1, 1, fragment(0, MAX_FRAGMENTS, 10)), Reassembly::Pending);
+        assert_eq!(receiver.incoming.len(), 1);
+    }
+}
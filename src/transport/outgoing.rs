@@ -0,0 +1,84 @@
+use wg_2024::{
+    network::{NodeId, SourceRoutingHeader},
+    packet::{Fragment, Packet, PacketType},
+};
+
+use crate::general_use::{FragmentIndex, SessionId};
+
+/// ###### Message being sent, split into fragments.
+/// Fragments are sent through a sliding window: up to `window_size` fragments can wait for their ACK,
+/// and the window slides forward as soon as its first fragment is acknowledged.
+#[derive(Debug, Clone)]
+pub(super) struct OutgoingMessage {
+    fragments: Vec<Fragment>,
+    route: Vec<NodeId>,
+    window_size: usize,
+    acknowledged: Vec<bool>,    // ACK received, by fragment index
+    next_to_send: usize,        // First fragment never sent
+}
+
+impl OutgoingMessage {
+    pub(super) fn new(fragments: Vec<Fragment>, route: Vec<NodeId>, window_size: usize) -> Self {
+        Self {
+            acknowledged: vec![false; fragments.len()],
+            fragments,
+            route,
+            window_size: window_size.max(1),
+            next_to_send: 0,
+        }
+    }
+
+    pub(super) fn destination(&self) -> NodeId {
+        *self.route.last().unwrap()
+    }
+
+    pub(super) fn route(&self) -> &[NodeId] {
+        &self.route
+    }
+
+    pub(super) fn set_route(&mut self, route: Vec<NodeId>) {
+        self.route = route;
+    }
+
+    /// ###### Builds the packet of a fragment, ready to leave the source (hop index 0).
+    pub(super) fn packet(&self, session_id: SessionId, fragment_index: FragmentIndex) -> Option<Packet> {
+        let fragment = self.fragments.get(fragment_index as usize)?.clone();
+        Some(Packet {
+            routing_header: SourceRoutingHeader {
+                hop_index: 0,
+                hops: self.route.clone(),
+            },
+            session_id,
+            pack_type: PacketType::MsgFragment(fragment),
+        })
+    }
+
+    /// ###### Returns the fragments that entered the window and were never sent, marking them as sent.
+    pub(super) fn next_in_window(&mut self) -> Vec<FragmentIndex> {
+        let window_end = self.window_base().saturating_add(self.window_size).min(self.fragments.len());
+        let indexes = (self.next_to_send..window_end).map(|index| index as FragmentIndex).collect();
+        self.next_to_send = self.next_to_send.max(window_end);
+        indexes
+    }
+
+    /// ###### Marks the fragment as acknowledged.
+    /// Returns false if the fragment does not exist or was already acknowledged.
+    pub(super) fn acknowledge(&mut self, fragment_index: FragmentIndex) -> bool {
+        match self.acknowledged.get_mut(fragment_index as usize) {
+            Some(acked) if !*acked => {
+                *acked = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn is_complete(&self) -> bool {
+        self.acknowledged.iter().all(|acked| *acked)
+    }
+
+    // Index of the first fragment not acknowledged yet.
+    fn window_base(&self) -> usize {
+        self.acknowledged.iter().position(|acked| !*acked).unwrap_or(self.fragments.len())
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::general_use::{FragmentIndex, SessionId};
+
+/// ###### How often the nodes check their retransmission timers.
+pub const RETRANSMISSION_TICK: Duration = Duration::from_millis(100);
+
+const DEFAULT_INITIAL_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// ###### Timeout of the first transmission and number of retransmissions allowed per fragment.
+/// The timeout doubles after every retransmission.
+#[derive(Debug, Clone, Copy)]
+pub struct RetransmissionConfig {
+    pub initial_timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl Default for RetransmissionConfig {
+    fn default() -> Self {
+        Self {
+            initial_timeout: DEFAULT_INITIAL_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+/// ###### What to do with a fragment whose timer expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expired {
+    Retransmit(SessionId, FragmentIndex),
+    Failed(SessionId, FragmentIndex),     // Retries exhausted: every timer of the session is removed
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Timer {
+    deadline: Instant,
+    retries: u32,
+}
+
+/// ###### Timers of the fragments sent and not acknowledged yet.
+#[derive(Debug, Clone, Default)]
+pub struct RetransmissionTimers {
+    config: RetransmissionConfig,
+    timers: HashMap<(SessionId, FragmentIndex), Timer>,
+}
+
+impl RetransmissionTimers {
+    /// ###### Starts the timer of a fragment that was just sent.
+    /// If the fragment was already waiting for its ACK (e.g. it was resent after a NACK),
+    /// the timer restarts with its current timeout and keeps its retry count.
+    pub fn sent(&mut self, session_id: SessionId, fragment_index: FragmentIndex) {
+        let now = Instant::now();
+        let initial_timeout = self.config.initial_timeout;
+        self.timers
+            .entry((session_id, fragment_index))
+            .and_modify(|timer| timer.deadline = now + Self::timeout(initial_timeout, timer.retries))
+            .or_insert(Timer {
+                deadline: now + initial_timeout,
+                retries: 0,
+            });
+    }
+
+    /// ###### Stops the timer of an acknowledged fragment.
+    /// Returns false if the fragment was not waiting for an ACK (duplicate or late ACK).
+    pub fn acknowledged(&mut self, session_id: SessionId, fragment_index: FragmentIndex) -> bool {
+        self.timers.remove(&(session_id, fragment_index)).is_some()
+    }
+
+    pub fn cancel_session(&mut self, session_id: SessionId) {
+        self.timers.retain(|(session, _), _| *session != session_id);
+    }
+
+    /// ###### Collects the expired timers.
+    /// A fragment with retries left is rescheduled with a doubled timeout and must be retransmitted,
+    /// otherwise its session fails and is reported once.
+    pub fn expired(&mut self) -> Vec<Expired> {
+        self.expired_at(Instant::now())
+    }
+
+    fn expired_at(&mut self, now: Instant) -> Vec<Expired> {
+        let mut expired: Vec<(SessionId, FragmentIndex)> = self.timers
+            .iter()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        expired.sort();
+
+        let mut actions = Vec::new();
+        let mut failed_sessions = HashSet::new();
+        for (session_id, fragment_index) in expired {
+            if failed_sessions.contains(&session_id) {
+                continue;
+            }
+
+            let timer = self.timers.get_mut(&(session_id, fragment_index)).unwrap();
+            if timer.retries >= self.config.max_retries {
+                failed_sessions.insert(session_id);
+                actions.push(Expired::Failed(session_id, fragment_index));
+            } else {
+                timer.retries += 1;
+                timer.deadline = now + Self::timeout(self.config.initial_timeout, timer.retries);
+                actions.push(Expired::Retransmit(session_id, fragment_index));
+            }
+        }
+
+        for session_id in failed_sessions {
+            self.cancel_session(session_id);
+        }
+        actions
+    }
+
+    fn timeout(initial_timeout: Duration, retries: u32) -> Duration {
+        initial_timeout.saturating_mul(1 << retries.min(16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timers(max_retries: u32) -> RetransmissionTimers {
+        RetransmissionTimers {
+            config: RetransmissionConfig { initial_timeout: Duration::from_millis(500), max_retries },
+            timers: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn timeout_doubles_after_every_retransmission() {
+        let mut timers = timers(5);
+        let start = Instant::now();
+        timers.sent(1, 0);
+
+        assert!(timers.expired_at(start + Duration::from_millis(400)).is_empty());
+
+        // Sent at `start` or a little later: late at 500 ms, then after 1 s more
+        let first = start + Duration::from_millis(600);
+        assert_eq!(timers.expired_at(first), vec![Expired::Retransmit(1, 0)]);
+        assert!(timers.expired_at(first + Duration::from_millis(900)).is_empty());
+        assert_eq!(timers.expired_at(first + Duration::from_millis(1000)), vec![Expired::Retransmit(1, 0)]);
+    }
+
+    #[test]
+    fn session_fails_once_when_retries_run_out() {
+        let mut timers = timers(1);
+        let start = Instant::now();
+        timers.sent(1, 0);
+        timers.sent(1, 1);
+        timers.sent(2, 0);
+
+        let first = start + Duration::from_secs(1);
+        assert_eq!(timers.expired_at(first).len(), 3);
+
+        timers.acknowledged(2, 0);
+        assert_eq!(timers.expired_at(first + Duration::from_secs(2)), vec![Expired::Failed(1, 0)]);
+        assert!(timers.timers.is_empty());
+    }
+
+    #[test]
+    fn duplicate_and_late_acks_are_reported() {
+        let mut timers = timers(5);
+        timers.sent(1, 0);
+
+        assert!(timers.acknowledged(1, 0));
+        assert!(!timers.acknowledged(1, 0));
+        assert!(!timers.acknowledged(7, 0));
+    }
+}